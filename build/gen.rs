@@ -1,5 +1,5 @@
 extern crate codegen;
-use crate::protocol::{BlackmagicCameraProtocol, Parameter};
+use crate::protocol::{BlackmagicCameraProtocol, CecProtocol, Parameter};
 use codegen::{Block, Scope};
 use convert_case::{Case, Casing};
 
@@ -12,6 +12,15 @@ static DERIVE_TRAITS: [&str; 6] = [
     "Deserialize",
 ];
 
+static STATE_DERIVE_TRAITS: [&str; 6] = [
+    "Debug",
+    "Default",
+    "PartialEq",
+    "Clone",
+    "Serialize",
+    "Deserialize",
+];
+
 pub struct Datagen {
     protocol: BlackmagicCameraProtocol,
 }
@@ -31,6 +40,49 @@ impl Datagen {
         scope.to_string()
     }
 
+    pub fn gen_keys(&mut self) -> String {
+        let mut scope = Scope::new();
+
+        scope.import("crate::command", "*");
+        scope.import("crate::key", "{CommandKey, Key}");
+
+        Datagen::keys(&mut scope, &self.protocol);
+
+        scope.to_string()
+    }
+
+    pub fn gen_state(&mut self) -> String {
+        let mut scope = Scope::new();
+
+        scope.import("crate::command", "*");
+        scope.import("serde", "{Serialize, Deserialize}");
+
+        Datagen::state(&mut scope, &self.protocol);
+
+        scope.to_string()
+    }
+
+    pub fn gen_parameters(&mut self) -> String {
+        let mut scope = Scope::new();
+
+        scope.import("crate::info", "{CategoryInfo, ParameterInfo}");
+
+        Datagen::parameter_info(&mut scope, &self.protocol);
+
+        scope.to_string()
+    }
+
+    pub fn gen_cec(&mut self, cec: &CecProtocol) -> String {
+        let mut scope = Scope::new();
+
+        scope.import("crate::defs", "CecParameter");
+        scope.import("crate::key", "CommandKey");
+
+        Datagen::cec_parameters(&mut scope, &self.protocol, cec);
+
+        scope.to_string()
+    }
+
     fn imports(s: &mut Scope) {
         s.import(
             "crate::rawcommand",
@@ -172,6 +224,53 @@ impl Datagen {
             am.push_block(mb);
         }
 
+        //defaults func
+        {
+            let am = im
+                .new_fn("defaults")
+                .ret("Vec<Command>")
+                .vis("pub")
+                .doc(
+                    "One command per valued parameter, holding a value inside its documented range",
+                )
+                .line("vec![");
+
+            for category in protocol.groups.iter() {
+                for param in category.parameters.iter() {
+                    if let Some(value) = defaultvalue(param) {
+                        am.line(format!(
+                            "    Command::{}({}::{}({})),",
+                            &category.normalized_name.to_case(Case::UpperCamel),
+                            &category.normalized_name.to_case(Case::UpperCamel),
+                            &param.normalized_parameter.to_case(Case::UpperCamel),
+                            value
+                        ));
+                    }
+                }
+            }
+            am.line("]");
+        }
+
+        //max_length func
+        {
+            let am = im
+                .new_fn("max_length")
+                .arg_ref_self()
+                .ret("Option<usize>")
+                .vis("pub");
+            am.line("match self");
+
+            let mut mb = Block::new("");
+
+            for category in protocol.groups.iter() {
+                mb.line(format!(
+                    "Command::{}(v) => v.max_length(),",
+                    &category.normalized_name.to_case(Case::UpperCamel),
+                ));
+            }
+            am.push_block(mb);
+        }
+
         //Display trait
         {
             let tr = s
@@ -364,6 +463,37 @@ impl Datagen {
                 }
                 am.push_block(mb);
             }
+
+            //max_length
+            {
+                let am = im.new_fn("max_length").arg_ref_self().ret("Option<usize>");
+                am.line("match self");
+
+                let mut mb = Block::new("");
+
+                for param in category.parameters.iter() {
+                    let max = match maxlength(param) {
+                        Some(v) => format!("Some({})", v),
+                        None => "None".to_string(),
+                    };
+                    if lookuptype(param) != "Void" {
+                        mb.line(format!(
+                            "{}::{}(_) => {},",
+                            &category.normalized_name.to_case(Case::UpperCamel),
+                            &param.normalized_parameter.to_case(Case::UpperCamel),
+                            max
+                        ));
+                    } else {
+                        mb.line(format!(
+                            "{}::{} => {},",
+                            &category.normalized_name.to_case(Case::UpperCamel),
+                            &param.normalized_parameter.to_case(Case::UpperCamel),
+                            max
+                        ));
+                    }
+                }
+                am.push_block(mb);
+            }
         }
 
         //Implementations of fmt::Display for subenum
@@ -379,7 +509,7 @@ impl Datagen {
             let mut mb = Block::new("");
 
             for param in category.parameters.iter() {
-                if lookuptype(&param) != "Void" {
+                if lookuptype(param) != "Void" {
                     mb.line(format!(
                         "{}::{}(v) => write!(f, \"{{}}\", v.data_as_string()),",
                         &category.normalized_name.to_case(Case::UpperCamel),
@@ -396,6 +526,303 @@ impl Datagen {
             im.push_block(mb);
         }
     }
+
+    fn keys(s: &mut Scope, protocol: &BlackmagicCameraProtocol) {
+        //Typed key constants, one module per category
+        for category in protocol.groups.iter() {
+            let m = s.new_module(&category.normalized_name).vis("pub");
+            m.import("super", "*");
+
+            m.scope()
+                .raw(format!("pub const CATEGORY: u8 = {};", &category.id));
+
+            let all: Vec<String> = category
+                .parameters
+                .iter()
+                .map(|p| format!("CommandKey::new({}, {})", &category.id, &p.id))
+                .collect();
+            m.scope().raw(format!(
+                "pub const KEYS: &[CommandKey] = &[{}];",
+                all.join(", ")
+            ));
+
+            for param in category.parameters.iter() {
+                let t = lookuptype(param);
+                let (t, pattern, value) = match t {
+                    "Void" => ("()", "".to_string(), "()"),
+                    "String" | "Vec<i8>" | "Vec<i16>" | "Vec<i32>" | "Vec<i64>" | "Vec<f32>" => {
+                        (t, "(v)".to_string(), "v.clone()")
+                    }
+                    _ => (t, "(v)".to_string(), "*v"),
+                };
+
+                m.scope().raw(format!(
+                    "pub const {}: Key<{}> = Key::new({}, {}, |c| match c {{ Command::{}({}::{}{}) => Some({}), _ => None }});",
+                    &param.normalized_parameter.to_uppercase(),
+                    t,
+                    &category.id,
+                    &param.id,
+                    &category.normalized_name.to_case(Case::UpperCamel),
+                    &category.normalized_name.to_case(Case::UpperCamel),
+                    &param.normalized_parameter.to_case(Case::UpperCamel),
+                    pattern,
+                    value
+                ));
+            }
+        }
+
+        //category_keys func
+        {
+            let f = s
+                .new_fn("category_keys")
+                .arg("category", "u8")
+                .ret("&'static [CommandKey]")
+                .vis("pub");
+
+            f.line("match category");
+
+            let mut mb = Block::new("");
+            for category in protocol.groups.iter() {
+                mb.line(format!(
+                    "{} => {}::KEYS,",
+                    &category.id, &category.normalized_name
+                ));
+            }
+            mb.line("_ => &[],");
+            f.push_block(mb);
+        }
+
+        //from_normalized_name func
+        {
+            let f = s
+                .new_fn("from_normalized_name")
+                .arg("name", "&str")
+                .ret("Option<CommandKey>")
+                .vis("pub");
+
+            f.line("match name");
+
+            let mut mb = Block::new("");
+            for category in protocol.groups.iter() {
+                for param in category.parameters.iter() {
+                    mb.line(format!(
+                        "\"{}_{}\" => Some(CommandKey::new({}, {})),",
+                        &category.normalized_name,
+                        &param.normalized_parameter,
+                        &category.id,
+                        &param.id
+                    ));
+                }
+            }
+            mb.line("_ => None,");
+            f.push_block(mb);
+        }
+    }
+
+    fn parameter_info(s: &mut Scope, protocol: &BlackmagicCameraProtocol) {
+        let categories: Vec<String> = protocol
+            .groups
+            .iter()
+            .map(|c| {
+                format!(
+                    "CategoryInfo {{ id: {}, name: {:?}, normalized_name: {:?} }}",
+                    c.id, c.name, c.normalized_name
+                )
+            })
+            .collect();
+        s.raw(format!(
+            "pub const CATEGORIES: &[CategoryInfo] = &[\n    {},\n];",
+            categories.join(",\n    ")
+        ));
+
+        let mut parameters = Vec::new();
+        for category in protocol.groups.iter() {
+            for param in category.parameters.iter() {
+                let index: Vec<String> = param
+                    .index
+                    .iter()
+                    .map(|i| format!("{:?}", collapse(i)))
+                    .collect();
+
+                parameters.push(format!(
+                    "ParameterInfo {{ category: {}, parameter: {}, name: {:?}, normalized_name: {:?}, data_type: {:?}, index: &[{}], interpretation: {}, minimum: {:?}, maximum: {:?} }}",
+                    category.id,
+                    param.id,
+                    param.parameter,
+                    param.normalized_parameter,
+                    param.type_field,
+                    index.join(", "),
+                    match &param.interpretation {
+                        Some(v) => format!("Some({:?})", collapse(v)),
+                        None => "None".to_string(),
+                    },
+                    param.minimum,
+                    param.maximum
+                ));
+            }
+        }
+        s.raw(format!(
+            "pub const PARAMETERS: &[ParameterInfo] = &[\n    {},\n];",
+            parameters.join(",\n    ")
+        ));
+    }
+
+    // Resolves every opcode against PROTOCOL.json so a typo fails the build
+    fn cec_parameters(s: &mut Scope, protocol: &BlackmagicCameraProtocol, cec: &CecProtocol) {
+        let mut parameters = Vec::new();
+        for op in cec.opcodes.iter() {
+            let (category, param) = protocol
+                .groups
+                .iter()
+                .filter(|c| c.normalized_name == op.group)
+                .flat_map(|c| c.parameters.iter().map(move |p| (c, p)))
+                .find(|(_, p)| p.normalized_parameter == op.parameter)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "CEC opcode {:#04x}: no parameter {}.{} in PROTOCOL.json",
+                        op.opcode, op.group, op.parameter
+                    )
+                });
+
+            let elements = param.index.len().max(1);
+            if op.index + op.count > elements {
+                panic!(
+                    "CEC opcode {:#04x}: elements {}..{} out of range, {} has {}",
+                    op.opcode,
+                    op.index,
+                    op.index + op.count,
+                    op.parameter,
+                    elements
+                );
+            }
+
+            let data_type = typename_id(op.type_field.as_deref().unwrap_or(&param.type_field));
+            if data_type == 5 {
+                panic!("CEC opcode {:#04x}: strings are not supported", op.opcode);
+            }
+
+            let scale = op.scale.unwrap_or(1.0);
+            if scale == 0.0 {
+                panic!("CEC opcode {:#04x}: scale can not be zero", op.opcode);
+            }
+
+            parameters.push(format!(
+                "CecParameter {{ opcode: {:#04x}, name: {:?}, key: CommandKey::new({}, {}), index: {}, count: {}, data_type: {}, scale: {:?}, offset: {:?} }}",
+                op.opcode,
+                op.name,
+                category.id,
+                param.id,
+                op.index,
+                op.count,
+                data_type,
+                scale,
+                op.offset.unwrap_or(0.0)
+            ));
+        }
+        s.raw(format!(
+            "pub const CEC_PARAMETERS: &[CecParameter] = &[\n    {},\n];",
+            parameters.join(",\n    ")
+        ));
+    }
+
+    fn state(s: &mut Scope, protocol: &BlackmagicCameraProtocol) {
+        //Top level state, one field per category
+        {
+            let data = s.new_struct("CameraState").vis("pub");
+            for t in STATE_DERIVE_TRAITS {
+                data.derive(t);
+            }
+
+            for category in protocol.groups.iter() {
+                data.new_field(
+                    &category.normalized_name,
+                    format!(
+                        "{}State",
+                        &category.normalized_name.to_case(Case::UpperCamel)
+                    ),
+                )
+                .vis("pub");
+            }
+
+            let am = s
+                .new_impl("CameraState")
+                .new_fn("apply")
+                .arg_mut_self()
+                .arg("cmd", "&Command")
+                .vis("pub")
+                .line("match cmd");
+
+            let mut mb = Block::new("");
+            for category in protocol.groups.iter() {
+                mb.line(format!(
+                    "Command::{}(v) => self.{}.apply(v),",
+                    &category.normalized_name.to_case(Case::UpperCamel),
+                    &category.normalized_name
+                ));
+            }
+            am.push_block(mb);
+        }
+
+        //Category states, triggers carry no value so they get no field
+        for category in protocol.groups.iter() {
+            let name = format!(
+                "{}State",
+                &category.normalized_name.to_case(Case::UpperCamel)
+            );
+
+            let data = s.new_struct(&name).vis("pub");
+            for t in STATE_DERIVE_TRAITS {
+                data.derive(t);
+            }
+
+            for param in category.parameters.iter() {
+                let t = lookuptype(param);
+                if t != "Void" {
+                    data.new_field(&param.normalized_parameter, format!("Option<{}>", t))
+                        .vis("pub");
+                }
+            }
+
+            let am = s
+                .new_impl(&name)
+                .new_fn("apply")
+                .arg_mut_self()
+                .arg(
+                    "cmd",
+                    format!("&{}", &category.normalized_name.to_case(Case::UpperCamel)),
+                )
+                .vis("pub")
+                .line("match cmd");
+
+            let mut mb = Block::new("");
+            for param in category.parameters.iter() {
+                let t = lookuptype(param);
+                let value = match t {
+                    "Void" => None,
+                    "String" | "Vec<i8>" | "Vec<i16>" | "Vec<i32>" | "Vec<i64>" | "Vec<f32>" => {
+                        Some("v.clone()")
+                    }
+                    _ => Some("*v"),
+                };
+
+                match value {
+                    Some(value) => mb.line(format!(
+                        "{}::{}(v) => self.{} = Some({}),",
+                        &category.normalized_name.to_case(Case::UpperCamel),
+                        &param.normalized_parameter.to_case(Case::UpperCamel),
+                        &param.normalized_parameter,
+                        value
+                    )),
+                    None => mb.line(format!(
+                        "{}::{} => {{}}",
+                        &category.normalized_name.to_case(Case::UpperCamel),
+                        &param.normalized_parameter.to_case(Case::UpperCamel),
+                    )),
+                };
+            }
+            am.push_block(mb);
+        }
+    }
 }
 
 fn lookuptype(p: &Parameter) -> &'static str {
@@ -425,7 +852,11 @@ fn lookuptype(p: &Parameter) -> &'static str {
 }
 
 fn typeid(p: &Parameter) -> u8 {
-    match p.type_field.as_ref() {
+    typename_id(&p.type_field)
+}
+
+fn typename_id(t: &str) -> u8 {
+    match t {
         "void" => 0,
         "int8" => 1,
         "int16" => 2,
@@ -436,3 +867,43 @@ fn typeid(p: &Parameter) -> u8 {
         _ => 0,
     }
 }
+
+// The protocol text is lifted from a PDF and has line breaks in odd places
+fn collapse(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// String parameters describe their byte range in the index, like "[0-55] = type"
+fn maxlength(p: &Parameter) -> Option<usize> {
+    if p.type_field != "string" {
+        return None;
+    }
+
+    let range = p.index.first()?;
+    let end = range.split(']').next()?.split('-').nth(1)?;
+    end.trim().parse::<usize>().ok().map(|v| v + 1)
+}
+
+// Zero if the documented range allows it, otherwise the bound closest to it
+fn defaultvalue(p: &Parameter) -> Option<String> {
+    let mut v = 0.0f64;
+    if let Some(min) = p.minimum {
+        v = v.max(min);
+    }
+    if let Some(max) = p.maximum {
+        v = v.min(max);
+    }
+
+    let single = match lookuptype(p) {
+        "Void" => return None,
+        "String" => return Some("String::new()".to_string()),
+        "f32" | "Vec<f32>" => format!("{:?}", v),
+        _ => format!("{}", v as i64),
+    };
+
+    if lookuptype(p).starts_with("Vec") {
+        Some(format!("vec![{}; {}]", single, p.index.len()))
+    } else {
+        Some(single)
+    }
+}
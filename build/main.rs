@@ -6,6 +6,7 @@ mod protocol;
 
 fn main() {
     let prt = protocol::BlackmagicCameraProtocol::new().unwrap();
+    let cec = protocol::CecProtocol::new().unwrap();
     let mut cg = gen::Datagen::new(prt);
 
     //Command
@@ -16,4 +17,40 @@ fn main() {
         let cmd_file = cg.gen_command();
         std::fs::write(dest_path, cmd_file.as_bytes()).unwrap();
     }
+
+    //Keys
+    {
+        let out_dir = env::var_os("OUT_DIR").unwrap();
+        let dest_path = Path::new(&out_dir).join("keys.rs");
+
+        let keys_file = cg.gen_keys();
+        std::fs::write(dest_path, keys_file.as_bytes()).unwrap();
+    }
+
+    //State
+    {
+        let out_dir = env::var_os("OUT_DIR").unwrap();
+        let dest_path = Path::new(&out_dir).join("state.rs");
+
+        let state_file = cg.gen_state();
+        std::fs::write(dest_path, state_file.as_bytes()).unwrap();
+    }
+
+    //Parameter info
+    {
+        let out_dir = env::var_os("OUT_DIR").unwrap();
+        let dest_path = Path::new(&out_dir).join("parameters.rs");
+
+        let parameters_file = cg.gen_parameters();
+        std::fs::write(dest_path, parameters_file.as_bytes()).unwrap();
+    }
+
+    //CEC parameters
+    {
+        let out_dir = env::var_os("OUT_DIR").unwrap();
+        let dest_path = Path::new(&out_dir).join("cec_parameters.rs");
+
+        let cec_file = cg.gen_cec(&cec);
+        std::fs::write(dest_path, cec_file.as_bytes()).unwrap();
+    }
 }
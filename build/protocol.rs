@@ -73,3 +73,37 @@ pub struct Characteristic {
     pub description: Option<String>,
     pub decription: Option<String>,
 }
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CecProtocol {
+    pub information: CecInformation,
+    pub opcodes: Vec<CecOpcode>,
+}
+
+impl CecProtocol {
+    pub fn new() -> Result<CecProtocol, std::io::Error> {
+        let data = include_str!("../CEC_PROTOCOL.json");
+        let cfg: CecProtocol = serde_json::from_str(data)?;
+        Ok(cfg)
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CecInformation {
+    pub readme: String,
+    pub source: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CecOpcode {
+    pub opcode: u8,
+    pub name: String,
+    pub group: String,
+    pub parameter: String,
+    pub index: usize,
+    pub count: usize,
+    #[serde(rename = "type")]
+    pub type_field: Option<String>,
+    pub scale: Option<f64>,
+    pub offset: Option<f64>,
+}
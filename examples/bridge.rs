@@ -0,0 +1,28 @@
+use blackmagic_camera_control::blecamera::BluetoothTransport;
+use blackmagic_camera_control::bridge::BridgeServer;
+use blackmagic_camera_control::queue::QueuedTransport;
+use blackmagic_camera_control::Camera;
+use std::error::Error;
+use std::time::Duration;
+
+const CAMERA_NAME: &str = "A:4BE2529F";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    //Connect to the camera over Bluetooth, queueing writes so a slow one does not hold up
+    //every client
+    let transport = QueuedTransport::new(BluetoothTransport::new(CAMERA_NAME).await?);
+    let mut camera = Camera::with_transport(transport);
+    camera.connect(Duration::from_secs(10)).await?;
+
+    //Share it with everyone on the network
+    let mut server = BridgeServer::new(camera);
+    let ws = server.listen_websocket("0.0.0.0:9000").await?;
+    let tcp = server.listen_tcp("0.0.0.0:9001").await?;
+    println!("WebSocket on {}, raw TCP on {}", ws, tcp);
+
+    tokio::signal::ctrl_c().await?;
+    server.shutdown();
+
+    Ok(())
+}
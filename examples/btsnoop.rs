@@ -0,0 +1,22 @@
+use blackmagic_camera_control::btsnoop::BtSnoopParser;
+use std::error::Error;
+use std::fs::File;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    //Path to a btsnoop_hci.log pulled from an Android phone
+    let path = std::env::args()
+        .nth(1)
+        .ok_or("usage: btsnoop <btsnoop_hci.log>")?;
+
+    for c in BtSnoopParser::new().commands(File::open(path)?)? {
+        println!(
+            "{} {:?} {} {}",
+            c.timestamp_us,
+            c.direction,
+            c.command.normalized_name().1,
+            c.command
+        );
+    }
+
+    Ok(())
+}
@@ -1,5 +1,5 @@
-use blackmagic_camera_control::command::{Command, Metadata, Video};
-use blackmagic_camera_control::{BluetoothCamera, Operation};
+use blackmagic_camera_control::command::{Command, Video};
+use blackmagic_camera_control::{keys, BluetoothCamera, Operation};
 use std::error::Error;
 use std::time::Duration;
 use tokio::time;
@@ -14,6 +14,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
     //Connect with a set timeout
     camera.connect(Duration::from_secs(10)).await.unwrap();
 
+    //Wait for the camera to send its current settings
+    camera
+        .wait_synced(Duration::from_millis(500), Duration::from_secs(5))
+        .await
+        .unwrap();
+
     //Change the ISO to 320
     camera
         .write(255, Operation::AssignValue, Command::Video(Video::Iso(320)))
@@ -46,9 +52,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     time::sleep(Duration::from_secs(1)).await;
 
     // Get a specific piece of info from the cached properties
-    let info = camera
-        .get(Command::Metadata(Metadata::LensDistance("".to_string())))
-        .await;
+    let info = camera.get(keys::metadata::LENS_DISTANCE).await;
     dbg!(info);
 
     Ok(())
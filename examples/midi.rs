@@ -0,0 +1,22 @@
+use blackmagic_camera_control::midi::{MidiController, MidiMap};
+use blackmagic_camera_control::BluetoothCamera;
+use std::error::Error;
+use std::time::Duration;
+
+const CAMERA_NAME: &str = "A:4BE2529F";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut camera = BluetoothCamera::new(CAMERA_NAME).await?;
+    camera.connect(Duration::from_secs(10)).await?;
+
+    //No input in the mapping, so this creates a virtual port to connect a controller to
+    let map = MidiMap::open("examples/midi.json")?;
+    let mut controller = MidiController::new(camera, map)?;
+    controller.start()?;
+
+    tokio::signal::ctrl_c().await?;
+    controller.stop();
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use blackmagic_camera_control::command::{Command, Lens, Video};
+use blackmagic_camera_control::{keys, Camera, Operation, SimulatedCamera};
+use futures::StreamExt;
+use std::error::Error;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    //Create a camera backed by the simulator instead of Bluetooth
+    let mut camera = Camera::with_transport(SimulatedCamera::new());
+
+    camera.connect(Duration::from_secs(1)).await?;
+
+    //The simulator sends its full state on connect just like a real camera
+    camera
+        .wait_synced(Duration::from_millis(100), Duration::from_secs(1))
+        .await?;
+
+    //Watch the ISO
+    let mut iso = camera.subscribe(keys::video::ISO).await;
+    tokio::spawn(async move {
+        while let Some(v) = iso.next().await {
+            println!("ISO {}", v);
+        }
+    });
+
+    camera
+        .write(255, Operation::AssignValue, Command::Video(Video::Iso(640)))
+        .await?;
+    camera
+        .write(255, Operation::AssignValue, Command::Lens(Lens::Focus(0.5)))
+        .await?;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    dbg!(camera.state().await.lens);
+
+    Ok(())
+}
@@ -0,0 +1,611 @@
+use crate::error::{CameraControlError, TransportError};
+use crate::rawcommand::{CommandError, RawCommand};
+use crate::transport::{CameraTransport, EventStream, TransportEvent};
+use async_trait::async_trait;
+use futures::stream;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+/// UDP port ATEM switchers listen on
+pub const ATEM_PORT: u16 = 9910;
+
+/// Camera control sent to the switcher
+pub const CCMD: &[u8; 4] = b"CCmd";
+/// Camera control reported by the switcher
+pub const CCDP: &[u8; 4] = b"CCdP";
+
+/// How long the switcher can stay silent before the session is considered lost,
+/// it normally pings every half second
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the switcher to acknowledge a packet before sending it again
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+const HEADER_LENGTH: usize = 12;
+const COMMAND_HEADER_LENGTH: usize = 8;
+
+const FLAG_ACK_REQUEST: u8 = 0x01;
+const FLAG_HELLO: u8 = 0x02;
+const FLAG_RETRANSMISSION: u8 = 0x04;
+const FLAG_ACK_REPLY: u8 = 0x10;
+
+const HELLO_CONNECT: u8 = 0x01;
+const HELLO_ACCEPTED: u8 = 0x02;
+
+// CCmd and CCdP carry a count per value type before the values
+const CAMERA_CONTROL_HEADER_LENGTH: usize = 16;
+
+/// Encodes a camera control packet as the data of a CCmd command
+///
+/// The ATEM sends values big endian and keeps a separate count for every value type,
+/// the destination becomes the switcher input the camera is on.
+///
+/// # Arguments
+///
+/// * `raw` - RawCommand as parsed from a camera control packet
+pub fn encode_camera_control(raw: &RawCommand) -> Result<Vec<u8>, CommandError> {
+    let (slot, size) = value_layout(raw.data_type).ok_or(CommandError::ParameterNotDefined)?;
+
+    let mut data = vec![0u8; CAMERA_CONTROL_HEADER_LENGTH];
+    data[0] = raw.destination_device;
+    data[1] = raw.category;
+    data[2] = raw.parameter;
+    data[3] = raw.operation;
+    data[4] = raw.data_type;
+
+    let count = (raw.data.len() / size) as u16;
+    data[6 + slot * 2..8 + slot * 2].copy_from_slice(&count.to_be_bytes());
+
+    for element in raw.data.chunks_exact(size) {
+        data.extend(element.iter().rev());
+    }
+    data.resize((data.len() + 3) & !3, 0);
+
+    Ok(data)
+}
+
+/// Decodes the data of a CCdP or CCmd command into a camera control packet
+///
+/// # Arguments
+///
+/// * `data` - &[u8] data of the command, without the command header
+pub fn decode_camera_control(data: &[u8]) -> Result<RawCommand, CommandError> {
+    if data.len() < CAMERA_CONTROL_HEADER_LENGTH {
+        return Err(CommandError::MessageShort);
+    }
+
+    let data_type = data[4];
+    let (slot, size) = value_layout(data_type).ok_or(CommandError::ParameterNotDefined)?;
+    let count = u16::from_be_bytes([data[6 + slot * 2], data[7 + slot * 2]]) as usize;
+
+    let end = CAMERA_CONTROL_HEADER_LENGTH + count * size;
+    if data.len() < end {
+        return Err(CommandError::NotEnoughBytes);
+    }
+
+    let values = data[CAMERA_CONTROL_HEADER_LENGTH..end]
+        .chunks_exact(size)
+        .flat_map(|e| e.iter().rev().copied())
+        .collect();
+
+    Ok(RawCommand {
+        destination_device: data[0],
+        command_id: 0,
+        category: data[1],
+        parameter: data[2],
+        data_type,
+        operation: data[3],
+        data: values,
+    })
+}
+
+// Which count a value type is kept in and how many bytes one value takes
+fn value_layout(data_type: u8) -> Option<(usize, usize)> {
+    Some(match data_type {
+        0 | 1 => (0, 1),
+        2 | 128 => (1, 2),
+        3 => (2, 4),
+        4 => (3, 8),
+        5 => (4, 1),
+        _ => return None,
+    })
+}
+
+/// Header of every packet in an ATEM session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Header {
+    flags: u8,
+    length: u16,
+    session: u16,
+    ack: u16,
+    packet: u16,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Option<Header> {
+        if data.len() < HEADER_LENGTH {
+            return None;
+        }
+
+        let word = u16::from_be_bytes([data[0], data[1]]);
+        Some(Header {
+            flags: (word >> 11) as u8,
+            length: word & 0x07FF,
+            session: u16::from_be_bytes([data[2], data[3]]),
+            ack: u16::from_be_bytes([data[4], data[5]]),
+            packet: u16::from_be_bytes([data[10], data[11]]),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_LENGTH] {
+        let mut b = [0u8; HEADER_LENGTH];
+        let word = ((self.flags as u16) << 11) | (self.length & 0x07FF);
+        b[0..2].copy_from_slice(&word.to_be_bytes());
+        b[2..4].copy_from_slice(&self.session.to_be_bytes());
+        b[4..6].copy_from_slice(&self.ack.to_be_bytes());
+        b[10..12].copy_from_slice(&self.packet.to_be_bytes());
+        b
+    }
+}
+
+fn packet(flags: u8, session: u16, ack: u16, id: u16, payload: &[u8]) -> Vec<u8> {
+    let header = Header {
+        flags,
+        length: (HEADER_LENGTH + payload.len()) as u16,
+        session,
+        ack,
+        packet: id,
+    };
+
+    let mut v = header.to_bytes().to_vec();
+    v.extend_from_slice(payload);
+    v
+}
+
+// Splits the payload of a packet into (name, data) commands
+fn commands(mut payload: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut commands = Vec::new();
+
+    while payload.len() >= COMMAND_HEADER_LENGTH {
+        let length = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+        if length < COMMAND_HEADER_LENGTH || length > payload.len() {
+            break;
+        }
+
+        let mut name = [0u8; 4];
+        name.copy_from_slice(&payload[4..8]);
+        commands.push((name, &payload[COMMAND_HEADER_LENGTH..length]));
+        payload = &payload[length..];
+    }
+
+    commands
+}
+
+#[derive(Debug, Default)]
+struct Session {
+    id: u16,
+    local_packet: u16,
+    remote_packet: Option<u16>,
+    unacked: Vec<Unacked>,
+}
+
+// A packet sent to the switcher that has not been acknowledged yet
+#[derive(Debug)]
+struct Unacked {
+    packet: u16,
+    payload: Vec<u8>,
+    first_sent: Instant,
+    last_sent: Instant,
+}
+
+impl Session {
+    // The switcher acknowledges every packet up to and including `ack`
+    fn acked(&mut self, ack: u16) {
+        self.unacked
+            .retain(|u| ack.wrapping_sub(u.packet) & 0x7FFF >= 0x4000);
+    }
+
+    // Packets to send again, None once one has gone unacknowledged for too long
+    fn resends(&mut self, now: Instant) -> Option<Vec<Vec<u8>>> {
+        let mut resends = Vec::new();
+        for u in self.unacked.iter_mut() {
+            if now.duration_since(u.first_sent) > SESSION_TIMEOUT {
+                return None;
+            }
+            if now.duration_since(u.last_sent) >= RESEND_INTERVAL {
+                u.last_sent = now;
+                resends.push(packet(
+                    FLAG_ACK_REQUEST | FLAG_RETRANSMISSION,
+                    self.id,
+                    0,
+                    u.packet,
+                    &u.payload,
+                ));
+            }
+        }
+        Some(resends)
+    }
+}
+
+/// Controls cameras through an ATEM switcher
+///
+/// Camera control is sent to the switcher as CCmd commands over its UDP protocol and the
+/// switcher relays it to the cameras over SDI. The camera id in `Camera::write` is the
+/// switcher input the camera is on. Packets the switcher does not acknowledge are sent
+/// again every RESEND_INTERVAL, the session is dropped if one stays unacknowledged for
+/// SESSION_TIMEOUT.
+#[derive(Debug)]
+pub struct AtemTransport {
+    address: SocketAddr,
+
+    socket: Option<Arc<UdpSocket>>,
+    session: Arc<Mutex<Session>>,
+    incoming: Option<UnboundedReceiver<TransportEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl AtemTransport {
+    /// Takes the address of the switcher and returns a new AtemTransport
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - SocketAddr of the switcher, usually on port ATEM_PORT
+    pub fn new(address: SocketAddr) -> AtemTransport {
+        AtemTransport {
+            address,
+
+            socket: None,
+            session: Arc::new(Mutex::new(Session::default())),
+            incoming: None,
+            task: None,
+        }
+    }
+
+    async fn handshake(socket: &UdpSocket, session: u16) -> Result<u16, TransportError> {
+        let mut hello = [0u8; 8];
+        hello[0] = HELLO_CONNECT;
+        socket
+            .send(&packet(FLAG_HELLO, session, 0, 0, &hello))
+            .await?;
+
+        let mut buf = [0u8; 2048];
+        loop {
+            let n = socket.recv(&mut buf).await?;
+            let header = match Header::parse(&buf[..n]) {
+                Some(h) if h.flags & FLAG_HELLO != 0 => h,
+                _ => continue,
+            };
+
+            if buf.get(HEADER_LENGTH) != Some(&HELLO_ACCEPTED) {
+                return Err(TransportError::Refused);
+            }
+
+            socket
+                .send(&packet(FLAG_ACK_REPLY, header.session, 0, 0, &[]))
+                .await?;
+            return Ok(header.session);
+        }
+    }
+
+    async fn receive(
+        socket: Arc<UdpSocket>,
+        session: Arc<Mutex<Session>>,
+        tx: mpsc::UnboundedSender<TransportEvent>,
+    ) {
+        let mut buf = [0u8; 2048];
+        let mut resend = time::interval(RESEND_INTERVAL);
+        let mut last_heard = Instant::now();
+
+        'session: loop {
+            let n = tokio::select! {
+                received = socket.recv(&mut buf) => match received {
+                    Ok(n) => n,
+                    Err(_) => break,
+                },
+                now = resend.tick() => {
+                    if now.duration_since(last_heard) > SESSION_TIMEOUT {
+                        break;
+                    }
+                    let resends = match session.lock().unwrap().resends(now) {
+                        Some(v) => v,
+                        None => break,
+                    };
+                    for datagram in resends {
+                        if socket.send(&datagram).await.is_err() {
+                            break 'session;
+                        }
+                    }
+                    continue;
+                }
+            };
+            last_heard = Instant::now();
+
+            let header = match Header::parse(&buf[..n]) {
+                Some(h) => h,
+                None => continue,
+            };
+
+            if header.flags & FLAG_ACK_REPLY != 0 {
+                session.lock().unwrap().acked(header.ack);
+            }
+
+            if header.flags & FLAG_ACK_REQUEST == 0 {
+                continue;
+            }
+
+            // The switcher moves to a new session id once the handshake is done
+            let duplicate = {
+                let mut s = session.lock().unwrap();
+                s.id = header.session;
+
+                let duplicate = match s.remote_packet {
+                    Some(last) => last.wrapping_sub(header.packet) & 0x7FFF < 0x4000,
+                    None => false,
+                };
+                if !duplicate {
+                    s.remote_packet = Some(header.packet);
+                }
+                duplicate
+            };
+
+            let ack = packet(FLAG_ACK_REPLY, header.session, header.packet, 0, &[]);
+            if socket.send(&ack).await.is_err() {
+                break;
+            }
+
+            if duplicate {
+                continue;
+            }
+
+            let end = (header.length as usize).clamp(HEADER_LENGTH, n);
+            for (name, data) in commands(&buf[HEADER_LENGTH..end]) {
+                if &name != CCDP {
+                    continue;
+                }
+                if let Ok(raw) = decode_camera_control(data) {
+                    if tx.send(TransportEvent::Packet(raw.to_bytes())).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let _ = tx.send(TransportEvent::Disconnected);
+    }
+}
+
+#[async_trait]
+impl CameraTransport for AtemTransport {
+    type Error = TransportError;
+
+    /// Opens a session with the switcher, waiting as long as supplied timeout specifies
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - std::Duration of how long to wait before giving up
+    async fn connect(&mut self, timeout: Duration) -> Result<(), TransportError> {
+        self.disconnect().await?;
+
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).await?;
+        socket.connect(self.address).await?;
+
+        // The client picks the first session id, the switcher assigns the real one
+        let hello_session = (std::process::id() as u16 & 0x7FFF) | 0x1000;
+        let id = time::timeout(timeout, AtemTransport::handshake(&socket, hello_session))
+            .await
+            .map_err(|_| CameraControlError::ConnectionTimeout)??;
+
+        *self.session.lock().unwrap() = Session {
+            id,
+            local_packet: 0,
+            remote_packet: None,
+            unacked: Vec::new(),
+        };
+
+        let socket = Arc::new(socket);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let _ = tx.send(TransportEvent::Connected);
+
+        self.task = Some(tokio::spawn(AtemTransport::receive(
+            socket.clone(),
+            self.session.clone(),
+            tx,
+        )));
+        self.socket = Some(socket);
+        self.incoming = Some(rx);
+
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        self.socket = None;
+        self.incoming = None;
+        Ok(())
+    }
+
+    async fn send(&mut self, packet_data: &[u8]) -> Result<(), TransportError> {
+        let socket = self.socket.as_ref().ok_or(TransportError::NotConnected)?;
+
+        let data = encode_camera_control(&RawCommand::from_raw(packet_data)?)?;
+        let mut command = ((COMMAND_HEADER_LENGTH + data.len()) as u16)
+            .to_be_bytes()
+            .to_vec();
+        command.extend_from_slice(&[0, 0]);
+        command.extend_from_slice(CCMD);
+        command.extend_from_slice(&data);
+
+        let datagram = {
+            let mut s = self.session.lock().unwrap();
+            s.local_packet = (s.local_packet + 1) & 0x7FFF;
+
+            let now = Instant::now();
+            let id = s.local_packet;
+            s.unacked.push(Unacked {
+                packet: id,
+                payload: command.clone(),
+                first_sent: now,
+                last_sent: now,
+            });
+
+            packet(FLAG_ACK_REQUEST, s.id, 0, id, &command)
+        };
+
+        socket.send(&datagram).await?;
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, TransportError> {
+        let mut rx = self.incoming.take().ok_or(TransportError::NotConnected)?;
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Command, Lens};
+    use crate::rawcommand::Operation;
+    use futures::StreamExt;
+
+    // Plays the switcher's side of a session
+    struct Switcher {
+        socket: UdpSocket,
+        client: Option<SocketAddr>,
+    }
+
+    impl Switcher {
+        async fn bind() -> Switcher {
+            Switcher {
+                socket: UdpSocket::bind(("127.0.0.1", 0)).await.unwrap(),
+                client: None,
+            }
+        }
+
+        async fn recv(&mut self) -> (Header, Vec<u8>) {
+            let mut buf = [0u8; 2048];
+            let (n, from) = time::timeout(Duration::from_secs(5), self.socket.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            self.client = Some(from);
+            (
+                Header::parse(&buf[..n]).unwrap(),
+                buf[HEADER_LENGTH..n].to_vec(),
+            )
+        }
+
+        async fn send(&self, datagram: &[u8]) {
+            self.socket
+                .send_to(datagram, self.client.unwrap())
+                .await
+                .unwrap();
+        }
+
+        async fn accept(&mut self, session: u16) {
+            let (hello, payload) = self.recv().await;
+            assert_ne!(hello.flags & FLAG_HELLO, 0);
+            assert_eq!(payload[0], HELLO_CONNECT);
+
+            let mut accepted = [0u8; 8];
+            accepted[0] = HELLO_ACCEPTED;
+            self.send(&packet(FLAG_HELLO, session, 0, 0, &accepted))
+                .await;
+
+            let (ack, _) = self.recv().await;
+            assert_ne!(ack.flags & FLAG_ACK_REPLY, 0);
+        }
+    }
+
+    fn focus(value: f32) -> RawCommand {
+        RawCommand::from_raw(&RawCommand::to_raw(
+            1,
+            Operation::AssignValue,
+            &Command::Lens(Lens::Focus(value)),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn camera_control_layout() {
+        let data = encode_camera_control(&focus(0.5)).unwrap();
+
+        assert_eq!(data[..5], [1, 0, 0, 0, 128]);
+        // One fixed16 value, kept in the second count
+        assert_eq!(data[6..16], [0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(data[16..18], [0x04, 0x00]);
+        assert_eq!(data.len() % 4, 0);
+
+        assert_eq!(decode_camera_control(&data).unwrap().data, focus(0.5).data);
+    }
+
+    #[tokio::test]
+    async fn talks_to_a_switcher() {
+        let mut switcher = Switcher::bind().await;
+        let mut transport = AtemTransport::new(switcher.socket.local_addr().unwrap());
+
+        let (connected, _) = tokio::join!(
+            transport.connect(Duration::from_secs(5)),
+            switcher.accept(0x1234)
+        );
+        connected.unwrap();
+        let mut events = transport.events().await.unwrap();
+        assert_eq!(events.next().await, Some(TransportEvent::Connected));
+
+        // The switcher reports a value, the client acknowledges it
+        let data = encode_camera_control(&focus(0.25)).unwrap();
+        let mut command = ((COMMAND_HEADER_LENGTH + data.len()) as u16)
+            .to_be_bytes()
+            .to_vec();
+        command.extend_from_slice(&[0, 0]);
+        command.extend_from_slice(CCDP);
+        command.extend_from_slice(&data);
+        switcher
+            .send(&packet(FLAG_ACK_REQUEST, 0x8001, 0, 1, &command))
+            .await;
+
+        let (ack, _) = switcher.recv().await;
+        assert_ne!(ack.flags & FLAG_ACK_REPLY, 0);
+        assert_eq!((ack.session, ack.ack), (0x8001, 1));
+        assert_eq!(
+            events.next().await,
+            Some(TransportEvent::Packet(focus(0.25).to_bytes()))
+        );
+
+        // A write goes out as CCmd
+        transport.send(&focus(0.5).to_bytes()).await.unwrap();
+        let (sent, payload) = switcher.recv().await;
+        assert_ne!(sent.flags & FLAG_ACK_REQUEST, 0);
+        assert_eq!(sent.session, 0x8001);
+        let sent_commands = commands(&payload);
+        assert_eq!(sent_commands.len(), 1);
+        assert_eq!(&sent_commands[0].0, CCMD);
+        assert_eq!(
+            sent_commands[0].1,
+            &encode_camera_control(&focus(0.5)).unwrap()[..]
+        );
+
+        // Left unacknowledged it comes again, until it is acknowledged
+        let (resent, again) = switcher.recv().await;
+        assert_ne!(resent.flags & FLAG_RETRANSMISSION, 0);
+        assert_eq!(resent.packet, sent.packet);
+        assert_eq!(again, payload);
+
+        switcher
+            .send(&packet(FLAG_ACK_REPLY, 0x8001, sent.packet, 0, &[]))
+            .await;
+        let mut buf = [0u8; 2048];
+        assert!(
+            time::timeout(RESEND_INTERVAL * 3, switcher.socket.recv_from(&mut buf))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,34 +1,60 @@
-use crate::command::Command;
+use crate::camera::Camera;
 use crate::error::BluetoothCameraError;
-use crate::rawcommand::{Operation, RawCommand};
+use crate::transport::{CameraTransport, EventStream, TransportEvent};
+use async_trait::async_trait;
 use btleplug::api::{
-    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, ValueNotification,
+    Central, CentralEvent, Characteristic, Manager as _, Peripheral as _, ScanFilter,
 };
 use btleplug::platform::{Adapter, Manager, Peripheral};
-use futures::stream::StreamExt;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::pin::Pin;
-use std::sync::Arc;
+use futures::future;
+use futures::stream::{self, StreamExt};
 use std::time::Duration;
-use tokio::sync::broadcast::{self, Receiver, Sender};
-use tokio::sync::RwLock;
 use tokio::time;
-use uuid::Uuid;
-
-pub const CAMERA_SERVICE: Uuid = Uuid::from_u128(54650678423016196498641639054569411539);
-pub const CAMERA_MANUFACTURER: Uuid = Uuid::from_u128(855109558092022082745622393992443);
-pub const CAMERA_MODEL: Uuid = Uuid::from_u128(854713417279450761057654674240763);
-pub const OUTGOING_CAMERA_CONTROL: Uuid = Uuid::from_u128(124715205548830368390231916378743955899);
-pub const INCOMING_CAMERA_CONTROL: Uuid = Uuid::from_u128(245101749559754194128926468485788547033);
-pub const TIMECODE: Uuid = Uuid::from_u128(145629020620256484157652687441451644616);
-pub const CAMERA_STATUS: Uuid = Uuid::from_u128(170018700332869099062316608707586904505);
-pub const DEVICE_NAME: Uuid = Uuid::from_u128(339846463932956345205123112215954503836);
-pub const PROTOCOL_VERSION: Uuid = Uuid::from_u128(190244785298557795456958317949635929862);
+
+pub use crate::characteristics::*;
+
+/// Default ATT MTU before any negotiation, leaves 20 bytes of payload per write
+pub const DEFAULT_MTU: u16 = 23;
+
+// ATT opcode and handle that every write spends out of the MTU
+const ATT_HEADER_LENGTH: u16 = 3;
+
+/// A Blackmagic camera connected over Bluetooth Low Energy
+pub type BluetoothCamera = Camera<BluetoothTransport>;
+
+impl Camera<BluetoothTransport> {
+    /// Takes the BLE name of the camera and returns a new BluetoothCamera instance
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - &str representing the Bluetooth name of the camera such as "A:5CA7128B"
+    pub async fn new(name: &str) -> Result<BluetoothCamera, BluetoothCameraError> {
+        Ok(Camera::with_transport(BluetoothTransport::new(name).await?))
+    }
+
+    /// Sets the ATT MTU negotiated with the camera
+    ///
+    /// The underlying bluetooth library does not report the negotiated MTU, so it stays at
+    /// DEFAULT_MTU unless you set it here. Writes that do not fit in it are sent with
+    /// response, so only set what your platform actually negotiated. A larger value gets
+    /// long packets sent without response and truncated by the camera.
+    ///
+    /// # Arguments
+    ///
+    /// * `mtu` - u16 ATT MTU, values below the BLE minimum of 23 are raised to it
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.transport_mut().set_mtu(mtu);
+    }
+
+    /// Returns the ATT MTU used when deciding how to send a packet
+    pub fn mtu(&self) -> u16 {
+        self.transport().mtu()
+    }
+}
 
 #[derive(Debug)]
 #[allow(dead_code)]
-pub struct BluetoothCamera {
+pub struct BluetoothTransport {
     name: String,
 
     bluetooth_manager: Manager,
@@ -39,23 +65,16 @@ pub struct BluetoothCamera {
     write_char: Option<Characteristic>,
     read_char: Option<Characteristic>,
 
-    updates: Sender<Command>,
-    cache: Arc<RwLock<HashMap<String, Command>>>,
+    mtu: u16,
 }
 
-// impl std::fmt::Display for BluetoothCamera {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         todo!()
-//     }
-// }
-
-impl BluetoothCamera {
-    /// Takes the BLE name of the camera and returns a new BluetoothCamera instance
+impl BluetoothTransport {
+    /// Takes the BLE name of the camera and returns a new BluetoothTransport instance
     ///
     /// # Arguments
     ///
     /// * `name` - &str representing the Bluetooth name of the camera such as "A:5CA7128B"
-    pub async fn new(name: &str) -> Result<BluetoothCamera, BluetoothCameraError> {
+    pub async fn new(name: &str) -> Result<BluetoothTransport, BluetoothCameraError> {
         let bluetooth_manager = Manager::new().await?;
 
         let adapter = bluetooth_manager.adapters().await?;
@@ -65,7 +84,7 @@ impl BluetoothCamera {
             .nth(0)
             .ok_or(BluetoothCameraError::NoBluetooth)?;
 
-        Ok(BluetoothCamera {
+        Ok(BluetoothTransport {
             name: name.to_string(),
             bluetooth_manager,
             adapter,
@@ -74,17 +93,52 @@ impl BluetoothCamera {
             write_char: None,
             read_char: None,
 
-            updates: broadcast::channel(16).0,
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            mtu: DEFAULT_MTU,
         })
     }
 
+    /// Sets the ATT MTU negotiated with the camera
+    ///
+    /// Never read from the connection, callers have to set it, see BluetoothCamera::set_mtu.
+    ///
+    /// # Arguments
+    ///
+    /// * `mtu` - u16 ATT MTU, values below the BLE minimum of 23 are raised to it
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.mtu = mtu.max(DEFAULT_MTU);
+    }
+
+    /// Returns the ATT MTU used when deciding how to send a packet
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    async fn find_camera(&self) -> Result<Peripheral, BluetoothCameraError> {
+        for p in self.adapter.peripherals().await? {
+            if p.properties()
+                .await?
+                .ok_or(BluetoothCameraError::DiscoveryError)?
+                .local_name
+                .iter()
+                .any(|name| name.contains(&self.name))
+            {
+                return Ok(p);
+            }
+        }
+        Err(BluetoothCameraError::CameraNotFound(self.name.to_string()))
+    }
+}
+
+#[async_trait]
+impl CameraTransport for BluetoothTransport {
+    type Error = BluetoothCameraError;
+
     /// Tries to connect to the camera, waiting as long as supplied timeout specifies
     ///
     /// # Arguments
     ///
     /// * `timeout` - std::Duration of how long to wait before giving up
-    pub async fn connect(&mut self, timeout: Duration) -> Result<(), BluetoothCameraError> {
+    async fn connect(&mut self, timeout: Duration) -> Result<(), BluetoothCameraError> {
         let now = time::Instant::now();
         self.adapter
             .start_scan(ScanFilter {
@@ -143,13 +197,6 @@ impl BluetoothCamera {
                                 .ok_or(BluetoothCameraError::DevRefError)?,
                         )
                         .await?;
-                    let stream = device.notifications().await?;
-
-                    let ble_cache = self.cache.clone();
-                    let ble_updates = self.updates.clone();
-                    tokio::spawn(async move {
-                        BluetoothCamera::handle_incoming(ble_cache, ble_updates, stream).await;
-                    });
 
                     return Ok(());
                 }
@@ -165,7 +212,7 @@ impl BluetoothCamera {
     /// Disconnects from the camera
     ///
     /// NOTE: THIS ACTUALLY DOESN'T WORK ON OSX BECAUSE THE UNDERLYING LIBRARY IS PEPEGA
-    pub async fn disconnect(&mut self) -> Result<(), BluetoothCameraError> {
+    async fn disconnect(&mut self) -> Result<(), BluetoothCameraError> {
         Ok(self
             .device
             .as_ref()
@@ -174,100 +221,59 @@ impl BluetoothCamera {
             .await?)
     }
 
-    pub async fn write(
-        &mut self,
-        destination: u8,
-        operation: Operation,
-        command: Command,
-    ) -> Result<(), BluetoothCameraError> {
+    /// Writes a packet to the camera
+    ///
+    /// Packets that fit in a single ATT payload are sent without response. Longer ones,
+    /// such as most metadata strings, are sent as a write with response so the bluetooth
+    /// stack splits them into a long write instead of the camera seeing a truncated packet.
+    async fn send(&mut self, packet: &[u8]) -> Result<(), BluetoothCameraError> {
         let device = self
             .device
             .as_ref()
             .ok_or(BluetoothCameraError::SendError)?;
 
+        let write_type = if packet.len() <= (self.mtu - ATT_HEADER_LENGTH) as usize {
+            btleplug::api::WriteType::WithoutResponse
+        } else {
+            btleplug::api::WriteType::WithResponse
+        };
+
         device
             .write(
                 self.write_char
                     .as_ref()
                     .ok_or(BluetoothCameraError::NoCharacteristic)?,
-                &RawCommand::to_raw(destination, operation, &command),
-                btleplug::api::WriteType::WithoutResponse,
+                packet,
+                write_type,
             )
             .await?;
 
         Ok(())
     }
 
-    async fn handle_incoming(
-        cache: Arc<RwLock<HashMap<String, Command>>>,
-        updates: Sender<Command>,
-        mut stream: Pin<Box<dyn futures::Stream<Item = ValueNotification> + Send>>,
-    ) {
-        while let Some(data) = stream.next().await {
-            let cmd = Command::from_raw(&data.value);
-            match cmd {
-                Ok(v) => {
-                    let (cg, pr) = v.normalized_name();
-                    cache
-                        .write()
-                        .await
-                        .insert(format!("{}_{}", cg, pr), v.clone());
-                    let _ = updates.send(v.clone());
-                }
-                Err(_) => {}
-            }
-        }
-    }
-
-    /// Gives you the latest cached version of the supplied Command
-    /// If no cached version is found, returns the empty property
-    ///
-    /// # Arguments
-    ///
-    /// * `cmd` - Command like this: Command::Metadata(Metadata::LensDistance("".to_string()))
-    pub async fn get(&self, cmd: Command) -> Command {
-        let (cg, pr) = cmd.normalized_name();
-        match self
-            .cache
-            .clone()
-            .read()
-            .await
-            .get(&format! {"{}_{}", &cg, &pr})
-        {
-            Some(c) => c.clone(),
-            None => cmd,
-        }
-    }
+    async fn events(&mut self) -> Result<EventStream, BluetoothCameraError> {
+        let device = self
+            .device
+            .as_ref()
+            .ok_or(BluetoothCameraError::DevRefError)?;
 
-    /// Gives you the latest cached version of the supplied normalized_name
-    ///
-    /// # Arguments
-    ///
-    /// * `normalized_name` - &str like this: metadata_lens_distance
-    pub async fn get_normalized(&self, normalized_name: &str) -> Option<Command> {
-        match self.cache.clone().read().await.get(normalized_name) {
-            Some(c) => Some(c.clone()),
-            None => None,
-        }
-    }
+        let notifications = device.notifications().await?.filter_map(|n| {
+            future::ready(match n.uuid {
+                INCOMING_CAMERA_CONTROL => Some(TransportEvent::Packet(n.value)),
+                _ => None,
+            })
+        });
 
-    /// Returns a channel which allows you to get updates from the camera
-    pub async fn updates(&mut self) -> Receiver<Command> {
-        self.updates.subscribe()
-    }
+        let id = device.id();
+        let central = self.adapter.events().await?.filter_map(move |e| {
+            future::ready(match e {
+                CentralEvent::DeviceDisconnected(d) if d == id => {
+                    Some(TransportEvent::Disconnected)
+                }
+                _ => None,
+            })
+        });
 
-    async fn find_camera(&self) -> Result<Peripheral, BluetoothCameraError> {
-        for p in self.adapter.peripherals().await? {
-            if p.properties()
-                .await?
-                .ok_or(BluetoothCameraError::DiscoveryError)?
-                .local_name
-                .iter()
-                .any(|name| name.contains(&self.name))
-            {
-                return Ok(p);
-            }
-        }
-        Err(BluetoothCameraError::CameraNotFound(self.name.to_string()))
+        Ok(Box::pin(stream::select(notifications, central)))
     }
 }
@@ -0,0 +1,477 @@
+use crate::camera::Camera;
+use crate::command::Command;
+use crate::rawcommand::{Operation, RawCommand};
+use crate::state::CameraState;
+use crate::transport::CameraTransport;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+/// A message from a WebSocket client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Writes a command to the camera
+    Write {
+        #[serde(default = "broadcast_destination")]
+        destination: u8,
+        #[serde(default = "assign_value")]
+        operation: Operation,
+        command: Command,
+    },
+
+    /// Asks for a snapshot of everything the camera has reported
+    State,
+}
+
+fn broadcast_destination() -> u8 {
+    255
+}
+
+fn assign_value() -> Operation {
+    Operation::AssignValue
+}
+
+/// A message to a WebSocket client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Sent when a client connects, when it asks and when it fell behind on updates
+    State {
+        connected: bool,
+        state: Box<CameraState>,
+    },
+
+    /// A value the camera reported
+    Update { command: Command },
+
+    /// A message from the client could not be handled
+    Error { message: String },
+}
+
+/// Pads a camera control packet to the next 32-bit boundary for the raw TCP protocol
+///
+/// The raw protocol is just packets back to back, padded the same way the camera pads
+/// them over SDI, so the length in the header is all a reader needs to find the next one.
+///
+/// # Arguments
+///
+/// * `packet` - &[u8] as built by RawCommand::to_raw
+pub fn encode_packet(packet: &[u8]) -> Vec<u8> {
+    let mut v = packet.to_vec();
+    v.resize((v.len() + 3) & !3, 0);
+    v
+}
+
+/// Reads the next packet of the raw TCP protocol, None once the stream has ended
+///
+/// The padding is dropped, the packet is returned the way RawCommand::to_raw builds it.
+pub async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let length = header[1] as usize;
+    let mut rest = vec![0u8; (length + 3) & !3];
+    reader.read_exact(&mut rest).await?;
+
+    let mut packet = header.to_vec();
+    packet.extend_from_slice(&rest[..length]);
+    Ok(Some(packet))
+}
+
+/// Shares a single camera with any number of clients over the network
+///
+/// WebSocket clients exchange JSON ClientMessage and ServerMessage values, raw TCP
+/// clients exchange camera control packets. Either way a new client first gets
+/// everything the camera has reported so far and then every update as it arrives,
+/// and anything a client writes goes straight to the camera.
+///
+/// A write holds the shared camera until the transport has sent it, wrap a slow
+/// transport such as Bluetooth in a QueuedTransport so one write does not hold up every
+/// other client.
+#[derive(Debug)]
+pub struct BridgeServer<T: CameraTransport> {
+    camera: Arc<Mutex<Camera<T>>>,
+    shutdown: Option<broadcast::Sender<()>>,
+    listeners: Vec<JoinHandle<()>>,
+}
+
+impl<T: CameraTransport> BridgeServer<T> {
+    /// Takes a camera and returns a new BridgeServer, nothing listens until told to
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - Camera to share, connected or not
+    pub fn new(camera: Camera<T>) -> BridgeServer<T> {
+        BridgeServer {
+            camera: Arc::new(Mutex::new(camera)),
+            shutdown: Some(broadcast::channel(1).0),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Gives you the shared camera, to connect it or use it alongside the clients
+    pub fn camera(&self) -> Arc<Mutex<Camera<T>>> {
+        self.camera.clone()
+    }
+
+    /// Starts accepting WebSocket clients, returns the address it listens on
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - address to listen on like this: "0.0.0.0:9000"
+    pub async fn listen_websocket<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<SocketAddr> {
+        self.listen(addr, |camera, stream, shutdown| {
+            tokio::spawn(websocket_client(camera, stream, shutdown));
+        })
+        .await
+    }
+
+    /// Starts accepting raw TCP clients, returns the address it listens on
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - address to listen on like this: "0.0.0.0:9001"
+    pub async fn listen_tcp<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<SocketAddr> {
+        self.listen(addr, |camera, stream, shutdown| {
+            tokio::spawn(tcp_client(camera, stream, shutdown));
+        })
+        .await
+    }
+
+    async fn listen<A, F>(&mut self, addr: A, serve: F) -> io::Result<SocketAddr>
+    where
+        A: ToSocketAddrs,
+        F: Fn(Arc<Mutex<Camera<T>>>, TcpStream, broadcast::Receiver<()>) + Send + 'static,
+    {
+        let shutdown = self
+            .shutdown
+            .clone()
+            .ok_or_else(|| io::Error::other("bridge is shut down"))?;
+
+        let listener = TcpListener::bind(addr).await?;
+        let local = listener.local_addr()?;
+
+        let camera = self.camera.clone();
+        self.listeners.push(tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let _ = stream.set_nodelay(true);
+                serve(camera.clone(), stream, shutdown.subscribe());
+            }
+        }));
+
+        Ok(local)
+    }
+
+    /// Stops listening and disconnects every client, the camera stays connected
+    pub fn shutdown(&mut self) {
+        for listener in self.listeners.drain(..) {
+            listener.abort();
+        }
+        self.shutdown = None;
+    }
+}
+
+impl<T: CameraTransport> Drop for BridgeServer<T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+async fn state_message<T: CameraTransport>(camera: &Mutex<Camera<T>>) -> ServerMessage {
+    let camera = camera.lock().await;
+    ServerMessage::State {
+        connected: camera.is_connected(),
+        state: Box::new(camera.state().await),
+    }
+}
+
+async fn handle_message<T: CameraTransport>(
+    camera: &Mutex<Camera<T>>,
+    text: &str,
+) -> Option<ServerMessage> {
+    let message = match serde_json::from_str(text) {
+        Ok(m) => m,
+        Err(e) => {
+            return Some(ServerMessage::Error {
+                message: e.to_string(),
+            })
+        }
+    };
+
+    match message {
+        // The camera echoes accepted changes, which reach the client as an update
+        ClientMessage::Write {
+            destination,
+            operation,
+            command,
+        } => camera
+            .lock()
+            .await
+            .write(destination, operation, command)
+            .await
+            .err()
+            .map(|e| ServerMessage::Error {
+                message: e.to_string(),
+            }),
+        ClientMessage::State => Some(state_message(camera).await),
+    }
+}
+
+async fn websocket_client<T: CameraTransport>(
+    camera: Arc<Mutex<Camera<T>>>,
+    stream: TcpStream,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    let (mut sink, mut source) = socket.split();
+
+    let mut updates = camera.lock().await.updates().await;
+    let mut outgoing = Some(state_message(&camera).await);
+
+    loop {
+        if let Some(message) = outgoing.take() {
+            let text = serde_json::to_string(&message).unwrap_or_default();
+            if sink.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+
+        outgoing = tokio::select! {
+            _ = shutdown.recv() => break,
+            update = updates.recv() => match update {
+                Ok(command) => Some(ServerMessage::Update { command }),
+                // Missed updates are replaced by a fresh snapshot
+                Err(RecvError::Lagged(_)) => Some(state_message(&camera).await),
+                Err(RecvError::Closed) => break,
+            },
+            message = source.next() => match message {
+                Some(Ok(Message::Text(text))) => handle_message(&camera, &text).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => None,
+            },
+        };
+    }
+
+    let _ = sink.close().await;
+}
+
+async fn tcp_client<T: CameraTransport>(
+    camera: Arc<Mutex<Camera<T>>>,
+    stream: TcpStream,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let (mut reader, mut writer) = stream.into_split();
+
+    let mut updates = camera.lock().await.updates().await;
+
+    let writes = camera.clone();
+    let mut reading = tokio::spawn(async move {
+        while let Ok(Some(packet)) = read_packet(&mut reader).await {
+            let (raw, command) = match (RawCommand::from_raw(&packet), Command::from_raw(&packet)) {
+                (Ok(r), Ok(c)) => (r, c),
+                _ => continue,
+            };
+            let operation = Operation::from_u8(raw.operation);
+            let _ = writes
+                .lock()
+                .await
+                .write(raw.destination_device, operation, command)
+                .await;
+        }
+    });
+
+    let mut outgoing = camera.lock().await.commands().await;
+    loop {
+        let mut data = Vec::new();
+        for command in outgoing.drain(..) {
+            let packet = RawCommand::to_raw(255, Operation::AssignValue, &command);
+            data.extend(encode_packet(&packet));
+        }
+        if !data.is_empty() && writer.write_all(&data).await.is_err() {
+            break;
+        }
+
+        tokio::select! {
+            _ = shutdown.recv() => break,
+            _ = &mut reading => break,
+            update = updates.recv() => match update {
+                Ok(command) => outgoing.push(command),
+                // Missed updates are replaced by everything the camera has reported
+                Err(RecvError::Lagged(_)) => outgoing = camera.lock().await.commands().await,
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+
+    reading.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Lens, Video};
+    use crate::simulator::SimulatedCamera;
+    use futures::Stream;
+    use std::time::Duration;
+    use tokio::time::timeout;
+    use tokio_tungstenite::tungstenite;
+
+    async fn bridge() -> BridgeServer<SimulatedCamera> {
+        let mut camera = Camera::with_transport(SimulatedCamera::new());
+        camera.connect(Duration::from_secs(1)).await.unwrap();
+        camera
+            .wait_synced(Duration::from_millis(50), Duration::from_secs(1))
+            .await
+            .unwrap();
+        BridgeServer::new(camera)
+    }
+
+    async fn receive<S>(socket: &mut S) -> ServerMessage
+    where
+        S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        loop {
+            match timeout(Duration::from_secs(5), socket.next())
+                .await
+                .unwrap()
+            {
+                Some(Ok(Message::Text(text))) => return serde_json::from_str(&text).unwrap(),
+                Some(Ok(_)) => continue,
+                other => panic!("expected a message, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn packets_are_padded_to_32_bits() {
+        let packet =
+            RawCommand::to_raw(1, Operation::AssignValue, &Command::Lens(Lens::Focus(0.5)));
+        let encoded = encode_packet(&packet);
+        assert_eq!(encoded.len(), 12);
+
+        let mut stream = [encoded.clone(), encoded].concat();
+        let mut reader = &stream[..];
+        assert_eq!(
+            read_packet(&mut reader).await.unwrap(),
+            Some(packet.clone())
+        );
+        assert_eq!(read_packet(&mut reader).await.unwrap(), Some(packet));
+        assert_eq!(read_packet(&mut reader).await.unwrap(), None);
+
+        stream.truncate(18);
+        let mut reader = &stream[..];
+        read_packet(&mut reader).await.unwrap();
+        assert!(read_packet(&mut reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn serves_websocket_clients() {
+        let mut server = bridge().await;
+        let addr = server.listen_websocket("127.0.0.1:0").await.unwrap();
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+
+        match receive(&mut socket).await {
+            ServerMessage::State { connected, state } => {
+                assert!(connected);
+                assert!(state.video.iso.is_some());
+            }
+            other => panic!("expected the state, got {:?}", other),
+        }
+
+        let write = r#"{"type": "write", "command": {"Video": {"Iso": 640}}}"#;
+        socket.send(Message::Text(write.to_string())).await.unwrap();
+        let iso = Command::Video(Video::Iso(640));
+        while receive(&mut socket).await
+            != (ServerMessage::Update {
+                command: iso.clone(),
+            })
+        {}
+
+        socket
+            .send(Message::Text("{\"type\": \"zoom\"}".to_string()))
+            .await
+            .unwrap();
+        loop {
+            match receive(&mut socket).await {
+                ServerMessage::Error { .. } => break,
+                ServerMessage::Update { .. } => continue,
+                other => panic!("expected an error, got {:?}", other),
+            }
+        }
+
+        socket
+            .send(Message::Text("{\"type\": \"state\"}".to_string()))
+            .await
+            .unwrap();
+        loop {
+            match receive(&mut socket).await {
+                ServerMessage::State { state, .. } => {
+                    assert_eq!(state.video.iso, Some(640));
+                    break;
+                }
+                ServerMessage::Update { .. } => continue,
+                other => panic!("expected the state, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_tcp_clients() {
+        let mut server = bridge().await;
+        let cached = server.camera().lock().await.commands().await;
+        let addr = server.listen_tcp("127.0.0.1:0").await.unwrap();
+        let (mut reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+
+        // Everything cached comes first
+        for _ in 0..cached.len() {
+            let packet = timeout(Duration::from_secs(5), read_packet(&mut reader))
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert!(cached.contains(&Command::from_raw(&packet).unwrap()));
+        }
+
+        let focus = Command::Lens(Lens::Focus(0.25));
+        let packet = RawCommand::to_raw(255, Operation::AssignValue, &focus);
+        writer.write_all(&encode_packet(&packet)).await.unwrap();
+
+        loop {
+            let packet = timeout(Duration::from_secs(5), read_packet(&mut reader))
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            if Command::from_raw(&packet).unwrap() == focus {
+                break;
+            }
+        }
+        assert_eq!(
+            server
+                .camera()
+                .lock()
+                .await
+                .get(crate::keys::lens::FOCUS)
+                .await,
+            Some(0.25)
+        );
+    }
+}
@@ -0,0 +1,441 @@
+use crate::characteristics::{self, INCOMING_CAMERA_CONTROL, OUTGOING_CAMERA_CONTROL};
+use crate::command::Command;
+use crate::error::BtSnoopError;
+use crate::rawcommand::RawCommand;
+use crate::recording::RecordedPacket;
+use crate::transport::Direction;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Read};
+use uuid::Uuid;
+
+const MAGIC: &[u8; 8] = b"btsnoop\0";
+
+/// HCI packets without a packet type indicator
+pub const DATALINK_HCI_UNENCAPSULATED: u32 = 1001;
+/// HCI packets prefixed with their H4 packet type, what Android writes
+pub const DATALINK_HCI_UART: u32 = 1002;
+
+// btsnoop timestamps count microseconds from midnight, January 1st 0 AD
+const UNIX_EPOCH_OFFSET_US: u64 = 0x00dc_ddb3_0f2f_8000;
+
+// H4 packet type, ACL header and the largest ACL payload
+const MAX_RECORD_LEN: u32 = 1 + 4 + 0xffff;
+
+const H4_ACL: u8 = 0x02;
+const FLAG_RECEIVED: u32 = 0x01;
+const FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+
+const L2CAP_ATT: u16 = 0x0004;
+
+const ATT_READ_BY_TYPE_RESPONSE: u8 = 0x09;
+const ATT_WRITE_REQUEST: u8 = 0x12;
+const ATT_PREPARE_WRITE_REQUEST: u8 = 0x16;
+const ATT_EXECUTE_WRITE_REQUEST: u8 = 0x18;
+const ATT_NOTIFICATION: u8 = 0x1B;
+const ATT_INDICATION: u8 = 0x1D;
+const ATT_WRITE_COMMAND: u8 = 0x52;
+
+/// A camera control message found in a btsnoop log
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnoopCommand {
+    /// Microseconds since the unix epoch
+    pub timestamp_us: u64,
+
+    /// Outgoing for writes from the logging device, Incoming for notifications
+    pub direction: Direction,
+
+    pub raw: RawCommand,
+    pub command: Command,
+}
+
+/// Pulls camera traffic out of a Bluetooth HCI log in btsnoop format
+///
+/// The ATT handles of the camera's characteristics are learned from GATT discovery in
+/// the log. If the log starts after discovery, as it does when the phone has cached the
+/// camera, supply them with with_handle().
+#[derive(Debug, Default)]
+pub struct BtSnoopParser {
+    handles: HashMap<u16, Uuid>,
+    discovered: HashMap<(u16, u16), Uuid>,
+
+    fragments: HashMap<(u16, Direction), Vec<u8>>,
+    prepared: HashMap<u16, Vec<(u16, u16, Vec<u8>)>>,
+
+    packets: Vec<RecordedPacket>,
+}
+
+impl BtSnoopParser {
+    pub fn new() -> BtSnoopParser {
+        BtSnoopParser::default()
+    }
+
+    /// Tells the parser which characteristic an ATT handle belongs to
+    ///
+    /// # Arguments
+    ///
+    /// * `handle` - u16 ATT value handle, as shown by Wireshark
+    /// * `characteristic` - Uuid like this: OUTGOING_CAMERA_CONTROL
+    pub fn with_handle(mut self, handle: u16, characteristic: Uuid) -> BtSnoopParser {
+        self.handles.insert(handle, characteristic);
+        self
+    }
+
+    /// Reads a btsnoop log and returns every write and notification on the camera's
+    /// characteristics
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - anything implementing Read, like a File
+    pub fn parse<R: Read>(mut self, mut reader: R) -> Result<Vec<RecordedPacket>, BtSnoopError> {
+        let mut header = [0u8; 16];
+        read_exact(&mut reader, &mut header)?.ok_or(BtSnoopError::NotBtSnoop)?;
+        if &header[0..8] != MAGIC {
+            return Err(BtSnoopError::NotBtSnoop);
+        }
+
+        let datalink = be_u32(&header[12..16]);
+        if datalink != DATALINK_HCI_UNENCAPSULATED && datalink != DATALINK_HCI_UART {
+            return Err(BtSnoopError::UnsupportedDatalink(datalink));
+        }
+
+        let mut record = [0u8; 24];
+        while read_exact(&mut reader, &mut record)?.is_some() {
+            let original = be_u32(&record[0..4]);
+            let included = be_u32(&record[4..8]);
+            if included > original || included > MAX_RECORD_LEN {
+                return Err(BtSnoopError::RecordTooLong(included));
+            }
+            let flags = be_u32(&record[8..12]);
+            let timestamp = u64::from_be_bytes(record[16..24].try_into().unwrap());
+
+            let mut data = vec![0u8; included as usize];
+            read_exact(&mut reader, &mut data)?.ok_or(BtSnoopError::Truncated)?;
+
+            let acl = match datalink {
+                DATALINK_HCI_UART if data.first() == Some(&H4_ACL) => &data[1..],
+                DATALINK_HCI_UNENCAPSULATED if flags & FLAG_COMMAND_OR_EVENT == 0 => &data[..],
+                _ => continue,
+            };
+
+            let direction = if flags & FLAG_RECEIVED != 0 {
+                Direction::Incoming
+            } else {
+                Direction::Outgoing
+            };
+
+            self.acl(
+                acl,
+                direction,
+                timestamp.saturating_sub(UNIX_EPOCH_OFFSET_US),
+            );
+        }
+
+        Ok(self.packets)
+    }
+
+    /// Reads a btsnoop log and decodes the camera control messages in it
+    ///
+    /// Messages that do not decode, such as ones for parameters missing from
+    /// PROTOCOL.json, are left out.
+    pub fn commands<R: Read>(self, reader: R) -> Result<Vec<SnoopCommand>, BtSnoopError> {
+        let mut commands = Vec::new();
+
+        for packet in self.parse(reader)? {
+            if packet.characteristic != OUTGOING_CAMERA_CONTROL
+                && packet.characteristic != INCOMING_CAMERA_CONTROL
+            {
+                continue;
+            }
+
+            for message in RawCommand::split(&packet.data) {
+                if let (Ok(raw), Ok(command)) =
+                    (RawCommand::from_raw(message), Command::from_raw(message))
+                {
+                    commands.push(SnoopCommand {
+                        timestamp_us: packet.timestamp_us,
+                        direction: packet.direction,
+                        raw,
+                        command,
+                    });
+                }
+            }
+        }
+
+        Ok(commands)
+    }
+
+    // Reassembles L2CAP frames split over several ACL packets, each direction of a
+    // connection carries frames of its own
+    fn acl(&mut self, data: &[u8], direction: Direction, timestamp_us: u64) {
+        if data.len() < 4 {
+            return;
+        }
+
+        let header = u16::from_le_bytes([data[0], data[1]]);
+        let connection = header & 0x0fff;
+        let continuation = (header >> 12) & 0x3 == 0x1;
+        let payload = &data[4..];
+
+        let frame = self.fragments.entry((connection, direction)).or_default();
+        if continuation {
+            if frame.is_empty() {
+                return;
+            }
+        } else {
+            frame.clear();
+        }
+        frame.extend_from_slice(payload);
+
+        if frame.len() < 4 {
+            return;
+        }
+
+        let length = u16::from_le_bytes([frame[0], frame[1]]) as usize;
+        if frame.len() < 4 + length {
+            return;
+        }
+
+        let frame = std::mem::take(frame);
+        let channel = u16::from_le_bytes([frame[2], frame[3]]);
+        if channel == L2CAP_ATT {
+            self.att(connection, &frame[4..4 + length], direction, timestamp_us);
+        }
+    }
+
+    fn att(&mut self, connection: u16, pdu: &[u8], direction: Direction, timestamp_us: u64) {
+        let opcode = match pdu.first() {
+            Some(v) => *v,
+            None => return,
+        };
+
+        match opcode {
+            ATT_READ_BY_TYPE_RESPONSE if pdu.len() > 2 => {
+                // Characteristic declarations with a 128-bit UUID: declaration handle,
+                // properties, value handle and the UUID in little endian
+                if pdu[1] != 21 {
+                    return;
+                }
+                for entry in pdu[2..].chunks_exact(21) {
+                    let handle = u16::from_le_bytes([entry[3], entry[4]]);
+                    let uuid =
+                        Uuid::from_u128(u128::from_le_bytes(entry[5..21].try_into().unwrap()));
+                    if characteristics::name(uuid).is_some() {
+                        self.discovered.insert((connection, handle), uuid);
+                    }
+                }
+            }
+            ATT_WRITE_REQUEST | ATT_WRITE_COMMAND | ATT_NOTIFICATION | ATT_INDICATION
+                if pdu.len() >= 3 =>
+            {
+                let handle = u16::from_le_bytes([pdu[1], pdu[2]]);
+                self.record(connection, handle, direction, timestamp_us, &pdu[3..]);
+            }
+            ATT_PREPARE_WRITE_REQUEST if pdu.len() >= 5 => {
+                let handle = u16::from_le_bytes([pdu[1], pdu[2]]);
+                let offset = u16::from_le_bytes([pdu[3], pdu[4]]);
+                self.prepared.entry(connection).or_default().push((
+                    handle,
+                    offset,
+                    pdu[5..].to_vec(),
+                ));
+            }
+            ATT_EXECUTE_WRITE_REQUEST if pdu.len() >= 2 => {
+                let prepared = self.prepared.remove(&connection).unwrap_or_default();
+                if pdu[1] != 0x01 {
+                    return;
+                }
+
+                // A long write, put the parts back together per handle
+                let mut writes: Vec<(u16, Vec<u8>)> = Vec::new();
+                for (handle, offset, part) in prepared {
+                    let i = match writes.iter().position(|(h, _)| *h == handle) {
+                        Some(i) => i,
+                        None => {
+                            writes.push((handle, Vec::new()));
+                            writes.len() - 1
+                        }
+                    };
+                    let value = &mut writes[i].1;
+                    let end = offset as usize + part.len();
+                    if value.len() < end {
+                        value.resize(end, 0);
+                    }
+                    value[offset as usize..end].copy_from_slice(&part);
+                }
+
+                for (handle, value) in writes {
+                    self.record(connection, handle, direction, timestamp_us, &value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn record(
+        &mut self,
+        connection: u16,
+        handle: u16,
+        direction: Direction,
+        timestamp_us: u64,
+        value: &[u8],
+    ) {
+        let characteristic = match self
+            .discovered
+            .get(&(connection, handle))
+            .or_else(|| self.handles.get(&handle))
+        {
+            Some(v) => *v,
+            None => return,
+        };
+
+        self.packets.push(RecordedPacket {
+            direction,
+            timestamp_us,
+            characteristic,
+            data: value.to_vec(),
+        });
+    }
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes(data.try_into().unwrap())
+}
+
+// Like read_exact but tells a clean end of file apart from a truncated read
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<Option<()>, BtSnoopError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(BtSnoopError::Truncated),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(Some(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(records: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let records: Vec<_> = records.iter().map(|(o, i, d)| (*o, *i, 0, *d)).collect();
+        flagged_log(&records)
+    }
+
+    fn flagged_log(records: &[(u32, u32, u32, &[u8])]) -> Vec<u8> {
+        let mut log = MAGIC.to_vec();
+        log.extend(1u32.to_be_bytes());
+        log.extend(DATALINK_HCI_UART.to_be_bytes());
+
+        for (original, included, flags, data) in records {
+            log.extend(original.to_be_bytes());
+            log.extend(included.to_be_bytes());
+            log.extend(flags.to_be_bytes());
+            log.extend(0u32.to_be_bytes());
+            log.extend(UNIX_EPOCH_OFFSET_US.to_be_bytes());
+            log.extend_from_slice(data);
+        }
+        log
+    }
+
+    // An L2CAP frame carrying an ATT PDU for a handle
+    fn l2cap(opcode: u8, handle: u16, value: &[u8]) -> Vec<u8> {
+        let mut att = vec![opcode];
+        att.extend(handle.to_le_bytes());
+        att.extend_from_slice(value);
+
+        let mut l2cap = (att.len() as u16).to_le_bytes().to_vec();
+        l2cap.extend(L2CAP_ATT.to_le_bytes());
+        l2cap.extend(att);
+        l2cap
+    }
+
+    // An H4 ACL packet on connection 0x040, starting a frame or continuing one
+    fn acl(continuation: bool, payload: &[u8]) -> Vec<u8> {
+        let flags = if continuation { 0x10 } else { 0x20 };
+        let mut acl = vec![H4_ACL, 0x40, flags];
+        acl.extend((payload.len() as u16).to_le_bytes());
+        acl.extend_from_slice(payload);
+        acl
+    }
+
+    // An H4 ACL packet carrying an ATT write command
+    fn write(handle: u16, value: &[u8]) -> Vec<u8> {
+        acl(false, &l2cap(ATT_WRITE_COMMAND, handle, value))
+    }
+
+    #[test]
+    fn reads_writes_to_known_handles() {
+        let data = write(0x0d, &[1, 2, 3]);
+        let len = data.len() as u32;
+
+        let packets = BtSnoopParser::new()
+            .with_handle(0x0d, OUTGOING_CAMERA_CONTROL)
+            .parse(&log(&[(len, len, &data)])[..])
+            .unwrap();
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].direction, Direction::Outgoing);
+        assert_eq!(packets[0].timestamp_us, 0);
+        assert_eq!(packets[0].data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let data = write(0x0d, &[1, 2, 3]);
+        let len = data.len() as u32;
+        let mut file = log(&[(len, len, &data)]);
+        file.truncate(file.len() - 2);
+
+        assert!(matches!(
+            BtSnoopParser::new().parse(&file[..]),
+            Err(BtSnoopError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_records() {
+        for (original, included) in [(u32::MAX, u32::MAX), (2, 3)] {
+            assert!(matches!(
+                BtSnoopParser::new().parse(&log(&[(original, included, &[0; 3])])[..]),
+                Err(BtSnoopError::RecordTooLong(v)) if v == included
+            ));
+        }
+    }
+
+    #[test]
+    fn reassembles_each_direction_on_its_own() {
+        let write = l2cap(ATT_WRITE_COMMAND, 0x0d, &[1, 2, 3, 4, 5, 6]);
+        let notification = l2cap(ATT_NOTIFICATION, 0x0f, &[7, 8, 9, 10, 11, 12]);
+
+        // Both frames split in two, the halves interleaved on one connection
+        let fragments = [
+            (0, acl(false, &write[..6])),
+            (FLAG_RECEIVED, acl(false, &notification[..5])),
+            (0, acl(true, &write[6..])),
+            (FLAG_RECEIVED, acl(true, &notification[5..])),
+        ];
+        let records: Vec<(u32, u32, u32, &[u8])> = fragments
+            .iter()
+            .map(|(flags, data)| (data.len() as u32, data.len() as u32, *flags, &data[..]))
+            .collect();
+
+        let packets = BtSnoopParser::new()
+            .with_handle(0x0d, OUTGOING_CAMERA_CONTROL)
+            .with_handle(0x0f, INCOMING_CAMERA_CONTROL)
+            .parse(&flagged_log(&records)[..])
+            .unwrap();
+
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].direction, Direction::Outgoing);
+        assert_eq!(packets[0].characteristic, OUTGOING_CAMERA_CONTROL);
+        assert_eq!(packets[0].data, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(packets[1].direction, Direction::Incoming);
+        assert_eq!(packets[1].characteristic, INCOMING_CAMERA_CONTROL);
+        assert_eq!(packets[1].data, vec![7, 8, 9, 10, 11, 12]);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::command::Command;
+use crate::key::{CommandKey, Key};
+use crate::state::CameraState;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The last value received for a parameter and when it arrived
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub command: Command,
+    pub updated: Instant,
+}
+
+impl CacheEntry {
+    /// How long ago the value was received
+    pub fn age(&self) -> Duration {
+        self.updated.elapsed()
+    }
+}
+
+/// Latest known value of every parameter the camera has reported
+///
+/// Keeps a CameraState in step with the entries so both can be read under one lock.
+#[derive(Debug, Clone, Default)]
+pub struct CommandCache {
+    entries: HashMap<CommandKey, CacheEntry>,
+    state: CameraState,
+}
+
+impl CommandCache {
+    pub fn new() -> Self {
+        CommandCache::default()
+    }
+
+    /// Stores the command, replacing any earlier value for the same parameter
+    pub fn insert(&mut self, command: Command) {
+        self.state.apply(&command);
+        self.entries.insert(
+            command.key(),
+            CacheEntry {
+                command,
+                updated: Instant::now(),
+            },
+        );
+    }
+
+    pub fn entry(&self, key: impl Into<CommandKey>) -> Option<&CacheEntry> {
+        self.entries.get(&key.into())
+    }
+
+    pub fn command(&self, key: impl Into<CommandKey>) -> Option<&Command> {
+        self.entry(key).map(|e| &e.command)
+    }
+
+    /// Typed access to a cached value
+    pub fn value<T>(&self, key: Key<T>) -> Option<T> {
+        self.command(key).and_then(|c| key.extract(c))
+    }
+
+    pub fn state(&self) -> &CameraState {
+        &self.state
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&CommandKey, &CacheEntry)> {
+        self.entries.iter()
+    }
+}
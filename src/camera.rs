@@ -0,0 +1,278 @@
+use crate::cache::{CacheEntry, CommandCache};
+use crate::command::Command;
+use crate::error::CameraControlError;
+use crate::key::{CommandKey, Key};
+use crate::keys;
+use crate::rawcommand::{Operation, RawCommand};
+use crate::state::CameraState;
+use crate::subscription::{CommandStream, Subscriptions, ValueStream};
+use crate::transport::{CameraTransport, EventStream, TransportEvent};
+use futures::stream::StreamExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::RwLock;
+use tokio::time;
+
+/// A camera reachable over any CameraTransport
+///
+/// Takes care of encoding commands, caching what the camera reports and handing out
+/// updates, the transport only moves packets.
+#[derive(Debug)]
+pub struct Camera<T: CameraTransport> {
+    transport: T,
+
+    connected: Arc<AtomicBool>,
+    updates: Sender<Command>,
+    cache: Arc<RwLock<CommandCache>>,
+    subscriptions: Arc<Subscriptions>,
+}
+
+impl<T: CameraTransport> Camera<T> {
+    /// Wraps a transport, nothing is sent until connect() is called
+    ///
+    /// # Arguments
+    ///
+    /// * `transport` - the CameraTransport to talk to the camera through
+    pub fn with_transport(transport: T) -> Camera<T> {
+        Camera {
+            transport,
+
+            connected: Arc::new(AtomicBool::new(false)),
+            updates: broadcast::channel(16).0,
+            cache: Arc::new(RwLock::new(CommandCache::new())),
+            subscriptions: Arc::new(Subscriptions::new()),
+        }
+    }
+
+    /// Tries to connect to the camera, waiting as long as supplied timeout specifies
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - std::Duration of how long to wait before giving up
+    pub async fn connect(&mut self, timeout: Duration) -> Result<(), T::Error> {
+        self.transport.connect(timeout).await?;
+
+        let stream = self.transport.events().await?;
+        self.connected.store(true, Ordering::SeqCst);
+
+        let cache = self.cache.clone();
+        let updates = self.updates.clone();
+        let subscriptions = self.subscriptions.clone();
+        let connected = self.connected.clone();
+        tokio::spawn(async move {
+            Camera::<T>::handle_incoming(cache, updates, subscriptions, connected, stream).await;
+        });
+
+        Ok(())
+    }
+
+    /// Disconnects from the camera
+    pub async fn disconnect(&mut self) -> Result<(), T::Error> {
+        self.transport.disconnect().await?;
+        self.connected.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Returns whether the transport last reported being connected
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Writes a command to the camera
+    ///
+    /// Commands the camera can not accept, such as over-long metadata strings, return an
+    /// error instead of being sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - u8 camera id, 255 to broadcast to all cameras
+    /// * `operation` - Operation to perform with the value
+    /// * `command` - Command like this: Command::Video(Video::Iso(640))
+    pub async fn write(
+        &mut self,
+        destination: u8,
+        operation: Operation,
+        command: Command,
+    ) -> Result<(), T::Error> {
+        let packet = RawCommand::to_raw_checked(destination, operation, &command)?;
+        self.transport.send(&packet).await
+    }
+
+    async fn handle_incoming(
+        cache: Arc<RwLock<CommandCache>>,
+        updates: Sender<Command>,
+        subscriptions: Arc<Subscriptions>,
+        connected: Arc<AtomicBool>,
+        mut stream: EventStream,
+    ) {
+        while let Some(event) = stream.next().await {
+            match event {
+                TransportEvent::Packet(data) => {
+                    if let Ok(v) = Command::from_raw(&data) {
+                        let mut cache = cache.write().await;
+                        cache.insert(v.clone());
+                        subscriptions.publish(&v);
+                        drop(cache);
+
+                        let _ = updates.send(v);
+                    }
+                }
+                TransportEvent::Connected => connected.store(true, Ordering::SeqCst),
+                TransportEvent::Disconnected => connected.store(false, Ordering::SeqCst),
+                _ => {}
+            }
+        }
+
+        connected.store(false, Ordering::SeqCst);
+    }
+
+    /// Waits for the settings the camera sends right after connecting
+    ///
+    /// Resolves once the camera has reported something and then been quiet for `settle`.
+    ///
+    /// # Arguments
+    ///
+    /// * `settle` - std::Duration of silence after which the initial burst is considered done
+    /// * `timeout` - std::Duration of how long to wait before giving up
+    pub async fn wait_synced(&self, settle: Duration, timeout: Duration) -> Result<(), T::Error> {
+        let mut updates = self.updates.subscribe();
+
+        time::timeout(timeout, async {
+            loop {
+                match time::timeout(settle, updates.recv()).await {
+                    Ok(_) => {}
+                    Err(_) => {
+                        if !self.cache.read().await.is_empty() {
+                            return;
+                        }
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|_| CameraControlError::SyncTimeout.into())
+    }
+
+    /// Waits until the camera has reported every one of the supplied parameters
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - &[CommandKey] like this: &[keys::video::ISO.key(), keys::lens::FOCUS.key()]
+    /// * `timeout` - std::Duration of how long to wait before giving up
+    pub async fn wait_for(&self, keys: &[CommandKey], timeout: Duration) -> Result<(), T::Error> {
+        let mut updates = self.updates.subscribe();
+
+        time::timeout(timeout, async {
+            loop {
+                {
+                    let cache = self.cache.read().await;
+                    if keys.iter().all(|k| cache.entry(*k).is_some()) {
+                        return;
+                    }
+                }
+
+                let _ = updates.recv().await;
+            }
+        })
+        .await
+        .map_err(|_| CameraControlError::SyncTimeout.into())
+    }
+
+    /// Gives you the latest cached value for the supplied key
+    /// If the camera has not reported the parameter yet, returns None
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key like this: keys::video::ISO
+    pub async fn get<V>(&self, key: Key<V>) -> Option<V> {
+        self.cache.read().await.value(key)
+    }
+
+    /// Gives you the latest cached command for the supplied key, along with when it arrived
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - CommandKey or Key like this: keys::metadata::LENS_DISTANCE
+    pub async fn get_entry(&self, key: impl Into<CommandKey>) -> Option<CacheEntry> {
+        self.cache.read().await.entry(key).cloned()
+    }
+
+    /// Gives you the latest cached version of the supplied normalized_name
+    ///
+    /// # Arguments
+    ///
+    /// * `normalized_name` - &str like this: metadata_lens_distance
+    pub async fn get_normalized(&self, normalized_name: &str) -> Option<Command> {
+        let key = keys::from_normalized_name(normalized_name)?;
+        self.cache.read().await.command(key).cloned()
+    }
+
+    /// Gives you the latest cached command for every parameter the camera has reported
+    pub async fn commands(&self) -> Vec<Command> {
+        self.cache
+            .read()
+            .await
+            .iter()
+            .map(|(_, e)| e.command.clone())
+            .collect()
+    }
+
+    /// Gives you a consistent snapshot of every parameter the camera has reported
+    pub async fn state(&self) -> CameraState {
+        self.cache.read().await.state().clone()
+    }
+
+    /// Returns a channel which allows you to get updates from the camera
+    pub async fn updates(&mut self) -> Receiver<Command> {
+        self.updates.subscribe()
+    }
+
+    /// Returns a stream of typed values for a single parameter
+    ///
+    /// Starts with the cached value if there is one. A slow consumer only ever misses
+    /// intermediate values, it always gets the latest one.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key like this: keys::video::ISO
+    pub async fn subscribe<V: Send + 'static>(&self, key: Key<V>) -> ValueStream<V> {
+        let cache = self.cache.read().await;
+        self.subscriptions.stream(key, cache.command(key).cloned())
+    }
+
+    /// Returns a stream of commands for a set of parameters, latest value per parameter
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - &[CommandKey] like this: keys::lens::KEYS
+    pub async fn subscribe_keys(&self, keys: &[CommandKey]) -> CommandStream {
+        let cache = self.cache.read().await;
+        self.subscriptions
+            .stream_keys(keys, |k| cache.command(k).cloned())
+    }
+
+    /// Returns a stream of commands for every parameter in a category
+    ///
+    /// # Arguments
+    ///
+    /// * `category` - u8 category id like this: keys::lens::CATEGORY
+    pub async fn subscribe_group(&self, category: u8) -> CommandStream {
+        self.subscribe_keys(keys::category_keys(category)).await
+    }
+
+    /// Gives you the transport the camera talks through
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// The cache itself, for front-ends that look values up without holding the camera
+    pub(crate) fn cache(&self) -> Arc<RwLock<CommandCache>> {
+        self.cache.clone()
+    }
+}
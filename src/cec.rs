@@ -0,0 +1,702 @@
+use crate::camera::Camera;
+use crate::command::Command;
+use crate::defs::CecParameter;
+use crate::error::{CecError, TransportError};
+use crate::info::ParameterInfo;
+use crate::key::CommandKey;
+use crate::rawcommand::{CommandError, Operation, RawCommand};
+use crate::transport::{CameraTransport, EventStream, TransportEvent};
+use async_trait::async_trait;
+use futures::stream;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+
+/// CEC opcode of <Vendor Command>, every camera control message rides in one
+pub const VENDOR_COMMAND: u8 = 0x89;
+
+/// Most parameter bytes a CEC frame can carry
+pub const MAX_PARAMETERS: usize = 14;
+
+/// Logical address the camera is expected at, Recording Device 1
+pub const CAMERA_ADDRESS: u8 = 0x1;
+
+/// Logical address messages are sent from, Playback Device 1
+pub const CONTROLLER_ADDRESS: u8 = 0x4;
+
+/// A frame on the CEC bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CecFrame {
+    pub initiator: u8,
+    pub destination: u8,
+    pub opcode: u8,
+    pub parameters: Vec<u8>,
+}
+
+/// A Blackmagic vendor message, the parameters of a <Vendor Command> frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CecMessage {
+    pub opcode: u8,
+    pub data: Vec<u8>,
+}
+
+impl CecMessage {
+    /// Wraps the message in a <Vendor Command> frame
+    ///
+    /// # Arguments
+    ///
+    /// * `initiator` - u8 logical address of the sender
+    /// * `destination` - u8 logical address of the receiver
+    pub fn to_frame(&self, initiator: u8, destination: u8) -> CecFrame {
+        let mut parameters = Vec::with_capacity(self.data.len() + 1);
+        parameters.push(self.opcode);
+        parameters.extend_from_slice(&self.data);
+
+        CecFrame {
+            initiator,
+            destination,
+            opcode: VENDOR_COMMAND,
+            parameters,
+        }
+    }
+
+    /// Unwraps a <Vendor Command> frame, None for any other frame
+    pub fn from_frame(frame: &CecFrame) -> Option<CecMessage> {
+        match (frame.opcode, frame.parameters.split_first()) {
+            (VENDOR_COMMAND, Some((opcode, data))) => Some(CecMessage {
+                opcode: *opcode,
+                data: data.to_vec(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes a command as the vendor messages that carry it
+///
+/// # Arguments
+///
+/// * `command` - Command like this: Command::Lens(Lens::Focus(0.5))
+pub fn to_cec(command: &Command) -> Result<Vec<CecMessage>, CecError> {
+    let key = CommandKey::from(command);
+    let data = command.to_bytes();
+    let data_type = command.raw_type();
+    let size = element_size(data_type).unwrap_or(1);
+
+    let messages = CecParameter::by_key(key)
+        .map(|p| {
+            let start = p.index * size;
+            let end = start + p.count * size;
+            if end > data.len() {
+                return Err(CommandError::NotEnoughBytes.into());
+            }
+
+            let mut message = CecMessage {
+                opcode: p.opcode,
+                data: Vec::new(),
+            };
+            for element in data[start..end].chunks_exact(size) {
+                message
+                    .data
+                    .extend(rescale(element, data_type, p.data_type, |v| {
+                        v * p.scale + p.offset
+                    }));
+            }
+
+            if message.data.len() + 1 > MAX_PARAMETERS {
+                return Err(CecError::TooLong(message.data.len() + 1, MAX_PARAMETERS));
+            }
+            Ok(message)
+        })
+        .collect::<Result<Vec<CecMessage>, CecError>>()?;
+
+    if messages.is_empty() {
+        return Err(CecError::Unsupported(command.normalized_name().1));
+    }
+
+    Ok(messages)
+}
+
+// Bytes per element of a type, None for strings and unknown types
+fn element_size(data_type: u8) -> Option<usize> {
+    Some(match data_type {
+        0 | 1 => 1,
+        2 | 128 => 2,
+        3 => 4,
+        4 => 8,
+        _ => return None,
+    })
+}
+
+fn raw_type(key: CommandKey) -> Option<u8> {
+    Some(match ParameterInfo::lookup(key)?.data_type {
+        "void" | "boolean" => 0,
+        "int8" => 1,
+        "int16" => 2,
+        "int32" => 3,
+        "int64" => 4,
+        "fixed16" => 128,
+        _ => return None,
+    })
+}
+
+// Converts an element from one type to another through `f`, untouched when nothing changes
+fn rescale(bytes: &[u8], from: u8, to: u8, f: impl Fn(f64) -> f64) -> Vec<u8> {
+    if from == to && f(0.0) == 0.0 && f(1.0) == 1.0 {
+        return bytes.to_vec();
+    }
+
+    let v = match from {
+        0 | 1 => bytes[0] as i8 as f64,
+        2 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        3 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        4 => i64::from_le_bytes(bytes[..8].try_into().unwrap_or_default()) as f64,
+        128 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64 / 2048.0,
+        _ => 0.0,
+    };
+
+    let v = f(v);
+    match to {
+        0 | 1 => vec![v.round() as i8 as u8],
+        2 => (v.round() as i16).to_le_bytes().to_vec(),
+        3 => (v.round() as i32).to_le_bytes().to_vec(),
+        4 => (v.round() as i64).to_le_bytes().to_vec(),
+        128 => ((v * 2048.0).round() as i16).to_le_bytes().to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// Turns vendor messages from the camera back into commands
+///
+/// Parameters split over several opcodes, like hue and saturation, are put back together
+/// from the last value of each part.
+#[derive(Debug, Default)]
+pub struct CecDecoder {
+    values: HashMap<CommandKey, Vec<u8>>,
+}
+
+impl CecDecoder {
+    pub fn new() -> CecDecoder {
+        CecDecoder::default()
+    }
+
+    /// Decodes a message, None for opcodes that do not map onto a parameter
+    pub fn decode(&mut self, message: &CecMessage) -> Result<Option<Command>, CecError> {
+        let p = match CecParameter::by_opcode(message.opcode) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        let data_type = match raw_type(p.key) {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let (size, wire_size) = match (element_size(data_type), element_size(p.data_type)) {
+            (Some(s), Some(w)) => (s, w),
+            _ => return Ok(None),
+        };
+        if message.data.len() < p.count * wire_size {
+            return Err(CommandError::NotEnoughBytes.into());
+        }
+
+        let elements = ParameterInfo::lookup(p.key)
+            .map(|i| i.index.len())
+            .unwrap_or(0)
+            .max(1);
+        let value = self
+            .values
+            .entry(p.key)
+            .or_insert_with(|| vec![0; elements * size]);
+
+        for (i, element) in message.data[..p.count * wire_size]
+            .chunks_exact(wire_size)
+            .enumerate()
+        {
+            let start = (p.index + i) * size;
+            value[start..start + size].copy_from_slice(&rescale(
+                element,
+                p.data_type,
+                data_type,
+                |v| (v - p.offset) / p.scale,
+            ));
+        }
+
+        let raw = RawCommand {
+            destination_device: 255,
+            command_id: 0,
+            category: p.key.category,
+            parameter: p.key.parameter,
+            data_type,
+            operation: Operation::AssignValue.id(),
+            data: value.clone(),
+        };
+
+        Ok(Some(Command::from_raw(&raw.to_bytes())?))
+    }
+}
+
+/// Something that puts frames on a CEC bus, like a Pulse-Eight adapter through libcec
+pub trait CecAdapter: Send + Sync + 'static {
+    /// Opens the adapter, frames read off the bus go to `incoming` until it is closed
+    fn open(&mut self, incoming: UnboundedSender<CecFrame>) -> Result<(), CecError>;
+
+    fn close(&mut self);
+
+    fn transmit(&mut self, frame: &CecFrame) -> Result<(), CecError>;
+}
+
+/// A Blackmagic camera controlled over HDMI CEC
+pub type CecCamera<A> = Camera<CecTransport<A>>;
+
+impl<A: CecAdapter> Camera<CecTransport<A>> {
+    /// Takes a CEC adapter and returns a new CecCamera talking to the default addresses
+    ///
+    /// # Arguments
+    ///
+    /// * `adapter` - anything implementing CecAdapter, such as LibCecAdapter or MockCecBus
+    pub fn new(adapter: A) -> CecCamera<A> {
+        Camera::with_transport(CecTransport::new(adapter))
+    }
+}
+
+/// Carries camera control over CEC vendor messages
+///
+/// Only the parameters in CEC_PROTOCOL.json can be sent and only as absolute values, the camera's
+/// own vendor messages come back as incoming commands.
+#[derive(Debug)]
+pub struct CecTransport<A> {
+    adapter: A,
+    camera: u8,
+    controller: u8,
+
+    open: bool,
+    incoming: Option<mpsc::UnboundedReceiver<TransportEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl<A: CecAdapter> CecTransport<A> {
+    /// Takes a CEC adapter and returns a new CecTransport
+    ///
+    /// # Arguments
+    ///
+    /// * `adapter` - anything implementing CecAdapter
+    pub fn new(adapter: A) -> CecTransport<A> {
+        CecTransport {
+            adapter,
+            camera: CAMERA_ADDRESS,
+            controller: CONTROLLER_ADDRESS,
+
+            open: false,
+            incoming: None,
+            task: None,
+        }
+    }
+
+    /// Sets the logical addresses of the camera and of us
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - u8 logical address of the camera
+    /// * `controller` - u8 logical address messages are sent from
+    pub fn with_addresses(mut self, camera: u8, controller: u8) -> Self {
+        self.camera = camera;
+        self.controller = controller;
+        self
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.adapter
+    }
+
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.adapter
+    }
+}
+
+#[async_trait]
+impl<A: CecAdapter> CameraTransport for CecTransport<A> {
+    type Error = TransportError;
+
+    async fn connect(&mut self, _timeout: Duration) -> Result<(), TransportError> {
+        self.disconnect().await?;
+
+        let (frames_tx, mut frames) = mpsc::unbounded_channel();
+        self.adapter.open(frames_tx)?;
+        self.open = true;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let _ = tx.send(TransportEvent::Connected);
+
+        let camera = self.camera;
+        self.task = Some(tokio::spawn(async move {
+            let mut decoder = CecDecoder::new();
+
+            while let Some(frame) = frames.recv().await {
+                if frame.initiator != camera {
+                    continue;
+                }
+                let command = match CecMessage::from_frame(&frame).map(|m| decoder.decode(&m)) {
+                    Some(Ok(Some(c))) => c,
+                    _ => continue,
+                };
+
+                let packet = RawCommand::to_raw(255, Operation::AssignValue, &command);
+                if tx.send(TransportEvent::Packet(packet)).is_err() {
+                    return;
+                }
+            }
+
+            let _ = tx.send(TransportEvent::Disconnected);
+        }));
+        self.incoming = Some(rx);
+
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if self.open {
+            self.adapter.close();
+            self.open = false;
+        }
+        self.incoming = None;
+        Ok(())
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), TransportError> {
+        if !self.open {
+            return Err(TransportError::NotConnected);
+        }
+
+        let raw = RawCommand::from_raw(packet)?;
+        if raw.operation != Operation::AssignValue.id() {
+            return Err(TransportError::Unsupported(
+                "offsetting values over CEC".to_string(),
+            ));
+        }
+
+        for message in to_cec(&Command::from_raw(packet)?)? {
+            self.adapter
+                .transmit(&message.to_frame(self.controller, self.camera))?;
+        }
+
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, TransportError> {
+        let mut rx = self.incoming.take().ok_or(TransportError::NotConnected)?;
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+}
+
+/// An in-process CEC bus for testing without an adapter
+///
+/// Keeps every frame transmitted on it and lets frames be injected as if the camera sent
+/// them. Clones share the same bus, so keep one to inspect it after handing the other to
+/// a CecCamera.
+#[derive(Debug, Clone, Default)]
+pub struct MockCecBus {
+    inner: Arc<Mutex<MockBus>>,
+}
+
+#[derive(Debug, Default)]
+struct MockBus {
+    transmitted: Vec<CecFrame>,
+    incoming: Option<UnboundedSender<CecFrame>>,
+    echo: bool,
+}
+
+impl MockCecBus {
+    pub fn new() -> MockCecBus {
+        MockCecBus::default()
+    }
+
+    /// Answers every vendor message with the same message from the camera, the way a
+    /// camera confirms a change
+    pub fn with_echo(self) -> Self {
+        self.inner.lock().unwrap().echo = true;
+        self
+    }
+
+    /// Returns every frame transmitted so far
+    pub fn transmitted(&self) -> Vec<CecFrame> {
+        self.inner.lock().unwrap().transmitted.clone()
+    }
+
+    /// Puts a frame on the bus, returns false if the adapter is not open
+    pub fn inject(&self, frame: CecFrame) -> bool {
+        match &self.inner.lock().unwrap().incoming {
+            Some(tx) => tx.send(frame).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl CecAdapter for MockCecBus {
+    fn open(&mut self, incoming: UnboundedSender<CecFrame>) -> Result<(), CecError> {
+        self.inner.lock().unwrap().incoming = Some(incoming);
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.inner.lock().unwrap().incoming = None;
+    }
+
+    fn transmit(&mut self, frame: &CecFrame) -> Result<(), CecError> {
+        let mut bus = self.inner.lock().unwrap();
+        bus.transmitted.push(frame.clone());
+
+        if bus.echo && frame.opcode == VENDOR_COMMAND {
+            if let Some(tx) = &bus.incoming {
+                let _ = tx.send(CecFrame {
+                    initiator: frame.destination,
+                    destination: frame.initiator,
+                    opcode: frame.opcode,
+                    parameters: frame.parameters.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+type Transmit = (CecFrame, std::sync::mpsc::Sender<Result<(), CecError>>);
+
+/// A USB CEC adapter, such as a Pulse-Eight, driven through libcec
+///
+/// libcec connections can not move between threads, so the connection lives on a thread
+/// of its own for as long as the adapter is open.
+#[derive(Debug)]
+pub struct LibCecAdapter {
+    port: String,
+    device_name: String,
+    worker: Option<std::sync::mpsc::Sender<Transmit>>,
+}
+
+impl LibCecAdapter {
+    /// Takes the port of the adapter and returns a new LibCecAdapter
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - &str port of the adapter like this: "/dev/ttyACM0"
+    pub fn new(port: &str) -> LibCecAdapter {
+        LibCecAdapter {
+            port: port.to_string(),
+            device_name: "Camera Control".to_string(),
+            worker: None,
+        }
+    }
+
+    /// Sets the OSD name other devices on the bus see us as
+    pub fn with_device_name(mut self, name: &str) -> Self {
+        self.device_name = name.to_string();
+        self
+    }
+}
+
+impl CecAdapter for LibCecAdapter {
+    fn open(&mut self, incoming: UnboundedSender<CecFrame>) -> Result<(), CecError> {
+        use cec_rs::{CecConnectionCfgBuilder, CecDeviceType, CecDeviceTypeVec};
+
+        self.close();
+
+        let config = CecConnectionCfgBuilder::default()
+            .port(self.port.clone())
+            .device_name(self.device_name.clone())
+            .device_types(CecDeviceTypeVec::new(CecDeviceType::PlaybackDevice))
+            .command_received_callback(Box::new(move |c: cec_rs::CecCommand| {
+                let _ = incoming.send(CecFrame {
+                    initiator: i32::from(c.initiator) as u8,
+                    destination: i32::from(c.destination) as u8,
+                    opcode: u32::from(c.opcode) as u8,
+                    parameters: c.parameters.0.to_vec(),
+                });
+            }))
+            .build()
+            .map_err(|e| CecError::AdapterOpenFailed(e.to_string()))?;
+
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let (worker, requests) = std::sync::mpsc::channel::<Transmit>();
+
+        std::thread::spawn(move || {
+            let connection = match config.open() {
+                Ok(c) => c,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(CecError::AdapterOpenFailed(format!("{:?}", e))));
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+
+            // Runs until the adapter is closed, dropping the connection closes libcec
+            for (frame, reply) in requests {
+                let _ = reply.send(transmit(&connection, &frame));
+            }
+        });
+
+        ready_rx
+            .recv()
+            .map_err(|_| CecError::AdapterOpenFailed("libcec thread exited".to_string()))??;
+        self.worker = Some(worker);
+
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.worker = None;
+    }
+
+    fn transmit(&mut self, frame: &CecFrame) -> Result<(), CecError> {
+        let worker = self.worker.as_ref().ok_or(CecError::TransmitFailed)?;
+
+        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+        worker
+            .send((frame.clone(), reply_tx))
+            .map_err(|_| CecError::TransmitFailed)?;
+        reply_rx.recv().map_err(|_| CecError::TransmitFailed)?
+    }
+}
+
+fn transmit(connection: &cec_rs::CecConnection, frame: &CecFrame) -> Result<(), CecError> {
+    use cec_rs::{CecCommand, CecDatapacket, CecLogicalAddress, CecOpcode};
+    use std::convert::TryFrom;
+
+    if frame.parameters.len() > MAX_PARAMETERS {
+        return Err(CecError::TooLong(frame.parameters.len(), MAX_PARAMETERS));
+    }
+
+    let address =
+        |a: u8| CecLogicalAddress::try_from(a as i32).map_err(|_| CecError::TransmitFailed);
+    let command = CecCommand {
+        initiator: address(frame.initiator)?,
+        destination: address(frame.destination)?,
+        ack: false,
+        eom: true,
+        opcode: CecOpcode::try_from(frame.opcode as u32).map_err(|_| CecError::TransmitFailed)?,
+        parameters: CecDatapacket(frame.parameters.iter().copied().collect()),
+        opcode_set: true,
+        transmit_timeout: Duration::from_secs(1),
+    };
+
+    connection
+        .transmit(command)
+        .map_err(|_| CecError::TransmitFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{ColorCorrection, Lens};
+    use futures::StreamExt;
+
+    fn vendor(initiator: u8, destination: u8, parameters: &[u8]) -> CecFrame {
+        CecFrame {
+            initiator,
+            destination,
+            opcode: VENDOR_COMMAND,
+            parameters: parameters.to_vec(),
+        }
+    }
+
+    async fn connected(bus: &MockCecBus) -> (CecTransport<MockCecBus>, EventStream) {
+        let mut transport = CecTransport::new(bus.clone());
+        transport.connect(Duration::from_secs(1)).await.unwrap();
+        let mut events = transport.events().await.unwrap();
+        assert_eq!(events.next().await, Some(TransportEvent::Connected));
+        (transport, events)
+    }
+
+    #[tokio::test]
+    async fn sends_vendor_commands() {
+        let bus = MockCecBus::new();
+        let (mut transport, _events) = connected(&bus).await;
+
+        let focus = Command::Lens(Lens::Focus(0.5));
+        transport
+            .send(&RawCommand::to_raw(1, Operation::AssignValue, &focus))
+            .await
+            .unwrap();
+
+        // Hue and saturation go out as two messages
+        let color = Command::ColorCorrection(ColorCorrection::ColorAdjust(vec![0.25, 1.0]));
+        transport
+            .send(&RawCommand::to_raw(1, Operation::AssignValue, &color))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            bus.transmitted(),
+            vec![
+                vendor(CONTROLLER_ADDRESS, CAMERA_ADDRESS, &[65, 0x00, 0x04]),
+                vendor(CONTROLLER_ADDRESS, CAMERA_ADDRESS, &[29, 0x00, 0x02]),
+                vendor(CONTROLLER_ADDRESS, CAMERA_ADDRESS, &[30, 0x00, 0x08]),
+            ]
+        );
+
+        let offset = RawCommand::to_raw(1, Operation::OffsetValue, &focus);
+        assert!(matches!(
+            transport.send(&offset).await,
+            Err(TransportError::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn decodes_replies_from_the_camera() {
+        let bus = MockCecBus::new();
+        let (_transport, mut events) = connected(&bus).await;
+
+        // Only vendor commands from the camera count
+        assert!(bus.inject(vendor(3, CONTROLLER_ADDRESS, &[65, 0x00, 0x04])));
+        assert!(bus.inject(CecFrame {
+            opcode: 0x36,
+            ..vendor(CAMERA_ADDRESS, CONTROLLER_ADDRESS, &[])
+        }));
+        assert!(bus.inject(vendor(
+            CAMERA_ADDRESS,
+            CONTROLLER_ADDRESS,
+            &[65, 0x00, 0x02]
+        )));
+        assert!(bus.inject(vendor(
+            CAMERA_ADDRESS,
+            CONTROLLER_ADDRESS,
+            &[30, 0x00, 0x08]
+        )));
+
+        let mut commands = Vec::new();
+        while commands.len() < 2 {
+            match events.next().await {
+                Some(TransportEvent::Packet(p)) => commands.push(Command::from_raw(&p).unwrap()),
+                other => panic!("expected a packet, got {:?}", other),
+            }
+        }
+
+        assert_eq!(
+            commands,
+            vec![
+                Command::Lens(Lens::Focus(0.25)),
+                Command::ColorCorrection(ColorCorrection::ColorAdjust(vec![0.0, 1.0])),
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let message = CecMessage {
+            opcode: 65,
+            data: vec![0x00, 0x04],
+        };
+        let frame = message.to_frame(CONTROLLER_ADDRESS, CAMERA_ADDRESS);
+
+        assert_eq!(frame.parameters, vec![65, 0x00, 0x04]);
+        assert_eq!(CecMessage::from_frame(&frame), Some(message));
+        assert_eq!(
+            CecMessage::from_frame(&vendor(CAMERA_ADDRESS, CONTROLLER_ADDRESS, &[])),
+            None
+        );
+    }
+}
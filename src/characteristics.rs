@@ -0,0 +1,27 @@
+use uuid::Uuid;
+
+pub const CAMERA_SERVICE: Uuid = Uuid::from_u128(54650678423016196498641639054569411539);
+pub const CAMERA_MANUFACTURER: Uuid = Uuid::from_u128(855109558092022082745622393992443);
+pub const CAMERA_MODEL: Uuid = Uuid::from_u128(854713417279450761057654674240763);
+pub const OUTGOING_CAMERA_CONTROL: Uuid = Uuid::from_u128(124715205548830368390231916378743955899);
+pub const INCOMING_CAMERA_CONTROL: Uuid = Uuid::from_u128(245101749559754194128926468485788547033);
+pub const TIMECODE: Uuid = Uuid::from_u128(145629020620256484157652687441451644616);
+pub const CAMERA_STATUS: Uuid = Uuid::from_u128(170018700332869099062316608707586904505);
+pub const DEVICE_NAME: Uuid = Uuid::from_u128(339846463932956345205123112215954503836);
+pub const PROTOCOL_VERSION: Uuid = Uuid::from_u128(190244785298557795456958317949635929862);
+
+/// Returns a short name for one of the camera's characteristics
+pub fn name(characteristic: Uuid) -> Option<&'static str> {
+    Some(match characteristic {
+        CAMERA_SERVICE => "camera_service",
+        CAMERA_MANUFACTURER => "camera_manufacturer",
+        CAMERA_MODEL => "camera_model",
+        OUTGOING_CAMERA_CONTROL => "outgoing_camera_control",
+        INCOMING_CAMERA_CONTROL => "incoming_camera_control",
+        TIMECODE => "timecode",
+        CAMERA_STATUS => "camera_status",
+        DEVICE_NAME => "device_name",
+        PROTOCOL_VERSION => "protocol_version",
+        _ => return None,
+    })
+}
@@ -0,0 +1,43 @@
+pub use crate::cec_parameters::CEC_PARAMETERS;
+use crate::key::CommandKey;
+
+/// A vendor opcode Blackmagic cameras understand over CEC, as described in CEC_PROTOCOL.json
+///
+/// Each opcode carries some or all of the elements of a camera control parameter.
+/// Parameters such as color adjust are split over more than one opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CecParameter {
+    pub opcode: u8,
+    pub name: &'static str,
+    pub key: CommandKey,
+
+    /// First element of the value the opcode carries
+    pub index: usize,
+
+    /// How many elements the opcode carries, 0 for triggers
+    pub count: usize,
+
+    /// Type id each element is sent as, same ids as RawCommand uses
+    pub data_type: u8,
+
+    /// The value on the wire is the camera control value times scale plus offset
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl CecParameter {
+    /// Looks up an opcode, None if it is not one we know
+    pub fn by_opcode(opcode: u8) -> Option<&'static CecParameter> {
+        CEC_PARAMETERS.iter().find(|p| p.opcode == opcode)
+    }
+
+    /// Returns the opcodes that carry a parameter, empty if it can not be sent over CEC
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - CommandKey or Key like this: keys::lens::FOCUS
+    pub fn by_key(key: impl Into<CommandKey>) -> impl Iterator<Item = &'static CecParameter> {
+        let key = key.into();
+        CEC_PARAMETERS.iter().filter(move |p| p.key == key)
+    }
+}
@@ -0,0 +1,444 @@
+use crate::info::{CategoryInfo, ParameterInfo};
+use crate::key::CommandKey;
+use crate::rawcommand::{Operation, ParamType};
+use serde::Serialize;
+use std::fmt;
+
+/// A range of bytes in a packet and what they mean
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Field {
+    pub name: String,
+
+    /// Offset of the first byte in the packet
+    pub offset: usize,
+
+    #[serde(with = "crate::recording::hex")]
+    pub bytes: Vec<u8>,
+
+    /// The bytes as a human would read them, like "800" or "1 (Video)"
+    pub value: String,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Field>,
+}
+
+impl Field {
+    fn new(name: &str, offset: usize, bytes: &[u8], value: String) -> Field {
+        Field {
+            name: name.to_string(),
+            offset,
+            bytes: bytes.to_vec(),
+            value,
+            children: Vec::new(),
+        }
+    }
+
+    fn group(name: &str, offset: usize, bytes: &[u8], children: Vec<Field>) -> Field {
+        Field {
+            name: name.to_string(),
+            offset,
+            bytes: bytes.to_vec(),
+            value: String::new(),
+            children,
+        }
+    }
+}
+
+/// A camera control packet broken down byte by byte
+///
+/// Never fails, a packet that ends early or names a parameter missing from
+/// PROTOCOL.json is dissected as far as it goes and the problem is listed in `errors`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Dissection {
+    pub fields: Vec<Field>,
+    pub errors: Vec<String>,
+}
+
+impl Dissection {
+    /// Dissects a single camera control packet
+    ///
+    /// # Arguments
+    ///
+    /// * `packet` - &[u8] as sent to or received from the camera
+    pub fn new(packet: &[u8]) -> Dissection {
+        let mut fields = Vec::new();
+        let mut errors = Vec::new();
+
+        // Header
+        let header = &packet[..packet.len().min(4)];
+        let mut children = Vec::new();
+        for (i, b) in header.iter().enumerate() {
+            let (name, value) = match i {
+                0 => (
+                    "destination",
+                    match b {
+                        255 => "255 (broadcast)".to_string(),
+                        v => v.to_string(),
+                    },
+                ),
+                1 => ("length", b.to_string()),
+                2 => (
+                    "command id",
+                    match b {
+                        0 => "0 (change configuration)".to_string(),
+                        v => v.to_string(),
+                    },
+                ),
+                _ => ("reserved", b.to_string()),
+            };
+            children.push(Field::new(name, i, &[*b], value));
+        }
+        fields.push(Field::group("header", 0, header, children));
+
+        if packet.len() < 8 {
+            errors.push(format!(
+                "packet is {} bytes, shorter than a command",
+                packet.len()
+            ));
+            return Dissection { fields, errors };
+        }
+
+        // Command
+        let key = CommandKey::new(packet[4], packet[5]);
+        let category = CategoryInfo::lookup(key.category);
+        let info = ParameterInfo::lookup(key);
+        let data_type = packet[6];
+
+        if category.is_none() {
+            errors.push(format!("category {} is not defined", key.category));
+        } else if info.is_none() {
+            errors.push(format!("parameter {} is not defined", key));
+        }
+
+        let children = vec![
+            Field::new(
+                "category",
+                4,
+                &packet[4..5],
+                named(key.category, category.map(|c| c.name)),
+            ),
+            Field::new(
+                "parameter",
+                5,
+                &packet[5..6],
+                named(key.parameter, info.map(|p| p.name)),
+            ),
+            Field::new(
+                "type",
+                6,
+                &packet[6..7],
+                named(data_type, type_name(data_type)),
+            ),
+            Field::new(
+                "operation",
+                7,
+                &packet[7..8],
+                match Operation::from_u8(packet[7]) {
+                    Operation::AssignValue => "0 (assign value)".to_string(),
+                    Operation::OffsetValue => "1 (offset value)".to_string(),
+                    Operation::Unknown => packet[7].to_string(),
+                },
+            ),
+        ];
+        fields.push(Field::group("command", 4, &packet[4..8], children));
+
+        // Data, the length byte counts the command bytes too
+        let length = (packet[1] as usize).saturating_sub(4);
+        let end = (8 + length).min(packet.len());
+        if 8 + length > packet.len() {
+            errors.push(format!(
+                "length says {} bytes of data but only {} are there",
+                length,
+                packet.len() - 8
+            ));
+        }
+
+        let data = &packet[8..end];
+        if !data.is_empty() {
+            fields.push(Field::group(
+                "data",
+                8,
+                data,
+                elements(data, data_type, info, &mut errors),
+            ));
+        }
+
+        if end < packet.len() {
+            let padding = &packet[end..];
+            if padding.iter().any(|b| *b != 0) {
+                errors.push("padding is not zero".to_string());
+            }
+            fields.push(Field::new(
+                "padding",
+                end,
+                padding,
+                format!("{} bytes", padding.len()),
+            ));
+        }
+
+        Dissection { fields, errors }
+    }
+
+    /// Renders the dissection as JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// Renders an indented tree, one field per line with its offset and bytes
+impl fmt::Display for Dissection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_field(f: &mut fmt::Formatter<'_>, field: &Field, depth: usize) -> fmt::Result {
+            // Groups leave the bytes to their children
+            let bytes: String = match field.children.is_empty() {
+                true => field.bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+                false => String::new(),
+            };
+            let range = match field.bytes.len() {
+                0 | 1 => format!("{}", field.offset),
+                n => format!("{}-{}", field.offset, field.offset + n - 1),
+            };
+
+            write!(
+                f,
+                "{:>5}  {:<16} {}{}",
+                range,
+                bytes,
+                "  ".repeat(depth),
+                field.name
+            )?;
+            if !field.value.is_empty() {
+                write!(f, ": {}", field.value)?;
+            }
+            writeln!(f)?;
+
+            for c in field.children.iter() {
+                write_field(f, c, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        for field in self.fields.iter() {
+            write_field(f, field, 0)?;
+        }
+        for e in self.errors.iter() {
+            writeln!(f, "error: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+fn named(id: u8, name: Option<&str>) -> String {
+    match name {
+        Some(n) => format!("{} ({})", id, n),
+        None => id.to_string(),
+    }
+}
+
+fn type_name(data_type: u8) -> Option<&'static str> {
+    Some(match data_type {
+        0 => "void",
+        1 => "int8",
+        2 => "int16",
+        3 => "int32",
+        4 => "int64",
+        5 => "string",
+        128 => "fixed16",
+        _ => return None,
+    })
+}
+
+fn element_size(data_type: u8) -> Option<usize> {
+    Some(match data_type {
+        0 | 1 => 1,
+        2 | 128 => 2,
+        3 => 4,
+        4 => 8,
+        _ => return None,
+    })
+}
+
+// Splits the data into its elements and names them after the index in PROTOCOL.json
+fn elements(
+    data: &[u8],
+    data_type: u8,
+    info: Option<&ParameterInfo>,
+    errors: &mut Vec<String>,
+) -> Vec<Field> {
+    let name = info.map(|p| p.name).unwrap_or("value");
+
+    if data_type == 5 {
+        return vec![Field::new(
+            name,
+            8,
+            data,
+            format!("{:?}", String::from_utf8_lossy(data)),
+        )];
+    }
+
+    let size = match element_size(data_type) {
+        Some(v) => v,
+        None => {
+            errors.push(format!("type {} is not defined", data_type));
+            return vec![Field::new(name, 8, data, String::new())];
+        }
+    };
+
+    if !data.chunks_exact(size).remainder().is_empty() {
+        errors.push(format!(
+            "{} bytes of data is not a whole number of {} byte elements",
+            data.len(),
+            size
+        ));
+    }
+
+    let count = data.len() / size;
+    let index = info.map(|p| p.index).unwrap_or(&[]);
+
+    let mut fields: Vec<Field> = data
+        .chunks_exact(size)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let element = match (index.get(i), count) {
+                (Some(n), _) if index.len() == count => n.to_string(),
+                (_, 1) => name.to_string(),
+                _ => format!("{}[{}]", name, i),
+            };
+            Field::new(&element, 8 + i * size, bytes, value(bytes, data_type, info))
+        })
+        .collect();
+
+    let remainder = data.chunks_exact(size).remainder();
+    if !remainder.is_empty() {
+        fields.push(Field::new(
+            "remainder",
+            8 + data.len() - remainder.len(),
+            remainder,
+            String::new(),
+        ));
+    }
+
+    fields
+}
+
+fn value(bytes: &[u8], data_type: u8, info: Option<&ParameterInfo>) -> String {
+    let v = match data_type {
+        0 if info.map(|p| p.data_type) == Some("boolean") => {
+            return (bytes[0] != 0).to_string();
+        }
+        0 | 1 => i8::from_bytes(bytes).map(|v| v.to_string()),
+        2 => i16::from_bytes(bytes).map(|v| v.to_string()),
+        3 => i32::from_bytes(bytes).map(|v| v.to_string()),
+        4 => i64::from_bytes(bytes).map(|v| v.to_string()),
+        128 => f32::from_bytes(bytes).map(|v| v.to_string()),
+        _ => return String::new(),
+    };
+    v.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Command, Lens};
+    use crate::rawcommand::RawCommand;
+
+    // The fields that hold bytes themselves, in order
+    fn leaves(fields: &[Field]) -> Vec<&Field> {
+        fields
+            .iter()
+            .flat_map(|f| match f.children.is_empty() {
+                true => vec![f],
+                false => leaves(&f.children),
+            })
+            .collect()
+    }
+
+    fn focus_packet() -> Vec<u8> {
+        let mut packet =
+            RawCommand::to_raw(1, Operation::AssignValue, &Command::Lens(Lens::Focus(0.5)));
+        // Padded to 32 bits, the way it goes over the air
+        packet.resize(12, 0);
+        packet
+    }
+
+    #[test]
+    fn names_every_byte() {
+        let packet = focus_packet();
+        let dissection = Dissection::new(&packet);
+        assert!(dissection.errors.is_empty());
+
+        let leaves = leaves(&dissection.fields);
+        let mut offset = 0;
+        for field in leaves.iter() {
+            assert_eq!(field.offset, offset);
+            assert_eq!(field.bytes, packet[offset..offset + field.bytes.len()]);
+            offset += field.bytes.len();
+        }
+        assert_eq!(offset, packet.len());
+
+        let names: Vec<&str> = leaves.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "destination",
+                "length",
+                "command id",
+                "reserved",
+                "category",
+                "parameter",
+                "type",
+                "operation",
+                "Focus",
+                "padding",
+            ]
+        );
+
+        let values: Vec<&str> = leaves.iter().map(|f| f.value.as_str()).collect();
+        assert_eq!(
+            values[4..9],
+            [
+                "0 (Lens)",
+                "0 (Focus)",
+                "128 (fixed16)",
+                "0 (assign value)",
+                "0.5"
+            ]
+        );
+    }
+
+    #[test]
+    fn dissects_truncated_packets_as_far_as_they_go() {
+        let packet = focus_packet();
+
+        let dissection = Dissection::new(&packet[..6]);
+        assert_eq!(dissection.fields.len(), 1);
+        assert_eq!(dissection.fields[0].children.len(), 4);
+        assert_eq!(
+            dissection.errors,
+            ["packet is 6 bytes, shorter than a command"]
+        );
+
+        let dissection = Dissection::new(&packet[..9]);
+        let data = dissection.fields.iter().find(|f| f.name == "data").unwrap();
+        assert_eq!(data.bytes, packet[8..9]);
+        assert_eq!(
+            dissection.errors,
+            [
+                "length says 2 bytes of data but only 1 are there",
+                "1 bytes of data is not a whole number of 2 byte elements",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_parameters() {
+        let dissection = Dissection::new(&[255, 4, 0, 0, 200, 0, 0, 0]);
+        assert_eq!(dissection.errors, ["category 200 is not defined"]);
+        assert_eq!(dissection.fields[1].children[0].value, "200");
+
+        let dissection = Dissection::new(&[255, 4, 0, 0, 0, 99, 0, 0]);
+        assert_eq!(dissection.errors, ["parameter 0.99 is not defined"]);
+        assert_eq!(dissection.fields[1].children[0].value, "0 (Lens)");
+        assert_eq!(dissection.fields[1].children[1].value, "99");
+    }
+}
@@ -0,0 +1,864 @@
+use crate::camera::Camera;
+use crate::command::Command;
+use crate::error::DmxError;
+use crate::info::ParameterInfo;
+use crate::key::CommandKey;
+use crate::keys;
+use crate::rawcommand::Operation;
+use crate::transport::CameraTransport;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+/// UDP port Art-Net is sent to
+pub const ARTNET_PORT: u16 = 6454;
+
+/// UDP port sACN is sent to
+pub const SACN_PORT: u16 = 5568;
+
+/// A source that has not sent anything for this long has gone away, as E1.31 specifies
+pub const SOURCE_TIMEOUT: Duration = Duration::from_millis(2500);
+
+const ARTNET_ID: &[u8] = b"Art-Net\0";
+const ARTNET_OP_DMX: u16 = 0x5000;
+const ACN_ID: &[u8] = b"ASC-E1.17\0\0\0";
+
+/// Who sent a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DmxSource {
+    /// Art-Net has no source id, so senders are told apart by address
+    ArtNet(IpAddr),
+
+    /// sACN component identifier
+    Sacn([u8; 16]),
+}
+
+/// The levels of one universe as sent by one source
+#[derive(Debug, Clone, PartialEq)]
+pub struct DmxFrame {
+    pub source: DmxSource,
+
+    /// Universes are numbered from 1, Art-Net port-address 0 is universe 1
+    pub universe: u16,
+
+    /// 0 to 200, Art-Net frames get the patch's artnet_priority
+    pub priority: u8,
+    pub sequence: u8,
+
+    /// The source stopped sending this universe
+    pub terminated: bool,
+
+    /// Channel 1 first
+    pub data: Vec<u8>,
+}
+
+impl DmxFrame {
+    /// Parses an ArtDmx packet, None for anything else such as ArtPoll
+    ///
+    /// # Arguments
+    ///
+    /// * `packet` - &[u8] of a single UDP datagram
+    /// * `from` - IpAddr the datagram came from
+    /// * `priority` - u8 to give the frame, Art-Net has none of its own
+    pub fn from_artnet(packet: &[u8], from: IpAddr, priority: u8) -> Option<DmxFrame> {
+        if packet.len() < 18 || !packet.starts_with(ARTNET_ID) {
+            return None;
+        }
+        if u16::from_le_bytes([packet[8], packet[9]]) != ARTNET_OP_DMX {
+            return None;
+        }
+
+        let universe = u16::from_le_bytes([packet[14], packet[15] & 0x7f]);
+        let length = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+        let data = packet.get(18..18 + length.min(512))?;
+
+        Some(DmxFrame {
+            source: DmxSource::ArtNet(from),
+            universe: universe + 1,
+            priority,
+            sequence: packet[12],
+            terminated: false,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Parses an E1.31 data packet, None for anything else such as sync and discovery
+    ///
+    /// Preview data and alternate start codes are not levels to act on, so they are None too.
+    pub fn from_sacn(packet: &[u8]) -> Option<DmxFrame> {
+        if packet.len() < 126 || &packet[4..16] != ACN_ID {
+            return None;
+        }
+        // Root layer E1.31 data, framing layer DMP, DMP set property
+        if packet[18..22] != [0, 0, 0, 4] || packet[40..44] != [0, 0, 0, 2] || packet[117] != 2 {
+            return None;
+        }
+
+        let options = packet[112];
+        let terminated = options & 0x40 != 0;
+        if options & 0x80 != 0 || (packet[125] != 0 && !terminated) {
+            return None;
+        }
+
+        let mut cid = [0u8; 16];
+        cid.copy_from_slice(&packet[22..38]);
+
+        // The property count includes the start code
+        let count = u16::from_be_bytes([packet[123], packet[124]]) as usize;
+        let data = packet.get(126..125 + count.clamp(1, 513))?;
+
+        Some(DmxFrame {
+            source: DmxSource::Sacn(cid),
+            universe: u16::from_be_bytes([packet[113], packet[114]]),
+            priority: packet[108],
+            sequence: packet[111],
+            terminated,
+            data: data.to_vec(),
+        })
+    }
+}
+
+/// Binds one DMX channel, or a pair for 16-bit, to one element of a camera parameter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DmxChannel {
+    /// The patch's universe unless set
+    #[serde(default)]
+    pub universe: Option<u16>,
+
+    /// 1 to 512, the coarse channel for 16-bit, the fine one follows it
+    pub channel: u16,
+
+    /// 8 or 16
+    #[serde(default = "eight_bit")]
+    pub bits: u8,
+
+    /// Normalized name like this: lens_aperture_normalised
+    pub parameter: String,
+
+    /// Element of parameters with several, like "luma" for color_correction_gain_adjust
+    #[serde(default)]
+    pub element: Option<String>,
+
+    /// Range the channel covers, the one in PROTOCOL.json unless set
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+fn eight_bit() -> u8 {
+    8
+}
+
+/// A patch file, JSON like this:
+///
+/// ```json
+/// {
+///     "universe": 1,
+///     "channels": [
+///         { "channel": 1, "bits": 16, "parameter": "lens_aperture_normalised" },
+///         { "channel": 3, "parameter": "video_nd_filter", "min": 0, "max": 6 },
+///         { "channel": 4, "parameter": "color_correction_gain_adjust", "element": "red" }
+///     ]
+/// }
+/// ```
+///
+/// Channels patched to a trigger fire when the level crosses half way up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DmxPatch {
+    #[serde(default = "first_universe")]
+    pub universe: u16,
+
+    /// Camera the commands are addressed to
+    #[serde(default = "broadcast_destination")]
+    pub destination: u8,
+
+    /// Priority Art-Net sources get against sACN ones, the sACN default unless set
+    #[serde(default = "default_priority")]
+    pub artnet_priority: u8,
+
+    pub channels: Vec<DmxChannel>,
+}
+
+fn first_universe() -> u16 {
+    1
+}
+
+fn broadcast_destination() -> u8 {
+    255
+}
+
+fn default_priority() -> u8 {
+    100
+}
+
+impl DmxPatch {
+    /// Reads a patch file from disk
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<DmxPatch, DmxError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Binding {
+    universe: u16,
+    channel: usize,
+    fine: bool,
+    info: &'static ParameterInfo,
+    element: usize,
+    min: f64,
+    max: f64,
+
+    last: Option<u16>,
+}
+
+impl Binding {
+    fn new(channel: &DmxChannel, universe: u16) -> Result<Binding, DmxError> {
+        let invalid = |why: &str| DmxError::InvalidPatch(channel.parameter.clone(), why.into());
+
+        let info = keys::from_normalized_name(&channel.parameter)
+            .and_then(ParameterInfo::lookup)
+            .ok_or_else(|| DmxError::UnknownParameter(channel.parameter.clone()))?;
+
+        if info.data_type == "string" {
+            return Err(invalid("strings can not be driven from DMX"));
+        }
+
+        let fine = match channel.bits {
+            8 => false,
+            16 => true,
+            _ => return Err(invalid("bits has to be 8 or 16")),
+        };
+        let last_channel = if fine { 511 } else { 512 };
+        if channel.channel < 1 || channel.channel > last_channel {
+            return Err(invalid("channel out of range"));
+        }
+
+        let element = match &channel.element {
+            Some(name) => info
+                .index
+                .iter()
+                .position(|i| i == name)
+                .ok_or_else(|| invalid(&format!("no element named {}", name)))?,
+            None => 0,
+        };
+
+        Ok(Binding {
+            universe: channel.universe.unwrap_or(universe),
+            channel: channel.channel as usize - 1,
+            fine,
+            info,
+            element,
+            min: channel.min.or(info.minimum).unwrap_or(0.0),
+            max: channel.max.or(info.maximum).unwrap_or(1.0),
+
+            last: None,
+        })
+    }
+
+    fn level(&self, data: &[u8]) -> Option<u16> {
+        let coarse = *data.get(self.channel)? as u16;
+        if !self.fine {
+            return Some(coarse);
+        }
+        Some(coarse << 8 | *data.get(self.channel + 1)? as u16)
+    }
+
+    fn full(&self) -> f64 {
+        if self.fine {
+            65535.0
+        } else {
+            255.0
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SourceState {
+    priority: u8,
+    sequence: u8,
+    last_seen: Instant,
+}
+
+#[derive(Debug, Clone, Default)]
+struct UniverseState {
+    sources: HashMap<DmxSource, SourceState>,
+    active: Option<DmxSource>,
+}
+
+impl UniverseState {
+    /// Records a frame, returns false if it arrived out of order and should be dropped
+    fn update(&mut self, frame: &DmxFrame, now: Instant) -> bool {
+        if frame.terminated {
+            self.sources.remove(&frame.source);
+            return true;
+        }
+
+        if let (DmxSource::Sacn(_), Some(state)) = (frame.source, self.sources.get(&frame.source)) {
+            // E1.31 drops packets up to 20 behind the last one
+            let behind = frame.sequence.wrapping_sub(state.sequence) as i8;
+            if behind <= 0 && behind > -20 {
+                return false;
+            }
+        }
+
+        self.sources.insert(
+            frame.source,
+            SourceState {
+                priority: frame.priority,
+                sequence: frame.sequence,
+                last_seen: now,
+            },
+        );
+        true
+    }
+
+    /// Picks the source in control, the current one keeps it against others of equal priority
+    fn select(&mut self, now: Instant) -> Option<DmxSource> {
+        self.sources
+            .retain(|_, s| now.duration_since(s.last_seen) < SOURCE_TIMEOUT);
+
+        let highest = self.sources.values().map(|s| s.priority).max()?;
+        let keep = self
+            .active
+            .filter(|a| self.sources.get(a).map(|s| s.priority) == Some(highest));
+
+        self.active = keep.or_else(|| {
+            self.sources
+                .iter()
+                .find(|(_, s)| s.priority == highest)
+                .map(|(source, _)| *source)
+        });
+        self.active
+    }
+}
+
+/// Turns DMX frames into camera commands according to a patch
+///
+/// Holds no sockets, so it can be driven by any DMX source. DmxReceiver wires it to
+/// Art-Net and sACN and a camera.
+#[derive(Debug, Clone)]
+pub struct DmxMapper {
+    destination: u8,
+    artnet_priority: u8,
+    bindings: Vec<Binding>,
+    universes: HashMap<u16, UniverseState>,
+}
+
+impl DmxMapper {
+    /// Checks every channel against PROTOCOL.json and returns a new DmxMapper
+    pub fn new(patch: &DmxPatch) -> Result<DmxMapper, DmxError> {
+        let bindings = patch
+            .channels
+            .iter()
+            .map(|c| Binding::new(c, patch.universe))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(DmxMapper {
+            destination: patch.destination,
+            artnet_priority: patch.artnet_priority,
+            universes: bindings
+                .iter()
+                .map(|b| (b.universe, UniverseState::default()))
+                .collect(),
+            bindings,
+        })
+    }
+
+    /// Camera the commands are addressed to
+    pub fn destination(&self) -> u8 {
+        self.destination
+    }
+
+    /// Priority to give Art-Net frames
+    pub fn artnet_priority(&self) -> u8 {
+        self.artnet_priority
+    }
+
+    /// Universes the patch uses
+    pub fn universes(&self) -> Vec<u16> {
+        self.universes.keys().copied().collect()
+    }
+
+    /// Returns the commands a frame asks for, or the error one of them ran into
+    ///
+    /// Only frames from the source in control of the universe count, that is the one
+    /// with the highest priority heard from within SOURCE_TIMEOUT. Only channels that
+    /// changed since the last frame produce commands, one per parameter.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - DmxFrame from the network
+    /// * `now` - Instant the frame arrived
+    /// * `current` - looks up the latest value the camera reported for a parameter, so
+    ///   channels driving one element of a parameter keep the others
+    pub fn handle(
+        &mut self,
+        frame: &DmxFrame,
+        now: Instant,
+        current: &dyn Fn(CommandKey) -> Option<Command>,
+    ) -> Result<Vec<Command>, DmxError> {
+        let universe = match self.universes.get_mut(&frame.universe) {
+            Some(u) => u,
+            None => return Ok(Vec::new()),
+        };
+        if !universe.update(frame, now) {
+            return Ok(Vec::new());
+        }
+        if frame.terminated || universe.select(now) != Some(frame.source) {
+            return Ok(Vec::new());
+        }
+
+        let mut changed: Vec<(&'static ParameterInfo, Vec<f64>)> = Vec::new();
+        for binding in self.bindings.iter_mut() {
+            if binding.universe != frame.universe {
+                continue;
+            }
+            let level = match binding.level(&frame.data) {
+                Some(l) => l,
+                None => continue,
+            };
+            let last = binding.last.replace(level);
+            if last == Some(level) {
+                continue;
+            }
+
+            let info = binding.info;
+            if info.elements() == 0 {
+                // Fires on the way up, a level already high when we start does nothing
+                let half = if binding.fine { 0x8000 } else { 0x80 };
+                if last.is_some_and(|l| l < half) && level >= half {
+                    changed.push((info, Vec::new()));
+                }
+                continue;
+            }
+
+            let value = binding.min + (binding.max - binding.min) * level as f64 / binding.full();
+            let index = match changed.iter().position(|(i, _)| i.key() == info.key()) {
+                Some(index) => index,
+                None => {
+                    let values = current(info.key())
+                        .map(|c| info.values(&c))
+                        .filter(|v| v.len() == info.elements())
+                        .unwrap_or_else(|| vec![0.0; info.elements()]);
+                    changed.push((info, values));
+                    changed.len() - 1
+                }
+            };
+            changed[index].1[binding.element] = value;
+        }
+
+        Ok(changed
+            .into_iter()
+            .map(|(info, values)| info.command(&values))
+            .collect::<Result<_, _>>()?)
+    }
+}
+
+/// Controls a camera from a lighting console over Art-Net and sACN
+///
+/// Both protocols feed the same patch, so a console can switch between them or a backup
+/// console on the other protocol can take over by priority.
+#[derive(Debug)]
+pub struct DmxReceiver<T: CameraTransport> {
+    camera: Arc<Mutex<Camera<T>>>,
+    mapper: Arc<std::sync::Mutex<DmxMapper>>,
+    tasks: Vec<JoinHandle<()>>,
+    errors: mpsc::Sender<DmxError>,
+    error_receiver: Option<mpsc::Receiver<DmxError>>,
+}
+
+impl<T: CameraTransport> DmxReceiver<T> {
+    /// Takes a camera and a patch, nothing listens until told to
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - Camera to control, connected or not
+    /// * `patch` - DmxPatch like DmxPatch::open returns
+    pub fn new(camera: Camera<T>, patch: &DmxPatch) -> Result<DmxReceiver<T>, DmxError> {
+        DmxReceiver::from_shared(Arc::new(Mutex::new(camera)), patch)
+    }
+
+    /// Same as new but for a camera that is shared already, such as by a BridgeServer
+    pub fn from_shared(
+        camera: Arc<Mutex<Camera<T>>>,
+        patch: &DmxPatch,
+    ) -> Result<DmxReceiver<T>, DmxError> {
+        let (errors, error_receiver) = mpsc::channel(16);
+
+        Ok(DmxReceiver {
+            camera,
+            mapper: Arc::new(std::sync::Mutex::new(DmxMapper::new(patch)?)),
+            tasks: Vec::new(),
+            errors,
+            error_receiver: Some(error_receiver),
+        })
+    }
+
+    /// Gives you the camera, to connect it or use it alongside DMX
+    pub fn camera(&self) -> Arc<Mutex<Camera<T>>> {
+        self.camera.clone()
+    }
+
+    /// Takes the receiver of the errors the listeners run into
+    ///
+    /// Levels that cannot be turned into a command and writes the camera does not take end
+    /// up here. Nothing waits for it to be read, errors past the first 16 unread ones are
+    /// dropped.
+    pub fn errors(&mut self) -> Option<mpsc::Receiver<DmxError>> {
+        self.error_receiver.take()
+    }
+
+    /// Starts listening for Art-Net, returns the address it listens on
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - address to listen on like this: "0.0.0.0:6454"
+    pub async fn listen_artnet<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<SocketAddr> {
+        let socket = UdpSocket::bind(addr).await?;
+        socket.set_broadcast(true)?;
+        self.listen(socket, false)
+    }
+
+    /// Starts listening for sACN, returns the address it listens on
+    ///
+    /// Bound to an unspecified address, it joins the multicast groups of the patched
+    /// universes. Bound to a specific one, only unicast sACN reaches it.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - address to listen on like this: "0.0.0.0:5568"
+    pub async fn listen_sacn<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<SocketAddr> {
+        let socket = UdpSocket::bind(addr).await?;
+
+        if socket.local_addr()?.ip() == IpAddr::V4(Ipv4Addr::UNSPECIFIED) {
+            let universes = self.mapper.lock().unwrap().universes();
+            for universe in universes {
+                let [hi, lo] = universe.to_be_bytes();
+                socket.join_multicast_v4(Ipv4Addr::new(239, 255, hi, lo), Ipv4Addr::UNSPECIFIED)?;
+            }
+        }
+
+        self.listen(socket, true)
+    }
+
+    fn listen(&mut self, socket: UdpSocket, sacn: bool) -> io::Result<SocketAddr> {
+        let local = socket.local_addr()?;
+        let camera = self.camera.clone();
+        let mapper = self.mapper.clone();
+        let errors = self.errors.clone();
+
+        self.tasks.push(tokio::spawn(async move {
+            let cache = camera.lock().await.cache();
+            let mut buf = vec![0u8; 1024];
+
+            while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+                let now = Instant::now();
+                let frame = if sacn {
+                    DmxFrame::from_sacn(&buf[..n])
+                } else {
+                    let priority = mapper.lock().unwrap().artnet_priority();
+                    DmxFrame::from_artnet(&buf[..n], from.ip(), priority)
+                };
+                let frame = match frame {
+                    Some(f) => f,
+                    None => continue,
+                };
+
+                // Most frames repeat the last one, those do not need the camera at all
+                let (destination, writes) = {
+                    let cache = cache.read().await;
+                    let current = |key: CommandKey| cache.command(key).cloned();
+                    let mut mapper = mapper.lock().unwrap();
+                    (mapper.destination(), mapper.handle(&frame, now, &current))
+                };
+                let writes = match writes {
+                    Ok(writes) if writes.is_empty() => continue,
+                    Ok(writes) => writes,
+                    Err(e) => {
+                        let _ = errors.try_send(e);
+                        continue;
+                    }
+                };
+
+                let mut camera = camera.lock().await;
+                for command in writes {
+                    if let Err(e) = camera
+                        .write(destination, Operation::AssignValue, command)
+                        .await
+                    {
+                        let _ = errors.try_send(DmxError::WriteError(e.to_string()));
+                    }
+                }
+            }
+        }));
+
+        Ok(local)
+    }
+
+    /// Stops listening, the camera stays connected
+    pub fn shutdown(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+}
+
+impl<T: CameraTransport> Drop for DmxReceiver<T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Lens;
+    use crate::simulator::SimulatedCamera;
+    use tokio::sync::broadcast::Receiver;
+    use tokio::time::timeout;
+
+    const PATCH: &str = r#"{
+        "channels": [
+            { "channel": 1, "parameter": "lens_focus" },
+            { "channel": 2, "bits": 16, "parameter": "lens_aperture_normalised" }
+        ]
+    }"#;
+
+    fn artnet(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = ARTNET_ID.to_vec();
+        packet.extend_from_slice(&ARTNET_OP_DMX.to_le_bytes());
+        packet.extend_from_slice(&[0, 14, sequence, 0]);
+        packet.extend_from_slice(&universe.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn sacn(cid: u8, priority: u8, sequence: u8, options: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; 126];
+        packet[1] = 0x10;
+        packet[4..16].copy_from_slice(ACN_ID);
+        packet[21] = 4;
+        packet[22..38].copy_from_slice(&[cid; 16]);
+        packet[43] = 2;
+        packet[108] = priority;
+        packet[111] = sequence;
+        packet[112] = options;
+        packet[113..115].copy_from_slice(&1u16.to_be_bytes());
+        packet[117] = 2;
+        packet[118] = 0xa1;
+        packet[122] = 1;
+        packet[123..125].copy_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    /// Lens values in percent, fixed16 does not hold most levels exactly
+    fn percent(command: &Command) -> Option<(&'static str, i32)> {
+        match command {
+            Command::Lens(Lens::Focus(v)) => Some(("focus", (v * 100.0).round() as i32)),
+            Command::Lens(Lens::ApertureNormalised(v)) => {
+                Some(("aperture", (v * 100.0).round() as i32))
+            }
+            _ => None,
+        }
+    }
+
+    fn mapper() -> DmxMapper {
+        DmxMapper::new(&serde_json::from_str(PATCH).unwrap()).unwrap()
+    }
+
+    async fn receiver() -> (DmxReceiver<SimulatedCamera>, Receiver<Command>) {
+        let mut camera = Camera::with_transport(SimulatedCamera::new());
+        camera.connect(Duration::from_secs(1)).await.unwrap();
+        camera
+            .wait_synced(Duration::from_millis(50), Duration::from_secs(1))
+            .await
+            .unwrap();
+        let updates = camera.updates().await;
+
+        let patch = serde_json::from_str(PATCH).unwrap();
+        (DmxReceiver::new(camera, &patch).unwrap(), updates)
+    }
+
+    /// Lens values the camera reports, up to and including `last`
+    async fn changes_until(
+        updates: &mut Receiver<Command>,
+        last: (&'static str, i32),
+    ) -> Vec<(&'static str, i32)> {
+        timeout(Duration::from_secs(2), async {
+            let mut changes = Vec::new();
+            loop {
+                if let Some(change) = percent(&updates.recv().await.unwrap()) {
+                    changes.push(change);
+                    if change == last {
+                        return changes;
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_artnet() {
+        let from = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let frame = DmxFrame::from_artnet(&artnet(0, 7, &[1, 2, 3]), from, 50).unwrap();
+        assert_eq!(
+            frame,
+            DmxFrame {
+                source: DmxSource::ArtNet(from),
+                universe: 1,
+                priority: 50,
+                sequence: 7,
+                terminated: false,
+                data: vec![1, 2, 3],
+            }
+        );
+
+        // ArtPoll
+        let mut poll = artnet(0, 0, &[]);
+        poll[8..10].copy_from_slice(&0x2000u16.to_le_bytes());
+        assert_eq!(DmxFrame::from_artnet(&poll, from, 100), None);
+
+        // Claims more channels than it carries
+        let mut short = artnet(0, 0, &[1, 2, 3]);
+        short[17] = 10;
+        assert_eq!(DmxFrame::from_artnet(&short, from, 100), None);
+    }
+
+    #[test]
+    fn parses_sacn() {
+        let frame = DmxFrame::from_sacn(&sacn(1, 150, 9, 0, &[1, 2, 3])).unwrap();
+        assert_eq!(
+            frame,
+            DmxFrame {
+                source: DmxSource::Sacn([1; 16]),
+                universe: 1,
+                priority: 150,
+                sequence: 9,
+                terminated: false,
+                data: vec![1, 2, 3],
+            }
+        );
+        assert!(
+            DmxFrame::from_sacn(&sacn(1, 100, 0, 0x40, &[]))
+                .unwrap()
+                .terminated
+        );
+
+        // Preview data
+        assert_eq!(DmxFrame::from_sacn(&sacn(1, 100, 0, 0x80, &[1])), None);
+
+        // Alternate start code
+        let mut text = sacn(1, 100, 0, 0, &[1]);
+        text[125] = 0x17;
+        assert_eq!(DmxFrame::from_sacn(&text), None);
+
+        // Universe discovery
+        let mut discovery = sacn(1, 100, 0, 0, &[1]);
+        discovery[43] = 8;
+        assert_eq!(DmxFrame::from_sacn(&discovery), None);
+    }
+
+    #[test]
+    fn timed_out_sources_hand_back_control() {
+        let mut mapper = mapper();
+        let start = Instant::now();
+        let current = |_: CommandKey| None;
+        let mut handle = |packet: Vec<u8>, now: Instant| {
+            let frame = DmxFrame::from_sacn(&packet).unwrap();
+            let commands = mapper.handle(&frame, now, &current).unwrap();
+            commands.iter().filter_map(percent).collect::<Vec<_>>()
+        };
+
+        assert_eq!(handle(sacn(1, 100, 0, 0, &[51]), start), [("focus", 20)]);
+        assert_eq!(handle(sacn(2, 150, 0, 0, &[204]), start), [("focus", 80)]);
+
+        let later = start + Duration::from_secs(1);
+        assert!(handle(sacn(1, 100, 1, 0, &[102]), later).is_empty());
+
+        // The higher priority source has not been heard from for too long
+        let later = start + SOURCE_TIMEOUT + Duration::from_millis(10);
+        assert_eq!(handle(sacn(1, 100, 2, 0, &[102]), later), [("focus", 40)]);
+    }
+
+    #[tokio::test]
+    async fn listens_for_artnet() {
+        let (mut receiver, mut updates) = receiver().await;
+        let local = receiver.listen_artnet("127.0.0.1:0").await.unwrap();
+        let console = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // 8-bit focus, 16-bit aperture
+        let levels = [51, 0x80, 0x00];
+        console
+            .send_to(&artnet(0, 1, &levels), local)
+            .await
+            .unwrap();
+        console
+            .send_to(&artnet(0, 2, &levels), local)
+            .await
+            .unwrap();
+        console
+            .send_to(&artnet(0, 3, &[102, 0x80, 0x00]), local)
+            .await
+            .unwrap();
+
+        // The repeated frame wrote nothing
+        assert_eq!(
+            changes_until(&mut updates, ("focus", 40)).await,
+            [("focus", 20), ("aperture", 50), ("focus", 40)]
+        );
+    }
+
+    #[tokio::test]
+    async fn follows_sacn_priority() {
+        let (mut receiver, mut updates) = receiver().await;
+        let local = receiver.listen_sacn("127.0.0.1:0").await.unwrap();
+        let console = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        console
+            .send_to(&sacn(1, 100, 0, 0, &[51]), local)
+            .await
+            .unwrap();
+        assert_eq!(
+            changes_until(&mut updates, ("focus", 20)).await,
+            [("focus", 20)]
+        );
+
+        // A backup console with a higher priority takes over
+        console
+            .send_to(&sacn(2, 150, 0, 0, &[204]), local)
+            .await
+            .unwrap();
+        console
+            .send_to(&sacn(1, 100, 1, 0, &[102]), local)
+            .await
+            .unwrap();
+
+        // And hands control back when it terminates
+        console
+            .send_to(&sacn(2, 150, 1, 0x40, &[]), local)
+            .await
+            .unwrap();
+        console
+            .send_to(&sacn(1, 100, 2, 0, &[102]), local)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            changes_until(&mut updates, ("focus", 40)).await,
+            [("focus", 80), ("focus", 40)]
+        );
+    }
+}
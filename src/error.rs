@@ -8,6 +8,48 @@ pub enum CameraControlError {
     CategoryOutOfRange,
     #[error("ConnectionTimeout")]
     ConnectionTimeout,
+    #[error("SyncTimeout")]
+    SyncTimeout,
+    #[error("Disconnected")]
+    Disconnected,
+}
+
+#[derive(Error, Debug)]
+pub enum TransportError {
+    #[error("Not connected to the camera. Did you run connect()?")]
+    NotConnected,
+
+    #[error("The connection to the camera was closed.")]
+    Closed,
+
+    #[error("The other end refused the connection.")]
+    Refused,
+
+    #[error("Not supported by this transport: {0}")]
+    Unsupported(String),
+
+    #[error("The camera answered with HTTP status {0}")]
+    HttpStatus(u16),
+
+    #[error(transparent)]
+    CameraControlError(#[from] CameraControlError),
+
+    #[error(transparent)]
+    CommandError(#[from] crate::rawcommand::CommandError),
+
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    CecError(#[from] CecError),
+
+    #[cfg(feature = "rest")]
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
+
+    #[cfg(feature = "rest")]
+    #[error(transparent)]
+    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
 }
 
 #[derive(Error, Debug)]
@@ -42,9 +84,150 @@ pub enum BluetoothCameraError {
     #[error(transparent)]
     BTLEError(#[from] btleplug::Error),
 
+    #[error(transparent)]
+    CameraControlError(#[from] CameraControlError),
+
+    #[error(transparent)]
+    CommandError(#[from] crate::rawcommand::CommandError),
+
     #[error(transparent)]
     IOError(#[from] std::io::Error),
 
     #[error(transparent)]
     UUIDError(#[from] uuid::Error),
 }
+
+#[derive(Error, Debug)]
+pub enum BtSnoopError {
+    #[error("Not a btsnoop file.")]
+    NotBtSnoop,
+
+    #[error("Unsupported btsnoop datalink type: `{0}`")]
+    UnsupportedDatalink(u32),
+
+    #[error("The btsnoop file ends in the middle of a record.")]
+    Truncated,
+
+    #[error("btsnoop record of `{0}` bytes is larger than any HCI packet.")]
+    RecordTooLong(u32),
+
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum AncError {
+    #[error("Ancillary packet does not start with the data flag 000 3FF 3FF.")]
+    MissingDataFlag,
+
+    #[error("Ancillary packet ends before its data count says it should.")]
+    Truncated,
+
+    #[error("Parity error in word {0}")]
+    Parity(usize),
+
+    #[error("Checksum mismatch: expected {0:#05x}, got {1:#05x}")]
+    Checksum(u16, u16),
+
+    #[error("User data is too long: {0} bytes (max 255)")]
+    TooLong(usize),
+
+    #[error("Not the expected ancillary packet: DID {0:#04x}, SDID {1:#04x}")]
+    WrongPacket(u8, u8),
+
+    #[error(transparent)]
+    CommandError(#[from] crate::rawcommand::CommandError),
+}
+
+#[derive(Error, Debug)]
+pub enum CecError {
+    #[error("Could not open the CEC adapter: {0}")]
+    AdapterOpenFailed(String),
+
+    #[error("The CEC adapter failed to transmit the message.")]
+    TransmitFailed,
+
+    #[error("No CEC opcode carries {0}")]
+    Unsupported(String),
+
+    #[error("CEC message is too long: {0} bytes (max {1})")]
+    TooLong(usize, usize),
+
+    #[error(transparent)]
+    CommandError(#[from] crate::rawcommand::CommandError),
+}
+
+#[derive(Error, Debug)]
+pub enum OscError {
+    #[error("OSC packet ends early")]
+    Truncated,
+
+    #[error("Not an OSC packet")]
+    NotOsc,
+
+    #[error("OSC bundles nested more than {0} deep")]
+    TooDeep(usize),
+
+    #[error("OSC type tag '{0}' is not supported")]
+    UnsupportedType(char),
+
+    #[error("No camera parameter at {0}")]
+    UnknownAddress(String),
+
+    #[error("Wrong arguments for {0}: {1}")]
+    WrongArguments(String, String),
+
+    #[error("{0} is out of range for {1} ({2} to {3})")]
+    OutOfRange(f64, String, f64, f64),
+
+    #[error(transparent)]
+    CommandError(#[from] crate::rawcommand::CommandError),
+}
+
+#[derive(Error, Debug)]
+pub enum MidiError {
+    #[error("Unknown parameter in MIDI mapping: `{0}`")]
+    UnknownParameter(String),
+
+    #[error("Invalid MIDI mapping for {0}: {1}")]
+    InvalidMapping(String, String),
+
+    #[error("Could not find MIDI port: `{0}`")]
+    PortNotFound(String),
+
+    #[error("MIDI error: {0}")]
+    PortError(String),
+
+    #[error("Could not write to the camera: {0}")]
+    WriteError(String),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    CommandError(#[from] crate::rawcommand::CommandError),
+
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum DmxError {
+    #[error("Unknown parameter in DMX patch: `{0}`")]
+    UnknownParameter(String),
+
+    #[error("Invalid DMX patch for {0}: {1}")]
+    InvalidPatch(String, String),
+
+    #[error("Could not write to the camera: {0}")]
+    WriteError(String),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    CommandError(#[from] crate::rawcommand::CommandError),
+
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+}
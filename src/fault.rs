@@ -0,0 +1,499 @@
+use crate::error::CameraControlError;
+use crate::transport::{CameraTransport, EventStream, TransportEvent};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+pub use crate::transport::Direction;
+
+/// Something that can go wrong with a single packet
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    Drop,
+    Delay(Duration),
+    Duplicate,
+    /// Hold the packet back and deliver it after the next one
+    Reorder,
+    /// Cut the packet short at a random length
+    Truncate,
+    /// Flip a random bit
+    Corrupt,
+    /// Drop the link, nothing gets through until connect() is called again
+    Disconnect,
+}
+
+/// How likely each fault is for every packet, from 0.0 (never) to 1.0 (always)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultConfig {
+    pub drop: f64,
+    pub delay: f64,
+    pub max_delay: Duration,
+    pub duplicate: f64,
+    pub reorder: f64,
+    pub truncate: f64,
+    pub corrupt: f64,
+    pub disconnect: f64,
+}
+
+/// How many faults have been injected so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub dropped: u64,
+    pub delayed: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub truncated: u64,
+    pub corrupted: u64,
+    pub disconnects: u64,
+}
+
+/// Wraps a transport and mangles the traffic going through it
+///
+/// Faults are either scheduled for a specific packet or rolled at random from a seed, so
+/// the same seed and traffic always produce the same faults. Only camera control packets
+/// are affected, timecode and status pass through untouched.
+#[derive(Debug)]
+pub struct FaultyTransport<T: CameraTransport> {
+    inner: T,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug)]
+struct FaultState {
+    rng: Rng,
+    outgoing: FaultConfig,
+    incoming: FaultConfig,
+    schedule: Vec<(Direction, u64, Fault)>,
+
+    sent: u64,
+    received: u64,
+    held_outgoing: Vec<Vec<u8>>,
+    held_incoming: Vec<Vec<u8>>,
+
+    connected: bool,
+    // Bumped on every disconnect so delayed packets from an older link can be told apart
+    link: u64,
+    events: Option<UnboundedSender<TransportEvent>>,
+    stats: FaultStats,
+}
+
+// What to do with a packet once the faults have been rolled
+#[derive(Debug, Default)]
+struct Outcome {
+    deliveries: Vec<(Duration, Vec<u8>)>,
+    disconnect: bool,
+}
+
+impl FaultState {
+    fn faults(&mut self, direction: Direction) -> Vec<Fault> {
+        let index = match direction {
+            Direction::Outgoing => &mut self.sent,
+            Direction::Incoming => &mut self.received,
+        };
+        let n = *index;
+        *index += 1;
+
+        let mut faults: Vec<Fault> = self
+            .schedule
+            .iter()
+            .filter(|(d, i, _)| *d == direction && *i == n)
+            .map(|(_, _, f)| f.clone())
+            .collect();
+
+        let config = match direction {
+            Direction::Outgoing => self.outgoing.clone(),
+            Direction::Incoming => self.incoming.clone(),
+        };
+
+        if self.rng.chance(config.disconnect) {
+            faults.push(Fault::Disconnect);
+        }
+        if self.rng.chance(config.drop) {
+            faults.push(Fault::Drop);
+        }
+        if self.rng.chance(config.truncate) {
+            faults.push(Fault::Truncate);
+        }
+        if self.rng.chance(config.corrupt) {
+            faults.push(Fault::Corrupt);
+        }
+        if self.rng.chance(config.delay) {
+            let max = config.max_delay.as_micros() as usize;
+            let delay = Duration::from_micros(self.rng.below(max + 1) as u64);
+            faults.push(Fault::Delay(delay));
+        }
+        if self.rng.chance(config.duplicate) {
+            faults.push(Fault::Duplicate);
+        }
+        if self.rng.chance(config.reorder) {
+            faults.push(Fault::Reorder);
+        }
+
+        faults
+    }
+
+    fn process(&mut self, direction: Direction, mut packet: Vec<u8>) -> Outcome {
+        if !self.connected {
+            return Outcome::default();
+        }
+
+        let faults = self.faults(direction);
+
+        if faults.contains(&Fault::Disconnect) {
+            self.stats.disconnects += 1;
+            self.disconnect();
+            return Outcome {
+                deliveries: Vec::new(),
+                disconnect: true,
+            };
+        }
+
+        if faults.contains(&Fault::Drop) {
+            self.stats.dropped += 1;
+            return Outcome::default();
+        }
+
+        if faults.contains(&Fault::Truncate) && packet.len() > 1 {
+            self.stats.truncated += 1;
+            let len = self.rng.below(packet.len());
+            packet.truncate(len);
+        }
+
+        if faults.contains(&Fault::Corrupt) && !packet.is_empty() {
+            self.stats.corrupted += 1;
+            let i = self.rng.below(packet.len());
+            packet[i] ^= 1 << self.rng.below(8);
+        }
+
+        let mut delay = Duration::from_secs(0);
+        for f in faults.iter() {
+            if let Fault::Delay(d) = f {
+                self.stats.delayed += 1;
+                delay = *d;
+            }
+        }
+
+        let mut packets = vec![packet.clone()];
+        if faults.contains(&Fault::Duplicate) {
+            self.stats.duplicated += 1;
+            packets.push(packet);
+        }
+
+        let held = match direction {
+            Direction::Outgoing => &mut self.held_outgoing,
+            Direction::Incoming => &mut self.held_incoming,
+        };
+
+        if faults.contains(&Fault::Reorder) && held.is_empty() {
+            self.stats.reordered += 1;
+            *held = packets;
+            return Outcome::default();
+        }
+
+        packets.append(held);
+
+        Outcome {
+            deliveries: packets.into_iter().map(|p| (delay, p)).collect(),
+            disconnect: false,
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.drop_link();
+        if let Some(tx) = &self.events {
+            let _ = tx.send(TransportEvent::Disconnected);
+        }
+    }
+
+    // Forgets everything still in flight on the current link
+    fn drop_link(&mut self) {
+        self.connected = false;
+        self.link += 1;
+        self.held_outgoing.clear();
+        self.held_incoming.clear();
+    }
+
+    // Whether a packet delayed on `link` may still be delivered
+    fn deliverable(&self, link: u64) -> bool {
+        self.connected && self.link == link
+    }
+}
+
+impl<T: CameraTransport> FaultyTransport<T> {
+    /// Wraps a transport, no faults are injected until they are configured
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - the CameraTransport to mangle traffic for
+    /// * `seed` - u64 seed for the random faults
+    pub fn new(inner: T, seed: u64) -> FaultyTransport<T> {
+        FaultyTransport {
+            inner,
+            state: Arc::new(Mutex::new(FaultState {
+                rng: Rng(seed),
+                outgoing: FaultConfig::default(),
+                incoming: FaultConfig::default(),
+                schedule: Vec::new(),
+
+                sent: 0,
+                received: 0,
+                held_outgoing: Vec::new(),
+                held_incoming: Vec::new(),
+
+                connected: false,
+                link: 0,
+                events: None,
+                stats: FaultStats::default(),
+            })),
+        }
+    }
+
+    /// Sets the random faults for packets sent to the camera
+    pub fn with_outgoing(self, config: FaultConfig) -> FaultyTransport<T> {
+        self.state.lock().unwrap().outgoing = config;
+        self
+    }
+
+    /// Sets the random faults for packets coming from the camera
+    pub fn with_incoming(self, config: FaultConfig) -> FaultyTransport<T> {
+        self.state.lock().unwrap().incoming = config;
+        self
+    }
+
+    /// Schedules a fault for a specific packet
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - Direction the packet travels
+    /// * `packet` - u64 index of the packet in that direction, counting from 0
+    /// * `fault` - Fault to apply to it
+    pub fn schedule(self, direction: Direction, packet: u64, fault: Fault) -> FaultyTransport<T> {
+        self.state
+            .lock()
+            .unwrap()
+            .schedule
+            .push((direction, packet, fault));
+        self
+    }
+
+    /// Drops the link right away, as if the camera went out of range
+    pub fn disconnect_now(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.stats.disconnects += 1;
+        state.disconnect();
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.state.lock().unwrap().stats.clone()
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+#[async_trait]
+impl<T: CameraTransport> CameraTransport for FaultyTransport<T> {
+    type Error = T::Error;
+
+    async fn connect(&mut self, timeout: Duration) -> Result<(), T::Error> {
+        self.inner.connect(timeout).await?;
+        self.state.lock().unwrap().connected = true;
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), T::Error> {
+        self.state.lock().unwrap().drop_link();
+        self.inner.disconnect().await
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), T::Error> {
+        let (outcome, link) = {
+            let mut state = self.state.lock().unwrap();
+            if !state.connected {
+                return Err(CameraControlError::Disconnected.into());
+            }
+            (
+                state.process(Direction::Outgoing, packet.to_vec()),
+                state.link,
+            )
+        };
+
+        if outcome.disconnect {
+            return Err(CameraControlError::Disconnected.into());
+        }
+
+        for (delay, packet) in outcome.deliveries {
+            if delay > Duration::from_secs(0) {
+                tokio::time::sleep(delay).await;
+                if !self.state.lock().unwrap().deliverable(link) {
+                    return Err(CameraControlError::Disconnected.into());
+                }
+            }
+            self.inner.send(&packet).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, T::Error> {
+        let mut inner = self.inner.events().await?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.state.lock().unwrap().events = Some(tx.clone());
+
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            while let Some(event) = inner.next().await {
+                let packet = match event {
+                    TransportEvent::Packet(p) => p,
+                    other => {
+                        if state.lock().unwrap().connected {
+                            let _ = tx.send(other);
+                        }
+                        continue;
+                    }
+                };
+
+                let (outcome, link) = {
+                    let mut state = state.lock().unwrap();
+                    (state.process(Direction::Incoming, packet), state.link)
+                };
+                for (delay, packet) in outcome.deliveries {
+                    if delay > Duration::from_secs(0) {
+                        let tx = tx.clone();
+                        let state = state.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            // The link went down while this was in flight
+                            if state.lock().unwrap().deliverable(link) {
+                                let _ = tx.send(TransportEvent::Packet(packet));
+                            }
+                        });
+                    } else {
+                        let _ = tx.send(TransportEvent::Packet(packet));
+                    }
+                }
+            }
+        });
+
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+}
+
+// SplitMix64, small and good enough to make faults reproducible from a seed
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            return 0;
+        }
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::SimulatedCamera;
+
+    // Which packets came out of each send, by index
+    fn deliveries(seed: u64, config: FaultConfig) -> Vec<Vec<u8>> {
+        let transport = FaultyTransport::new(SimulatedCamera::new(), seed).with_outgoing(config);
+        let mut state = transport.state.lock().unwrap();
+        state.connected = true;
+
+        (0..200u8)
+            .map(|i| {
+                state
+                    .process(Direction::Outgoing, vec![i])
+                    .deliveries
+                    .into_iter()
+                    .map(|(_, p)| p[0])
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_faults() {
+        let config = FaultConfig {
+            drop: 0.2,
+            reorder: 0.2,
+            ..FaultConfig::default()
+        };
+
+        let first = deliveries(42, config.clone());
+        assert_eq!(first, deliveries(42, config.clone()));
+        assert_ne!(first, deliveries(43, config));
+
+        // Both faults actually happened
+        assert!(first.iter().any(|d| d.is_empty()));
+        assert!(first.iter().any(|d| d.len() == 2 && d[0] > d[1]));
+    }
+
+    async fn delayed_after_disconnect(real: bool) -> (Vec<TransportEvent>, FaultStats) {
+        let mut transport =
+            (0..1000).fold(FaultyTransport::new(SimulatedCamera::new(), 1), |t, i| {
+                t.schedule(
+                    Direction::Incoming,
+                    i,
+                    Fault::Delay(Duration::from_millis(50)),
+                )
+            });
+        transport.connect(Duration::from_secs(1)).await.unwrap();
+        let mut events = transport.events().await.unwrap();
+
+        // Let the camera's full state get picked up and go in flight
+        while transport.stats().delayed == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        if real {
+            transport.disconnect().await.unwrap();
+        } else {
+            transport.disconnect_now();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut received = Vec::new();
+        while let Some(Some(event)) = futures::FutureExt::now_or_never(events.next()) {
+            received.push(event);
+        }
+        (received, transport.stats())
+    }
+
+    #[tokio::test]
+    async fn drops_delayed_packets_after_disconnect() {
+        for real in [false, true] {
+            let (received, stats) = delayed_after_disconnect(real).await;
+            assert!(stats.delayed > 0);
+            assert!(
+                !received
+                    .iter()
+                    .any(|e| matches!(e, TransportEvent::Packet(_))),
+                "{:?}",
+                received
+            );
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::command::Command;
+use crate::key::CommandKey;
+use crate::parameters::{CATEGORIES, PARAMETERS};
+use crate::rawcommand::{CommandError, Operation, RawCommand};
+
+/// A category as described in PROTOCOL.json
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CategoryInfo {
+    pub id: u8,
+    pub name: &'static str,
+    pub normalized_name: &'static str,
+}
+
+impl CategoryInfo {
+    /// Looks up a category by id, None if PROTOCOL.json does not define it
+    pub fn lookup(category: u8) -> Option<&'static CategoryInfo> {
+        CATEGORIES.iter().find(|c| c.id == category)
+    }
+}
+
+/// A parameter as described in PROTOCOL.json
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParameterInfo {
+    pub category: u8,
+    pub parameter: u8,
+    pub name: &'static str,
+    pub normalized_name: &'static str,
+
+    /// Type as named in the protocol, like "int16" or "fixed16"
+    pub data_type: &'static str,
+
+    /// Names of the elements of the value, empty for single values
+    pub index: &'static [&'static str],
+
+    pub interpretation: Option<&'static str>,
+    pub minimum: Option<f64>,
+    pub maximum: Option<f64>,
+}
+
+impl ParameterInfo {
+    /// Looks up a parameter, None if PROTOCOL.json does not define it
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - CommandKey or Key like this: keys::video::ISO
+    pub fn lookup(key: impl Into<CommandKey>) -> Option<&'static ParameterInfo> {
+        let key = key.into();
+        PARAMETERS
+            .iter()
+            .find(|p| p.category == key.category && p.parameter == key.parameter)
+    }
+
+    pub fn key(&self) -> CommandKey {
+        CommandKey::new(self.category, self.parameter)
+    }
+
+    /// Number of values the parameter holds, 0 for triggers and strings
+    pub fn elements(&self) -> usize {
+        match self.data_type {
+            "int8" | "int16" | "int32" | "int64" | "fixed16" => self.index.len().max(1),
+            _ => 0,
+        }
+    }
+
+    /// Builds a command for this parameter out of plain numbers, one per element
+    ///
+    /// Values are rounded to the parameter's type. Triggers, and the types the protocol
+    /// carries without a value, take no values. Strings can not be built this way.
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - &[f64] like this: &[640.0] for keys::video::ISO
+    pub fn command(&self, values: &[f64]) -> Result<Command, CommandError> {
+        if self.data_type == "string" {
+            return Err(CommandError::NotNumeric);
+        }
+        if values.len() != self.elements() {
+            return Err(CommandError::WrongValueCount(values.len(), self.elements()));
+        }
+
+        let mut data = Vec::new();
+        for v in values {
+            match self.data_type {
+                "int8" => data.push(v.round() as i8 as u8),
+                "int16" => data.extend_from_slice(&(v.round() as i16).to_le_bytes()),
+                "int32" => data.extend_from_slice(&(v.round() as i32).to_le_bytes()),
+                "int64" => data.extend_from_slice(&(v.round() as i64).to_le_bytes()),
+                _ => data.extend_from_slice(&((v * 2048.0).round() as i16).to_le_bytes()),
+            }
+        }
+
+        let data_type = match self.data_type {
+            "int8" => 1,
+            "int16" => 2,
+            "int32" => 3,
+            "int64" => 4,
+            "fixed16" => 128,
+            _ => {
+                data.push(0);
+                0
+            }
+        };
+
+        let raw = RawCommand {
+            destination_device: 255,
+            command_id: 0,
+            category: self.category,
+            parameter: self.parameter,
+            data_type,
+            operation: Operation::AssignValue.id(),
+            data,
+        };
+
+        Command::from_raw(&raw.to_bytes())
+    }
+
+    /// Returns the values of a command as plain numbers, the reverse of command
+    ///
+    /// Empty for strings, triggers and commands for other parameters.
+    pub fn values(&self, command: &Command) -> Vec<f64> {
+        if CommandKey::from(command) != self.key() {
+            return Vec::new();
+        }
+
+        let data = command.to_bytes();
+        match command.raw_type() {
+            1 => data.iter().map(|b| *b as i8 as f64).collect(),
+            2 => data
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f64)
+                .collect(),
+            3 => data
+                .chunks_exact(4)
+                .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                .collect(),
+            4 => data
+                .chunks_exact(8)
+                .map(|b| {
+                    i64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f64
+                })
+                .collect(),
+            128 => data
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f64 / 2048.0)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
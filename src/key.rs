@@ -0,0 +1,88 @@
+use crate::command::Command;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Identifies a single camera parameter by its category and parameter id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CommandKey {
+    pub category: u8,
+    pub parameter: u8,
+}
+
+impl CommandKey {
+    pub const fn new(category: u8, parameter: u8) -> Self {
+        CommandKey {
+            category,
+            parameter,
+        }
+    }
+}
+
+impl fmt::Display for CommandKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.category, self.parameter)
+    }
+}
+
+impl From<&Command> for CommandKey {
+    fn from(cmd: &Command) -> Self {
+        cmd.key()
+    }
+}
+
+impl Command {
+    /// Returns the key identifying which parameter this command carries
+    pub fn key(&self) -> CommandKey {
+        CommandKey::new(self.id(), self.parameter_id())
+    }
+}
+
+/// A CommandKey that also knows the type of the value stored under it
+///
+/// Generated for every parameter in the protocol, see the `keys` module.
+pub struct Key<T> {
+    key: CommandKey,
+    extract: fn(&Command) -> Option<T>,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    pub const fn new(category: u8, parameter: u8, extract: fn(&Command) -> Option<T>) -> Self {
+        Key {
+            key: CommandKey::new(category, parameter),
+            extract,
+            _type: PhantomData,
+        }
+    }
+
+    /// Returns the untyped key
+    pub fn key(&self) -> CommandKey {
+        self.key
+    }
+
+    /// Pulls the value out of a command, if the command belongs to this key
+    pub fn extract(&self, cmd: &Command) -> Option<T> {
+        (self.extract)(cmd)
+    }
+}
+
+impl<T> Clone for Key<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Key<T> {}
+
+impl<T> fmt::Debug for Key<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Key").field(&self.key).finish()
+    }
+}
+
+impl<T> From<Key<T>> for CommandKey {
+    fn from(key: Key<T>) -> Self {
+        key.key
+    }
+}
@@ -3,12 +3,63 @@ pub mod blecamera;
 #[cfg(feature = "ble")]
 pub use blecamera::BluetoothCamera;
 
+pub mod atem;
+#[cfg(feature = "bridge")]
+pub mod bridge;
+pub mod btsnoop;
+pub mod cache;
+pub mod camera;
+#[cfg(feature = "cec")]
+pub mod cec;
+pub mod characteristics;
+pub mod defs;
+pub mod dissector;
+pub mod dmx;
 pub mod error;
+pub mod fault;
+pub mod info;
+pub mod key;
+#[cfg(feature = "midi")]
+pub mod midi;
+pub mod osc;
+pub mod pcapng;
+pub mod queue;
 pub mod rawcommand;
+pub mod recording;
+#[cfg(feature = "bridge")]
+pub mod remote;
+#[cfg(feature = "rest")]
+pub mod rest;
+pub mod sdi;
+pub mod serial;
+pub mod simulator;
+pub mod subscription;
+pub mod transport;
+
+pub mod cec_parameters {
+    include!(concat!(env!("OUT_DIR"), "/cec_parameters.rs"));
+}
 
 pub mod command {
     include!(concat!(env!("OUT_DIR"), "/command.rs"));
 }
 
+pub mod keys {
+    include!(concat!(env!("OUT_DIR"), "/keys.rs"));
+}
+
+pub mod parameters {
+    include!(concat!(env!("OUT_DIR"), "/parameters.rs"));
+}
+
+pub mod state {
+    include!(concat!(env!("OUT_DIR"), "/state.rs"));
+}
+
 //Exports
+pub use camera::Camera;
+pub use key::{CommandKey, Key};
 pub use rawcommand::Operation;
+pub use simulator::SimulatedCamera;
+pub use state::CameraState;
+pub use transport::{CameraTransport, TransportEvent};
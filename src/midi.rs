@@ -0,0 +1,1109 @@
+use crate::camera::Camera;
+use crate::command::Command;
+use crate::error::MidiError;
+use crate::info::ParameterInfo;
+use crate::key::CommandKey;
+use crate::keys;
+use crate::rawcommand::Operation;
+use crate::transport::CameraTransport;
+use midir::{MidiIO, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+/// Name the virtual ports are created with when the mapping names no device
+pub const CLIENT_NAME: &str = "Blackmagic Camera Control";
+
+/// How close a fader has to come to the camera's value to pick it up with soft takeover
+const TAKEOVER_WINDOW: f64 = 2.0 / 127.0;
+
+/// A channel message, channels are numbered 1 to 16 like on the devices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiMessage {
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+}
+
+impl MidiMessage {
+    /// Parses a single message, None for everything that is not one of the above
+    ///
+    /// A note on with velocity 0 is returned as the note off it means.
+    pub fn from_bytes(data: &[u8]) -> Option<MidiMessage> {
+        let (status, data1, data2) = match data {
+            [status, data1, data2, ..] => (*status, *data1 & 0x7f, *data2 & 0x7f),
+            _ => return None,
+        };
+        let channel = (status & 0x0f) + 1;
+
+        match status & 0xf0 {
+            0x80 => Some(MidiMessage::NoteOff {
+                channel,
+                note: data1,
+                velocity: data2,
+            }),
+            0x90 if data2 == 0 => Some(MidiMessage::NoteOff {
+                channel,
+                note: data1,
+                velocity: 0,
+            }),
+            0x90 => Some(MidiMessage::NoteOn {
+                channel,
+                note: data1,
+                velocity: data2,
+            }),
+            0xb0 => Some(MidiMessage::ControlChange {
+                channel,
+                controller: data1,
+                value: data2,
+            }),
+            0xe0 => Some(MidiMessage::PitchBend {
+                channel,
+                value: (data2 as u16) << 7 | data1 as u16,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => vec![0x80 | status_channel(channel), note, velocity],
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => vec![0x90 | status_channel(channel), note, velocity],
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => vec![0xb0 | status_channel(channel), controller, value],
+            MidiMessage::PitchBend { channel, value } => vec![
+                0xe0 | status_channel(channel),
+                (value & 0x7f) as u8,
+                (value >> 7 & 0x7f) as u8,
+            ],
+        }
+    }
+}
+
+fn status_channel(channel: u8) -> u8 {
+    channel.saturating_sub(1) & 0x0f
+}
+
+/// Kind of control a mapping listens to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlType {
+    Cc,
+    Note,
+    PitchBend,
+}
+
+/// What a control does to its parameter
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingMode {
+    /// Faders and knobs with a fixed position, assigns the value
+    Absolute,
+
+    /// Endless encoders, offsets the value by step per tick
+    Relative,
+
+    /// Buttons, fires the trigger or assigns the mapping's value when pressed
+    Trigger,
+}
+
+/// How an endless encoder encodes a turn in a CC value
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// 1 is one tick up, 127 one tick down
+    TwosComplement,
+
+    /// 65 is one tick up, 63 one tick down
+    BinaryOffset,
+
+    /// 1 is one tick up, 65 one tick down
+    SignBit,
+}
+
+impl Encoding {
+    fn ticks(&self, value: u8) -> f64 {
+        match self {
+            Encoding::TwosComplement if value < 64 => value as f64,
+            Encoding::TwosComplement => value as f64 - 128.0,
+            Encoding::BinaryOffset => value as f64 - 64.0,
+            Encoding::SignBit if value & 0x40 != 0 => -((value & 0x3f) as f64),
+            Encoding::SignBit => value as f64,
+        }
+    }
+}
+
+/// Response of an absolute control, applied to its position from 0 to 1
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Curve {
+    Linear,
+
+    /// Fine control at the bottom of the range
+    Exponential,
+
+    /// Fine control at the top of the range
+    Logarithmic,
+}
+
+impl Curve {
+    fn apply(&self, x: f64) -> f64 {
+        match self {
+            Curve::Linear => x,
+            Curve::Exponential => x * x,
+            Curve::Logarithmic => x.sqrt(),
+        }
+    }
+
+    fn invert(&self, y: f64) -> f64 {
+        match self {
+            Curve::Linear => y,
+            Curve::Exponential => y.sqrt(),
+            Curve::Logarithmic => y * y,
+        }
+    }
+}
+
+/// Binds one control to one element of a camera parameter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MidiMapping {
+    #[serde(rename = "type")]
+    pub control: ControlType,
+
+    /// 1 to 16
+    #[serde(default = "first_channel")]
+    pub channel: u8,
+
+    /// Controller or note number, unused for pitch bend
+    #[serde(default)]
+    pub number: u8,
+
+    /// Normalized name like this: lens_focus
+    pub parameter: String,
+
+    /// Element of parameters with several, like "luma" for color_correction_lift_adjust
+    #[serde(default)]
+    pub element: Option<String>,
+
+    /// Absolute unless the parameter is a trigger
+    #[serde(default)]
+    pub mode: Option<MappingMode>,
+
+    /// Range the control covers, the one in PROTOCOL.json unless set
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+
+    #[serde(default = "linear")]
+    pub curve: Curve,
+
+    /// How much one encoder tick changes the value, 1/127 of the range unless set
+    #[serde(default)]
+    pub step: Option<f64>,
+
+    #[serde(default = "twos_complement")]
+    pub encoding: Encoding,
+
+    /// Value a trigger mapping assigns to parameters that are not triggers themselves
+    #[serde(default)]
+    pub value: Option<f64>,
+
+    /// Ignores an absolute control until it reaches the camera's value
+    #[serde(default)]
+    pub takeover: bool,
+
+    /// Sends camera values back to the control, for motorised faders and LED rings
+    #[serde(default)]
+    pub feedback: bool,
+}
+
+fn first_channel() -> u8 {
+    1
+}
+
+fn linear() -> Curve {
+    Curve::Linear
+}
+
+fn twos_complement() -> Encoding {
+    Encoding::TwosComplement
+}
+
+/// A mapping file, JSON like this:
+///
+/// ```json
+/// {
+///     "input": "nanoKONTROL2",
+///     "mappings": [
+///         { "type": "cc", "number": 0, "parameter": "lens_focus", "curve": "exponential" },
+///         { "type": "cc", "number": 16, "parameter": "video_iso", "mode": "relative", "step": 100 },
+///         { "type": "note", "number": 41, "parameter": "lens_instantaneous_autofocus" }
+///     ]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MidiMap {
+    /// Part of the name of the port to read, a virtual port is created unless set
+    #[serde(default)]
+    pub input: Option<String>,
+
+    /// Part of the name of the port feedback goes to, a virtual port is created unless set
+    #[serde(default)]
+    pub output: Option<String>,
+
+    /// Camera the commands are addressed to
+    #[serde(default = "broadcast_destination")]
+    pub destination: u8,
+
+    pub mappings: Vec<MidiMapping>,
+}
+
+fn broadcast_destination() -> u8 {
+    255
+}
+
+impl MidiMap {
+    /// Reads a mapping file from disk
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MidiMap, MidiError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Binding {
+    mapping: MidiMapping,
+    info: &'static ParameterInfo,
+    element: usize,
+    mode: MappingMode,
+    min: f64,
+    max: f64,
+    step: f64,
+
+    engaged: bool,
+    last_input: Option<f64>,
+    last_sent: Option<f64>,
+}
+
+impl Binding {
+    fn new(mapping: MidiMapping) -> Result<Binding, MidiError> {
+        let invalid = |why: &str| MidiError::InvalidMapping(mapping.parameter.clone(), why.into());
+
+        let info = keys::from_normalized_name(&mapping.parameter)
+            .and_then(ParameterInfo::lookup)
+            .ok_or_else(|| MidiError::UnknownParameter(mapping.parameter.clone()))?;
+
+        if mapping.channel < 1 || mapping.channel > 16 {
+            return Err(invalid("channel out of range"));
+        }
+        if mapping.number > 127 {
+            return Err(invalid("number out of range"));
+        }
+
+        let element = match &mapping.element {
+            Some(name) => info
+                .index
+                .iter()
+                .position(|i| i == name)
+                .ok_or_else(|| invalid(&format!("no element named {}", name)))?,
+            None => 0,
+        };
+
+        let mode = match mapping.mode {
+            Some(mode) => mode,
+            None if info.elements() == 0 => MappingMode::Trigger,
+            None => MappingMode::Absolute,
+        };
+
+        match mode {
+            MappingMode::Absolute | MappingMode::Relative if info.elements() == 0 => {
+                return Err(invalid("only triggers can drive it"))
+            }
+            MappingMode::Relative if mapping.control != ControlType::Cc => {
+                return Err(invalid("relative mode needs a cc"))
+            }
+            MappingMode::Trigger if info.elements() > 0 && mapping.value.is_none() => {
+                return Err(invalid("a trigger needs a value to assign"))
+            }
+            _ => {}
+        }
+
+        let min = mapping.min.or(info.minimum).unwrap_or(0.0);
+        let max = mapping.max.or(info.maximum).unwrap_or(1.0);
+
+        // Integer parameters would round a smaller step away
+        let step = mapping.step.unwrap_or_else(|| match info.data_type {
+            "fixed16" => (max - min) / 127.0,
+            _ => ((max - min) / 127.0).round().max(1.0),
+        });
+
+        Ok(Binding {
+            mapping,
+            info,
+            element,
+            mode,
+            min,
+            max,
+            step,
+
+            engaged: false,
+            last_input: None,
+            last_sent: None,
+        })
+    }
+
+    /// Returns the position of the control from 0 to 1 if the message comes from it
+    fn position(&self, message: &MidiMessage) -> Option<f64> {
+        let m = &self.mapping;
+        match (*message, m.control) {
+            (
+                MidiMessage::ControlChange {
+                    channel,
+                    controller,
+                    value,
+                },
+                ControlType::Cc,
+            ) if channel == m.channel && controller == m.number => Some(value as f64 / 127.0),
+            (
+                MidiMessage::NoteOn {
+                    channel,
+                    note,
+                    velocity,
+                },
+                ControlType::Note,
+            ) if channel == m.channel && note == m.number => Some(velocity as f64 / 127.0),
+            (MidiMessage::NoteOff { channel, note, .. }, ControlType::Note)
+                if channel == m.channel && note == m.number =>
+            {
+                Some(0.0)
+            }
+            (MidiMessage::PitchBend { channel, value }, ControlType::PitchBend)
+                if channel == m.channel =>
+            {
+                Some(value as f64 / 16383.0)
+            }
+            _ => None,
+        }
+    }
+
+    fn normalize(&self, value: f64) -> f64 {
+        if self.max == self.min {
+            return 0.0;
+        }
+        self.mapping
+            .curve
+            .invert(((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0))
+    }
+
+    /// Current values of every element, from the camera if it reported them
+    fn current(&self, current: &dyn Fn(CommandKey) -> Option<Command>) -> Option<Vec<f64>> {
+        current(self.info.key())
+            .map(|c| self.info.values(&c))
+            .filter(|v| v.len() == self.info.elements())
+    }
+
+    fn assign(
+        &mut self,
+        value: f64,
+        current: &dyn Fn(CommandKey) -> Option<Command>,
+    ) -> Result<(Operation, Command), MidiError> {
+        let mut values = self
+            .current(current)
+            .unwrap_or_else(|| vec![0.0; self.info.elements()]);
+        values[self.element] = value;
+        self.last_sent = Some(value);
+
+        Ok((Operation::AssignValue, self.info.command(&values)?))
+    }
+
+    fn handle(
+        &mut self,
+        message: &MidiMessage,
+        current: &dyn Fn(CommandKey) -> Option<Command>,
+    ) -> Result<Option<(Operation, Command)>, MidiError> {
+        let position = match self.position(message) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        match self.mode {
+            MappingMode::Absolute => {
+                if self.mapping.takeover && !self.engaged {
+                    let camera = self
+                        .current(current)
+                        .map(|v| self.normalize(v[self.element]));
+
+                    self.engaged = match (camera, self.last_input) {
+                        (None, _) => true,
+                        (Some(c), _) if (position - c).abs() <= TAKEOVER_WINDOW => true,
+                        // Moved past the camera's value between two messages
+                        (Some(c), Some(last)) => (last - c).signum() != (position - c).signum(),
+                        (Some(_), None) => false,
+                    };
+                    self.last_input = Some(position);
+
+                    if !self.engaged {
+                        return Ok(None);
+                    }
+                }
+                self.last_input = Some(position);
+
+                let value = self.min + (self.max - self.min) * self.mapping.curve.apply(position);
+                self.assign(value, current).map(Some)
+            }
+            MappingMode::Relative => {
+                let ticks = match message {
+                    MidiMessage::ControlChange { value, .. } => self.mapping.encoding.ticks(*value),
+                    _ => return Ok(None),
+                };
+                if ticks == 0.0 {
+                    return Ok(None);
+                }
+
+                let mut values = vec![0.0; self.info.elements()];
+                values[self.element] = ticks * self.step;
+                Ok(Some((Operation::OffsetValue, self.info.command(&values)?)))
+            }
+            MappingMode::Trigger => {
+                // Buttons fire when pressed, not when released
+                if position == 0.0 {
+                    return Ok(None);
+                }
+
+                match self.mapping.value {
+                    Some(value) => self.assign(value, current).map(Some),
+                    None => Ok(Some((Operation::AssignValue, self.info.command(&[])?))),
+                }
+            }
+        }
+    }
+
+    fn feedback(&mut self, command: &Command) -> Option<MidiMessage> {
+        if self.mode == MappingMode::Trigger {
+            return None;
+        }
+        let value = *self.info.values(command).get(self.element)?;
+
+        // The camera confirming what we sent, the control is already there
+        let tolerance = (self.max - self.min).abs() / 254.0;
+        if let Some(sent) = self.last_sent {
+            if (value - sent).abs() <= tolerance {
+                return None;
+            }
+        }
+        self.last_sent = None;
+
+        // Changed from somewhere else, the control has to catch up again
+        self.engaged = false;
+
+        if !self.mapping.feedback {
+            return None;
+        }
+
+        let position = self.normalize(value);
+        let m = &self.mapping;
+        Some(match m.control {
+            ControlType::Cc => MidiMessage::ControlChange {
+                channel: m.channel,
+                controller: m.number,
+                value: (position * 127.0).round() as u8,
+            },
+            ControlType::Note => MidiMessage::NoteOn {
+                channel: m.channel,
+                note: m.number,
+                velocity: (position * 127.0).round() as u8,
+            },
+            ControlType::PitchBend => MidiMessage::PitchBend {
+                channel: m.channel,
+                value: (position * 16383.0).round() as u16,
+            },
+        })
+    }
+}
+
+/// Turns MIDI messages into camera commands and camera values into MIDI feedback
+///
+/// Holds no connections, so it can be driven by any MIDI source. MidiController wires
+/// it to ports and a camera.
+#[derive(Debug, Clone)]
+pub struct MidiMapper {
+    destination: u8,
+    bindings: Vec<Binding>,
+}
+
+impl MidiMapper {
+    /// Checks every mapping against PROTOCOL.json and returns a new MidiMapper
+    pub fn new(map: &MidiMap) -> Result<MidiMapper, MidiError> {
+        Ok(MidiMapper {
+            destination: map.destination,
+            bindings: map
+                .mappings
+                .iter()
+                .cloned()
+                .map(Binding::new)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Camera the commands are addressed to
+    pub fn destination(&self) -> u8 {
+        self.destination
+    }
+
+    /// Returns the writes a message asks for
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - MidiMessage from the controller
+    /// * `current` - looks up the latest value the camera reported for a parameter, which
+    ///   absolute controls need for soft takeover and to keep the elements they do not drive
+    pub fn handle(
+        &mut self,
+        message: &MidiMessage,
+        current: &dyn Fn(CommandKey) -> Option<Command>,
+    ) -> Result<Vec<(Operation, Command)>, MidiError> {
+        let mut writes = Vec::new();
+        for binding in self.bindings.iter_mut() {
+            if let Some(write) = binding.handle(message, current)? {
+                writes.push(write);
+            }
+        }
+        Ok(writes)
+    }
+
+    /// Returns the messages that move the controls to a value the camera reported
+    ///
+    /// Values that only confirm what a control just sent produce nothing, so a motorised
+    /// fader is not pulled back while it is being moved.
+    pub fn feedback(&mut self, command: &Command) -> Vec<MidiMessage> {
+        self.bindings
+            .iter_mut()
+            .filter_map(|b| b.feedback(command))
+            .collect()
+    }
+}
+
+/// Controls a camera from MIDI fader boxes and control surfaces
+///
+/// Reads the port the mapping names, or a new virtual port other software can connect to,
+/// and writes what the mappings turn the messages into. Mappings with feedback get camera
+/// values sent back on the output port.
+pub struct MidiController<T: CameraTransport> {
+    camera: Arc<Mutex<Camera<T>>>,
+    map: MidiMap,
+    mapper: Arc<std::sync::Mutex<MidiMapper>>,
+
+    input: Option<MidiInputConnection<()>>,
+    tasks: Vec<JoinHandle<()>>,
+    errors: mpsc::Sender<MidiError>,
+    error_receiver: Option<mpsc::Receiver<MidiError>>,
+}
+
+impl<T: CameraTransport> MidiController<T> {
+    /// Takes a camera and a mapping, nothing is read until started
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - Camera to control, connected or not
+    /// * `map` - MidiMap like MidiMap::open returns
+    pub fn new(camera: Camera<T>, map: MidiMap) -> Result<MidiController<T>, MidiError> {
+        MidiController::from_shared(Arc::new(Mutex::new(camera)), map)
+    }
+
+    /// Same as new but for a camera that is shared already, such as by a BridgeServer
+    pub fn from_shared(
+        camera: Arc<Mutex<Camera<T>>>,
+        map: MidiMap,
+    ) -> Result<MidiController<T>, MidiError> {
+        let mapper = MidiMapper::new(&map)?;
+        let (errors, error_receiver) = mpsc::channel(16);
+
+        Ok(MidiController {
+            camera,
+            map,
+            mapper: Arc::new(std::sync::Mutex::new(mapper)),
+
+            input: None,
+            tasks: Vec::new(),
+            errors,
+            error_receiver: Some(error_receiver),
+        })
+    }
+
+    /// Gives you the camera, to connect it or use it alongside MIDI
+    pub fn camera(&self) -> Arc<Mutex<Camera<T>>> {
+        self.camera.clone()
+    }
+
+    /// Takes the receiver of the errors the running controller runs into
+    ///
+    /// Mappings that cannot be turned into a command and writes the camera does not take
+    /// end up here. Nothing waits for it to be read, errors past the first 16 unread ones
+    /// are dropped.
+    pub fn errors(&mut self) -> Option<mpsc::Receiver<MidiError>> {
+        self.error_receiver.take()
+    }
+
+    /// Opens the ports and starts controlling the camera, must run inside a tokio runtime
+    pub fn start(&mut self) -> Result<(), MidiError> {
+        self.stop();
+
+        // Both ports are open before anything runs, so a port that fails leaves nothing behind
+        let output = if self.map.mappings.iter().any(|m| m.feedback) {
+            Some(open_output(self.map.output.as_deref())?)
+        } else {
+            None
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.input = Some(open_input(self.map.input.as_deref(), move |data| {
+            let _ = tx.send(data.to_vec());
+        })?);
+
+        let camera = self.camera.clone();
+        let mapper = self.mapper.clone();
+        let errors = self.errors.clone();
+        self.tasks.push(tokio::spawn(async move {
+            let cache = camera.lock().await.cache();
+
+            while let Some(data) = rx.recv().await {
+                let message = match MidiMessage::from_bytes(&data) {
+                    Some(m) => m,
+                    None => continue,
+                };
+
+                let (destination, writes) = {
+                    let cache = cache.read().await;
+                    let current = |key: CommandKey| cache.command(key).cloned();
+                    let mut mapper = mapper.lock().unwrap();
+                    (mapper.destination(), mapper.handle(&message, &current))
+                };
+                let writes = match writes {
+                    Ok(writes) if writes.is_empty() => continue,
+                    Ok(writes) => writes,
+                    Err(e) => {
+                        let _ = errors.try_send(e);
+                        continue;
+                    }
+                };
+
+                let mut camera = camera.lock().await;
+                for (operation, command) in writes {
+                    if let Err(e) = camera.write(destination, operation, command).await {
+                        let _ = errors.try_send(MidiError::WriteError(e.to_string()));
+                    }
+                }
+            }
+        }));
+
+        if let Some(mut output) = output {
+            let camera = self.camera.clone();
+            let mapper = self.mapper.clone();
+            let errors = self.errors.clone();
+            self.tasks.push(tokio::spawn(async move {
+                let mut updates = camera.lock().await.updates().await;
+
+                loop {
+                    let commands = match updates.recv().await {
+                        Ok(command) => vec![command],
+                        // Missed updates are replaced by everything the camera has reported
+                        Err(RecvError::Lagged(_)) => camera.lock().await.commands().await,
+                        Err(RecvError::Closed) => return,
+                    };
+
+                    let messages: Vec<MidiMessage> = {
+                        let mut mapper = mapper.lock().unwrap();
+                        commands.iter().flat_map(|c| mapper.feedback(c)).collect()
+                    };
+                    for message in messages {
+                        if let Err(e) = output.send(&message.to_bytes()) {
+                            let _ = errors.try_send(MidiError::PortError(e.to_string()));
+                        }
+                    }
+                }
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Closes the ports, the camera stays connected
+    pub fn stop(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        if let Some(input) = self.input.take() {
+            input.close();
+        }
+    }
+}
+
+impl<T: CameraTransport> fmt::Debug for MidiController<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MidiController")
+            .field("map", &self.map)
+            .field("started", &self.input.is_some())
+            .finish()
+    }
+}
+
+impl<T: CameraTransport> Drop for MidiController<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Returns the names of the MIDI input ports, to pick one for the mapping file
+pub fn input_ports() -> Result<Vec<String>, MidiError> {
+    let input = MidiInput::new(CLIENT_NAME).map_err(|e| MidiError::PortError(e.to_string()))?;
+    Ok(port_names(&input))
+}
+
+/// Returns the names of the MIDI output ports, to pick one for the mapping file
+pub fn output_ports() -> Result<Vec<String>, MidiError> {
+    let output = MidiOutput::new(CLIENT_NAME).map_err(|e| MidiError::PortError(e.to_string()))?;
+    Ok(port_names(&output))
+}
+
+fn port_names<M: MidiIO>(io: &M) -> Vec<String> {
+    io.ports()
+        .iter()
+        .filter_map(|p| io.port_name(p).ok())
+        .collect()
+}
+
+fn find_port<M: MidiIO>(io: &M, name: &str) -> Result<M::Port, MidiError> {
+    io.ports()
+        .into_iter()
+        .find(|p| io.port_name(p).is_ok_and(|n| n.contains(name)))
+        .ok_or_else(|| MidiError::PortNotFound(name.to_string()))
+}
+
+fn open_input<F>(name: Option<&str>, mut callback: F) -> Result<MidiInputConnection<()>, MidiError>
+where
+    F: FnMut(&[u8]) + Send + 'static,
+{
+    let input = MidiInput::new(CLIENT_NAME).map_err(|e| MidiError::PortError(e.to_string()))?;
+    let callback = move |_: u64, data: &[u8], _: &mut ()| callback(data);
+
+    let connection = match name {
+        Some(name) => {
+            let port = find_port(&input, name)?;
+            input.connect(&port, CLIENT_NAME, callback, ())
+        }
+        #[cfg(unix)]
+        None => midir::os::unix::VirtualInput::create_virtual(input, CLIENT_NAME, callback, ()),
+        #[cfg(not(unix))]
+        None => {
+            return Err(MidiError::PortNotFound(
+                "virtual ports need ALSA or CoreMIDI".into(),
+            ))
+        }
+    };
+
+    connection.map_err(|e| MidiError::PortError(e.to_string()))
+}
+
+fn open_output(name: Option<&str>) -> Result<MidiOutputConnection, MidiError> {
+    let output = MidiOutput::new(CLIENT_NAME).map_err(|e| MidiError::PortError(e.to_string()))?;
+
+    let connection = match name {
+        Some(name) => {
+            let port = find_port(&output, name)?;
+            output.connect(&port, CLIENT_NAME)
+        }
+        #[cfg(unix)]
+        None => midir::os::unix::VirtualOutput::create_virtual(output, CLIENT_NAME),
+        #[cfg(not(unix))]
+        None => {
+            return Err(MidiError::PortNotFound(
+                "virtual ports need ALSA or CoreMIDI".into(),
+            ))
+        }
+    };
+
+    connection.map_err(|e| MidiError::PortError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Lens, Video};
+
+    fn mapper(mappings: &str) -> MidiMapper {
+        let map: MidiMap =
+            serde_json::from_str(&format!("{{ \"mappings\": {} }}", mappings)).unwrap();
+        MidiMapper::new(&map).unwrap()
+    }
+
+    fn cc(value: u8) -> MidiMessage {
+        MidiMessage::ControlChange {
+            channel: 1,
+            controller: 0,
+            value,
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            MidiMessage::NoteOff {
+                channel: 1,
+                note: 60,
+                velocity: 64,
+            },
+            MidiMessage::NoteOn {
+                channel: 16,
+                note: 127,
+                velocity: 1,
+            },
+            MidiMessage::ControlChange {
+                channel: 10,
+                controller: 7,
+                value: 100,
+            },
+            MidiMessage::PitchBend {
+                channel: 3,
+                value: 16383,
+            },
+        ];
+        for message in messages.iter() {
+            assert_eq!(MidiMessage::from_bytes(&message.to_bytes()), Some(*message));
+        }
+
+        assert_eq!(
+            MidiMessage::from_bytes(&[0x91, 60, 0]),
+            Some(MidiMessage::NoteOff {
+                channel: 2,
+                note: 60,
+                velocity: 0,
+            })
+        );
+        assert_eq!(MidiMessage::from_bytes(&[0xb0, 7]), None);
+        assert_eq!(MidiMessage::from_bytes(&[0xf8, 0, 0]), None);
+    }
+
+    #[test]
+    fn encoders_count_ticks() {
+        assert_eq!(Encoding::TwosComplement.ticks(1), 1.0);
+        assert_eq!(Encoding::TwosComplement.ticks(127), -1.0);
+        assert_eq!(Encoding::TwosComplement.ticks(0), 0.0);
+        assert_eq!(Encoding::BinaryOffset.ticks(65), 1.0);
+        assert_eq!(Encoding::BinaryOffset.ticks(63), -1.0);
+        assert_eq!(Encoding::BinaryOffset.ticks(64), 0.0);
+        assert_eq!(Encoding::SignBit.ticks(1), 1.0);
+        assert_eq!(Encoding::SignBit.ticks(65), -1.0);
+        assert_eq!(Encoding::SignBit.ticks(0), 0.0);
+    }
+
+    #[test]
+    fn curves_invert() {
+        assert_eq!(Curve::Exponential.apply(0.5), 0.25);
+        assert_eq!(Curve::Logarithmic.apply(0.25), 0.5);
+
+        for curve in [Curve::Linear, Curve::Exponential, Curve::Logarithmic].iter() {
+            for i in 0..=10 {
+                let x = i as f64 / 10.0;
+                assert!((curve.invert(curve.apply(x)) - x).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn takeover_waits_for_the_fader_to_cross() {
+        let mut mapper = mapper(
+            r#"[{ "type": "cc", "number": 0, "parameter": "lens_focus", "takeover": true }]"#,
+        );
+        let focus = Command::Lens(Lens::Focus(0.5));
+        let current = |key: CommandKey| Some(focus.clone()).filter(|c| CommandKey::from(c) == key);
+
+        // Both below the camera's value
+        assert!(mapper.handle(&cc(10), &current).unwrap().is_empty());
+        assert!(mapper.handle(&cc(20), &current).unwrap().is_empty());
+
+        // Jumped past it, from then on the fader drives the camera
+        let writes = mapper.handle(&cc(70), &current).unwrap();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].0, Operation::AssignValue);
+        match writes[0].1 {
+            Command::Lens(Lens::Focus(v)) => assert!((v as f64 - 70.0 / 127.0).abs() < 0.001),
+            ref c => panic!("wrote {:?}", c),
+        }
+        assert_eq!(mapper.handle(&cc(10), &current).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn encoders_offset_the_value() {
+        let mut mapper = mapper(
+            r#"[{ "type": "cc", "number": 0, "parameter": "video_iso", "mode": "relative", "step": 100 }]"#,
+        );
+        let current = |_: CommandKey| None;
+
+        assert_eq!(
+            mapper.handle(&cc(1), &current).unwrap(),
+            vec![(Operation::OffsetValue, Command::Video(Video::Iso(100)))]
+        );
+        assert_eq!(
+            mapper.handle(&cc(126), &current).unwrap(),
+            vec![(Operation::OffsetValue, Command::Video(Video::Iso(-200)))]
+        );
+        assert!(mapper.handle(&cc(0), &current).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_channels_and_numbers_out_of_range() {
+        for mapping in [
+            r#"{ "type": "cc", "channel": 0, "number": 0, "parameter": "lens_focus" }"#,
+            r#"{ "type": "cc", "channel": 17, "number": 0, "parameter": "lens_focus" }"#,
+            r#"{ "type": "note", "number": 128, "parameter": "lens_focus" }"#,
+        ] {
+            let map: MidiMap =
+                serde_json::from_str(&format!("{{ \"mappings\": [{}] }}", mapping)).unwrap();
+            assert!(matches!(
+                MidiMapper::new(&map),
+                Err(MidiError::InvalidMapping(..))
+            ));
+        }
+    }
+
+    #[test]
+    fn feeds_back_external_changes() {
+        let mut mapper = mapper(
+            r#"[
+                { "type": "cc", "number": 0, "parameter": "lens_focus", "curve": "exponential", "feedback": true },
+                { "type": "note", "number": 60, "parameter": "lens_focus", "feedback": true },
+                { "type": "pitch_bend", "channel": 2, "parameter": "lens_focus", "feedback": true },
+                { "type": "cc", "number": 1, "parameter": "lens_focus" }
+            ]"#,
+        );
+
+        assert_eq!(
+            mapper.feedback(&Command::Lens(Lens::Focus(0.25))),
+            [
+                MidiMessage::ControlChange {
+                    channel: 1,
+                    controller: 0,
+                    value: 64,
+                },
+                MidiMessage::NoteOn {
+                    channel: 1,
+                    note: 60,
+                    velocity: 32,
+                },
+                MidiMessage::PitchBend {
+                    channel: 2,
+                    value: 4096,
+                },
+            ]
+        );
+        assert!(mapper.feedback(&Command::Video(Video::Iso(800))).is_empty());
+    }
+
+    #[test]
+    fn does_not_feed_back_its_own_values() {
+        let mut mapper = mapper(
+            r#"[{ "type": "cc", "number": 0, "parameter": "lens_focus", "feedback": true }]"#,
+        );
+        let current = |_: CommandKey| None;
+
+        let writes = mapper.handle(&cc(64), &current).unwrap();
+        assert!(mapper.feedback(&writes[0].1).is_empty());
+
+        // Something else moved it
+        assert_eq!(mapper.feedback(&Command::Lens(Lens::Focus(1.0))).len(), 1);
+    }
+
+    #[test]
+    fn external_changes_restart_takeover() {
+        let mut mapper = mapper(
+            r#"[{ "type": "cc", "number": 0, "parameter": "lens_focus", "takeover": true }]"#,
+        );
+        let at = |focus: f32| {
+            move |key: CommandKey| {
+                Some(Command::Lens(Lens::Focus(focus))).filter(|c| CommandKey::from(c) == key)
+            }
+        };
+
+        assert!(mapper.handle(&cc(10), &at(0.5)).unwrap().is_empty());
+        assert_eq!(mapper.handle(&cc(70), &at(0.5)).unwrap().len(), 1);
+
+        // The fader has to catch up with the new value before it drives the camera again
+        assert!(mapper
+            .feedback(&Command::Lens(Lens::Focus(0.875)))
+            .is_empty());
+        assert!(mapper.handle(&cc(20), &at(0.875)).unwrap().is_empty());
+        assert_eq!(mapper.handle(&cc(120), &at(0.875)).unwrap().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[ignore = "needs an ALSA sequencer or CoreMIDI to create the port on"]
+    async fn reads_a_virtual_port() {
+        use crate::simulator::SimulatedCamera;
+        use std::time::Duration;
+        use tokio::time::timeout;
+
+        let map: MidiMap = serde_json::from_str(
+            r#"{ "mappings": [{ "type": "cc", "number": 0, "parameter": "lens_focus" }] }"#,
+        )
+        .unwrap();
+        let mut camera = Camera::with_transport(SimulatedCamera::new());
+        camera.connect(Duration::from_secs(1)).await.unwrap();
+        camera
+            .wait_synced(Duration::from_millis(50), Duration::from_secs(1))
+            .await
+            .unwrap();
+        let mut updates = camera.updates().await;
+
+        let mut controller = MidiController::new(camera, map).unwrap();
+        controller.start().unwrap();
+
+        let output = MidiOutput::new("test").unwrap();
+        let port = find_port(&output, CLIENT_NAME).unwrap();
+        let mut connection = output.connect(&port, "test").unwrap();
+        connection.send(&cc(64).to_bytes()).unwrap();
+
+        let written = timeout(Duration::from_secs(2), async {
+            loop {
+                if let Command::Lens(Lens::Focus(v)) = updates.recv().await.unwrap() {
+                    if (v as f64 - 64.0 / 127.0).abs() < 0.001 {
+                        break;
+                    }
+                }
+            }
+        })
+        .await;
+        assert!(written.is_ok());
+    }
+}
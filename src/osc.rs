@@ -0,0 +1,778 @@
+use crate::camera::Camera;
+use crate::command::Command;
+use crate::error::OscError;
+use crate::info::{CategoryInfo, ParameterInfo};
+use crate::key::CommandKey;
+use crate::parameters::{CATEGORIES, PARAMETERS};
+use crate::rawcommand::{Operation, RawCommand};
+use crate::transport::CameraTransport;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// First element of every camera control address
+pub const ADDRESS_PREFIX: &str = "/camera";
+
+/// Address a client sends to start getting feedback, optionally with the port to send it to
+pub const REGISTER: &str = "/register";
+
+/// Address a client sends to stop getting feedback, with the port it registered if it gave one
+pub const UNREGISTER: &str = "/unregister";
+
+/// Address errors are reported back to the sender on
+pub const ERROR: &str = "/error";
+
+/// Deepest a bundle can be nested in other bundles
+pub const MAX_BUNDLE_DEPTH: usize = 8;
+
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+/// A single OSC argument
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Blob(Vec<u8>),
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+}
+
+impl OscArg {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            OscArg::Int(v) => Some(*v as f64),
+            OscArg::Float(v) => Some(*v as f64),
+            OscArg::Long(v) => Some(*v as f64),
+            OscArg::Double(v) => Some(*v),
+            OscArg::Bool(v) => Some(*v as u8 as f64),
+            OscArg::String(v) => v.trim().parse().ok(),
+            OscArg::Blob(_) => None,
+        }
+    }
+}
+
+/// An OSC message
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(address: &str, args: Vec<OscArg>) -> OscMessage {
+        OscMessage {
+            address: address.to_string(),
+            args,
+        }
+    }
+
+    /// Encodes the message as an OSC 1.0 packet
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut tags = String::from(",");
+        let mut data = Vec::new();
+
+        for arg in self.args.iter() {
+            match arg {
+                OscArg::Int(v) => {
+                    tags.push('i');
+                    data.extend_from_slice(&v.to_be_bytes());
+                }
+                OscArg::Float(v) => {
+                    tags.push('f');
+                    data.extend_from_slice(&v.to_be_bytes());
+                }
+                OscArg::String(v) => {
+                    tags.push('s');
+                    write_string(&mut data, v);
+                }
+                OscArg::Blob(v) => {
+                    tags.push('b');
+                    data.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                    data.extend_from_slice(v);
+                    data.resize((data.len() + 3) & !3, 0);
+                }
+                OscArg::Long(v) => {
+                    tags.push('h');
+                    data.extend_from_slice(&v.to_be_bytes());
+                }
+                OscArg::Double(v) => {
+                    tags.push('d');
+                    data.extend_from_slice(&v.to_be_bytes());
+                }
+                OscArg::Bool(v) => tags.push(if *v { 'T' } else { 'F' }),
+            }
+        }
+
+        let mut packet = Vec::new();
+        write_string(&mut packet, &self.address);
+        write_string(&mut packet, &tags);
+        packet.extend(data);
+        packet
+    }
+
+    /// Decodes an OSC packet, bundles are flattened into the messages they contain
+    ///
+    /// # Arguments
+    ///
+    /// * `packet` - &[u8] of a single UDP datagram
+    pub fn from_bytes(packet: &[u8]) -> Result<Vec<OscMessage>, OscError> {
+        OscMessage::decode(packet, 0)
+    }
+
+    fn decode(packet: &[u8], depth: usize) -> Result<Vec<OscMessage>, OscError> {
+        if packet.starts_with(BUNDLE_TAG) {
+            if depth >= MAX_BUNDLE_DEPTH {
+                return Err(OscError::TooDeep(MAX_BUNDLE_DEPTH));
+            }
+
+            // The time tag is ignored, everything is applied as it arrives
+            let mut rest = packet.get(16..).ok_or(OscError::Truncated)?;
+            let mut messages = Vec::new();
+
+            while !rest.is_empty() {
+                let size = read_i32(&mut rest)? as usize;
+                let element = rest.get(..size).ok_or(OscError::Truncated)?;
+                messages.extend(OscMessage::decode(element, depth + 1)?);
+                rest = &rest[size..];
+            }
+
+            return Ok(messages);
+        }
+
+        if !packet.starts_with(b"/") {
+            return Err(OscError::NotOsc);
+        }
+
+        let mut rest = packet;
+        let address = read_string(&mut rest)?;
+
+        // Type tags are optional in old implementations, no tags means no arguments
+        let tags = match rest.first() {
+            Some(b',') => read_string(&mut rest)?,
+            _ => String::from(","),
+        };
+
+        let mut args = Vec::new();
+        for tag in tags.chars().skip(1) {
+            args.push(match tag {
+                'i' => OscArg::Int(read_i32(&mut rest)?),
+                'f' => OscArg::Float(f32::from_bits(read_i32(&mut rest)? as u32)),
+                's' | 'S' => OscArg::String(read_string(&mut rest)?),
+                'b' => {
+                    let size = read_i32(&mut rest)? as usize;
+                    let blob = rest.get(..size).ok_or(OscError::Truncated)?.to_vec();
+                    rest = rest.get((size + 3) & !3..).unwrap_or(&[]);
+                    OscArg::Blob(blob)
+                }
+                'h' => OscArg::Long(read_i64(&mut rest)?),
+                'd' => OscArg::Double(f64::from_bits(read_i64(&mut rest)? as u64)),
+                'T' => OscArg::Bool(true),
+                'F' => OscArg::Bool(false),
+                t => return Err(OscError::UnsupportedType(t)),
+            });
+        }
+
+        Ok(vec![OscMessage { address, args }])
+    }
+}
+
+fn write_string(data: &mut Vec<u8>, s: &str) {
+    data.extend_from_slice(s.as_bytes());
+    data.push(0);
+    data.resize((data.len() + 3) & !3, 0);
+}
+
+fn read_string(rest: &mut &[u8]) -> Result<String, OscError> {
+    let end = rest
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or(OscError::Truncated)?;
+    let s = String::from_utf8_lossy(&rest[..end]).into_owned();
+    *rest = rest.get((end + 4) & !3..).unwrap_or(&[]);
+    Ok(s)
+}
+
+fn read_i32(rest: &mut &[u8]) -> Result<i32, OscError> {
+    let bytes = rest.get(..4).ok_or(OscError::Truncated)?;
+    let v = i32::from_be_bytes(bytes.try_into().map_err(|_| OscError::Truncated)?);
+    *rest = &rest[4..];
+    Ok(v)
+}
+
+fn read_i64(rest: &mut &[u8]) -> Result<i64, OscError> {
+    let bytes = rest.get(..8).ok_or(OscError::Truncated)?;
+    let v = i64::from_be_bytes(bytes.try_into().map_err(|_| OscError::Truncated)?);
+    *rest = &rest[8..];
+    Ok(v)
+}
+
+/// Returns the address of a parameter, like "/camera/1/video/iso"
+///
+/// # Arguments
+///
+/// * `camera` - u8 camera id
+/// * `key` - CommandKey or Key like this: keys::video::ISO
+pub fn address(camera: u8, key: impl Into<CommandKey>) -> Option<String> {
+    let key = key.into();
+    let category = CategoryInfo::lookup(key.category)?;
+    let parameter = ParameterInfo::lookup(key)?;
+
+    Some(format!(
+        "{}/{}/{}/{}",
+        ADDRESS_PREFIX, camera, category.normalized_name, parameter.normalized_name
+    ))
+}
+
+/// Turns a message into the camera id it addresses and the command it carries
+///
+/// Arguments are coerced to the parameter's type, so a fader sending floats can drive an
+/// integer parameter, and single values are checked against the range in PROTOCOL.json.
+/// Triggers fire on a message without arguments or with a true or non-zero one, buttons
+/// that also send 0 on release return None for it.
+///
+/// # Arguments
+///
+/// * `message` - OscMessage like this: /camera/1/video/iso 640
+pub fn to_command(message: &OscMessage) -> Result<Option<(u8, Command)>, OscError> {
+    let unknown = || OscError::UnknownAddress(message.address.clone());
+    let wrong = |why: &str| OscError::WrongArguments(message.address.clone(), why.to_string());
+
+    let parts: Vec<&str> = message.address.split('/').skip(1).collect();
+    let (camera, category, parameter) = match parts.as_slice() {
+        ["camera", camera, category, parameter] => (*camera, *category, *parameter),
+        _ => return Err(unknown()),
+    };
+    let camera: u8 = camera.parse().map_err(|_| unknown())?;
+    let category = CATEGORIES
+        .iter()
+        .find(|c| c.normalized_name == category)
+        .ok_or_else(unknown)?;
+    let info = PARAMETERS
+        .iter()
+        .find(|p| p.category == category.id && p.normalized_name == parameter)
+        .ok_or_else(unknown)?;
+
+    let command = match info.data_type {
+        "string" => match message.args.as_slice() {
+            [OscArg::String(s)] => {
+                let raw = RawCommand {
+                    destination_device: camera,
+                    command_id: 0,
+                    category: info.category,
+                    parameter: info.parameter,
+                    data_type: 5,
+                    operation: Operation::AssignValue.id(),
+                    data: s.as_bytes().to_vec(),
+                };
+                Command::from_raw(&raw.to_bytes())?
+            }
+            _ => return Err(wrong("expected a single string")),
+        },
+        "int8" | "int16" | "int32" | "int64" | "fixed16" => {
+            let elements = info.elements();
+            if message.args.len() != elements {
+                return Err(wrong(&format!("expected {} values", elements)));
+            }
+
+            let mut values = Vec::new();
+            for arg in message.args.iter() {
+                let v = arg.as_f64().ok_or_else(|| wrong("expected numbers"))?;
+
+                // Ranges of parameters with several elements rarely hold for all of them
+                if elements == 1 {
+                    let (min, max) = (
+                        info.minimum.unwrap_or(f64::MIN),
+                        info.maximum.unwrap_or(f64::MAX),
+                    );
+                    if v < min || v > max {
+                        return Err(OscError::OutOfRange(v, message.address.clone(), min, max));
+                    }
+                }
+                values.push(v);
+            }
+
+            info.command(&values)?
+        }
+        // Triggers, and the types the protocol carries without a value
+        _ => {
+            if let Some(arg) = message.args.first() {
+                if arg.as_f64() == Some(0.0) {
+                    return Ok(None);
+                }
+            }
+            info.command(&[])?
+        }
+    };
+
+    Ok(Some((camera, command)))
+}
+
+/// Turns a command into the message reporting it, None for parameters PROTOCOL.json lacks
+///
+/// # Arguments
+///
+/// * `camera` - u8 camera id to put in the address
+/// * `command` - Command like this: Command::Video(Video::Iso(640))
+pub fn from_command(camera: u8, command: &Command) -> Option<OscMessage> {
+    let address = address(camera, command)?;
+    let data = command.to_bytes();
+
+    let args = match command.raw_type() {
+        1 => data.iter().map(|b| OscArg::Int(*b as i8 as i32)).collect(),
+        2 => data
+            .chunks_exact(2)
+            .map(|b| OscArg::Int(i16::from_le_bytes([b[0], b[1]]) as i32))
+            .collect(),
+        3 => data
+            .chunks_exact(4)
+            .map(|b| OscArg::Int(i32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .collect(),
+        4 => data
+            .chunks_exact(8)
+            .map(|b| OscArg::Long(i64::from_le_bytes(b.try_into().unwrap_or_default())))
+            .collect(),
+        5 => vec![OscArg::String(String::from_utf8_lossy(&data).into_owned())],
+        128 => data
+            .chunks_exact(2)
+            .map(|b| OscArg::Float(i16::from_le_bytes([b[0], b[1]]) as f32 / 2048.0))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Some(OscMessage { address, args })
+}
+
+/// Controls a camera from OSC consoles and control surfaces
+///
+/// Listens for messages addressed like /camera/1/lens/focus and writes them to the camera.
+/// Clients that send /register get every value the camera reports back as feedback, on
+/// the same addresses, starting with everything reported so far.
+#[derive(Debug)]
+pub struct OscServer<T: CameraTransport> {
+    camera: Arc<Mutex<Camera<T>>>,
+    camera_id: u8,
+    clients: Arc<std::sync::Mutex<HashSet<SocketAddr>>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl<T: CameraTransport> OscServer<T> {
+    /// Takes a camera and returns a new OscServer, nothing listens until told to
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - Camera to control, connected or not
+    pub fn new(camera: Camera<T>) -> OscServer<T> {
+        OscServer::from_shared(Arc::new(Mutex::new(camera)))
+    }
+
+    /// Same as new but for a camera that is shared already, such as by a BridgeServer
+    pub fn from_shared(camera: Arc<Mutex<Camera<T>>>) -> OscServer<T> {
+        OscServer {
+            camera,
+            camera_id: 1,
+            clients: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Sets the camera id feedback is addressed with, 1 unless set
+    pub fn with_camera_id(mut self, camera_id: u8) -> Self {
+        self.camera_id = camera_id;
+        self
+    }
+
+    /// Gives you the camera, to connect it or use it alongside OSC
+    pub fn camera(&self) -> Arc<Mutex<Camera<T>>> {
+        self.camera.clone()
+    }
+
+    /// Adds a client that gets feedback without having to send /register
+    pub fn add_client(&self, addr: SocketAddr) {
+        self.clients.lock().unwrap().insert(addr);
+    }
+
+    pub fn remove_client(&self, addr: SocketAddr) {
+        self.clients.lock().unwrap().remove(&addr);
+    }
+
+    /// Returns the clients getting feedback
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        self.clients.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Starts listening for OSC over UDP, returns the address it listens on
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - address to listen on like this: "0.0.0.0:8000"
+    pub async fn listen<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<SocketAddr> {
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        let local = socket.local_addr()?;
+
+        self.tasks.push(tokio::spawn(OscServer::receive(
+            self.camera.clone(),
+            self.camera_id,
+            self.clients.clone(),
+            socket.clone(),
+        )));
+        self.tasks.push(tokio::spawn(OscServer::feedback(
+            self.camera.clone(),
+            self.camera_id,
+            self.clients.clone(),
+            socket,
+        )));
+
+        Ok(local)
+    }
+
+    /// Stops listening, the camera stays connected
+    pub fn shutdown(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+
+    async fn receive(
+        camera: Arc<Mutex<Camera<T>>>,
+        camera_id: u8,
+        clients: Arc<std::sync::Mutex<HashSet<SocketAddr>>>,
+        socket: Arc<UdpSocket>,
+    ) {
+        let mut buf = vec![0u8; 65536];
+
+        while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+            let messages = match OscMessage::from_bytes(&buf[..n]) {
+                Ok(m) => m,
+                Err(e) => {
+                    send_error(&socket, from, &e.to_string()).await;
+                    continue;
+                }
+            };
+
+            for message in messages {
+                match message.address.as_str() {
+                    REGISTER => {
+                        let client = match client_address(&message, from) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                send_error(&socket, from, &e.to_string()).await;
+                                continue;
+                            }
+                        };
+                        clients.lock().unwrap().insert(client);
+
+                        let snapshot = camera.lock().await.commands().await;
+                        for command in snapshot {
+                            if let Some(m) = from_command(camera_id, &command) {
+                                let _ = socket.send_to(&m.to_bytes(), client).await;
+                            }
+                        }
+                    }
+                    UNREGISTER => match client_address(&message, from) {
+                        Ok(client) => {
+                            clients.lock().unwrap().remove(&client);
+                        }
+                        Err(e) => send_error(&socket, from, &e.to_string()).await,
+                    },
+                    _ => {
+                        let result = match to_command(&message) {
+                            Ok(Some((destination, command))) => camera
+                                .lock()
+                                .await
+                                .write(destination, Operation::AssignValue, command)
+                                .await
+                                .map_err(|e| e.to_string()),
+                            Ok(None) => Ok(()),
+                            Err(e) => Err(e.to_string()),
+                        };
+                        if let Err(e) = result {
+                            send_error(&socket, from, &e).await;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn feedback(
+        camera: Arc<Mutex<Camera<T>>>,
+        camera_id: u8,
+        clients: Arc<std::sync::Mutex<HashSet<SocketAddr>>>,
+        socket: Arc<UdpSocket>,
+    ) {
+        let mut updates = camera.lock().await.updates().await;
+
+        loop {
+            let commands = match updates.recv().await {
+                Ok(command) => vec![command],
+                // Missed updates are replaced by everything the camera has reported
+                Err(RecvError::Lagged(_)) => camera.lock().await.commands().await,
+                Err(RecvError::Closed) => return,
+            };
+
+            let clients: Vec<SocketAddr> = clients.lock().unwrap().iter().copied().collect();
+            for command in commands {
+                if let Some(m) = from_command(camera_id, &command) {
+                    let packet = m.to_bytes();
+                    for client in clients.iter() {
+                        let _ = socket.send_to(&packet, client).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: CameraTransport> Drop for OscServer<T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// Where a client wants feedback, the port in the message or the one it sent from
+fn client_address(message: &OscMessage, from: SocketAddr) -> Result<SocketAddr, OscError> {
+    let port = match message.args.first() {
+        Some(arg) => arg.as_f64(),
+        None => return Ok(from),
+    };
+
+    match port {
+        Some(p) if p.fract() == 0.0 && (1.0..=65535.0).contains(&p) => {
+            Ok(SocketAddr::new(from.ip(), p as u16))
+        }
+        _ => Err(OscError::WrongArguments(
+            message.address.clone(),
+            "expected a port from 1 to 65535".to_string(),
+        )),
+    }
+}
+
+async fn send_error(socket: &UdpSocket, to: SocketAddr, error: &str) {
+    let message = OscMessage::new(ERROR, vec![OscArg::String(error.to_string())]);
+    let _ = socket.send_to(&message.to_bytes(), to).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Lens, Video};
+    use crate::simulator::SimulatedCamera;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn message(address: &str, args: Vec<OscArg>) -> OscMessage {
+        OscMessage::new(address, args)
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = BUNDLE_TAG.to_vec();
+        packet.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for element in elements {
+            packet.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            packet.extend_from_slice(element);
+        }
+        packet
+    }
+
+    #[test]
+    fn maps_addresses_onto_commands() {
+        assert_eq!(
+            to_command(&message("/camera/1/video/iso", vec![OscArg::Int(640)])).unwrap(),
+            Some((1, Command::Video(Video::Iso(640))))
+        );
+        assert_eq!(
+            to_command(&message("/camera/1/lens/focus", vec![OscArg::Float(0.5)])).unwrap(),
+            Some((1, Command::Lens(Lens::Focus(0.5))))
+        );
+        assert_eq!(
+            to_command(&message("/camera/1/lens/instantaneous_autofocus", vec![])).unwrap(),
+            Some((1, Command::Lens(Lens::InstantaneousAutofocus)))
+        );
+
+        // A button sends 0 when it is let go
+        assert_eq!(
+            to_command(&message(
+                "/camera/1/lens/instantaneous_autofocus",
+                vec![OscArg::Int(0)]
+            ))
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn coerces_and_checks_arguments() {
+        assert_eq!(
+            to_command(&message("/camera/2/video/iso", vec![OscArg::Float(800.0)])).unwrap(),
+            Some((2, Command::Video(Video::Iso(800))))
+        );
+
+        assert!(matches!(
+            to_command(&message("/camera/1/lens/focus", vec![OscArg::Float(1.5)])),
+            Err(OscError::OutOfRange(v, _, min, max)) if v == 1.5 && min == 0.0 && max == 1.0
+        ));
+        assert!(matches!(
+            to_command(&message("/camera/1/lens/focus", vec![])),
+            Err(OscError::WrongArguments(..))
+        ));
+        assert!(matches!(
+            to_command(&message(
+                "/camera/1/lens/focus",
+                vec![OscArg::Blob(vec![1])]
+            )),
+            Err(OscError::WrongArguments(..))
+        ));
+        assert!(matches!(
+            to_command(&message("/camera/1/lens/nothing", vec![])),
+            Err(OscError::UnknownAddress(_))
+        ));
+        assert!(matches!(
+            to_command(&message("/camera/x/lens/focus", vec![OscArg::Float(0.5)])),
+            Err(OscError::UnknownAddress(_))
+        ));
+    }
+
+    #[test]
+    fn reports_commands_on_the_same_address() {
+        let focus = Command::Lens(Lens::Focus(0.5));
+        let reported = from_command(3, &focus).unwrap();
+
+        assert_eq!(
+            reported,
+            message("/camera/3/lens/focus", vec![OscArg::Float(0.5)])
+        );
+        assert_eq!(to_command(&reported).unwrap(), Some((3, focus)));
+    }
+
+    #[test]
+    fn round_trips_every_argument_type() {
+        let original = message(
+            "/test",
+            vec![
+                OscArg::Int(-7),
+                OscArg::Float(0.25),
+                OscArg::String("hello".to_string()),
+                OscArg::Blob(vec![1, 2, 3]),
+                OscArg::Long(1 << 40),
+                OscArg::Double(-2.5),
+                OscArg::Bool(true),
+                OscArg::Bool(false),
+            ],
+        );
+
+        let packet = original.to_bytes();
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(OscMessage::from_bytes(&packet).unwrap(), vec![original]);
+    }
+
+    #[test]
+    fn flattens_bundles() {
+        let iso = message("/camera/1/video/iso", vec![OscArg::Int(640)]);
+        let focus = message("/camera/1/lens/focus", vec![OscArg::Float(0.5)]);
+        let packet = bundle(&[iso.to_bytes(), bundle(&[focus.to_bytes()])]);
+
+        assert_eq!(OscMessage::from_bytes(&packet).unwrap(), vec![iso, focus]);
+    }
+
+    #[test]
+    fn rejects_bad_packets() {
+        let iso = message("/camera/1/video/iso", vec![OscArg::Int(640)]).to_bytes();
+
+        assert!(matches!(
+            OscMessage::from_bytes(b"hello"),
+            Err(OscError::NotOsc)
+        ));
+        assert!(matches!(
+            OscMessage::from_bytes(&iso[..iso.len() - 2]),
+            Err(OscError::Truncated)
+        ));
+        assert!(matches!(
+            OscMessage::from_bytes(b"/camera"),
+            Err(OscError::Truncated)
+        ));
+
+        let mut short = bundle(std::slice::from_ref(&iso));
+        short.truncate(short.len() - 4);
+        assert!(matches!(
+            OscMessage::from_bytes(&short),
+            Err(OscError::Truncated)
+        ));
+
+        let nested = (0..=MAX_BUNDLE_DEPTH).fold(iso, |inner, _| bundle(&[inner]));
+        assert!(matches!(
+            OscMessage::from_bytes(&nested),
+            Err(OscError::TooDeep(_))
+        ));
+    }
+
+    async fn receive(socket: &UdpSocket) -> OscMessage {
+        let mut buf = vec![0u8; 65536];
+        let n = timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        OscMessage::from_bytes(&buf[..n]).unwrap().remove(0)
+    }
+
+    #[tokio::test]
+    async fn registers_clients_by_address() {
+        let mut camera = Camera::with_transport(SimulatedCamera::new());
+        camera.connect(Duration::from_secs(1)).await.unwrap();
+        camera
+            .wait_synced(Duration::from_millis(50), Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        let mut server = OscServer::new(camera);
+        let addr = server.listen("127.0.0.1:0").await.unwrap();
+
+        let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        first.connect(addr).await.unwrap();
+        second.connect(addr).await.unwrap();
+
+        for port in [OscArg::Int(0), OscArg::Int(70000), OscArg::Float(9000.5)] {
+            first
+                .send(&message(REGISTER, vec![port]).to_bytes())
+                .await
+                .unwrap();
+            assert_eq!(receive(&first).await.address, ERROR);
+        }
+
+        // Registering sends everything reported so far
+        first
+            .send(&message(REGISTER, vec![]).to_bytes())
+            .await
+            .unwrap();
+        assert!(receive(&first).await.address.starts_with(ADDRESS_PREFIX));
+
+        let port = OscArg::Int(second.local_addr().unwrap().port() as i32);
+        first
+            .send(&message(REGISTER, vec![port.clone()]).to_bytes())
+            .await
+            .unwrap();
+        assert!(receive(&second).await.address.starts_with(ADDRESS_PREFIX));
+
+        // Only the client named goes, not everyone on the same host
+        first
+            .send(&message(UNREGISTER, vec![port]).to_bytes())
+            .await
+            .unwrap();
+        let remaining = timeout(Duration::from_secs(5), async {
+            while server.clients().len() != 1 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await;
+        assert!(remaining.is_ok());
+        assert_eq!(server.clients(), vec![first.local_addr().unwrap()]);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::characteristics::{self, INCOMING_CAMERA_CONTROL, OUTGOING_CAMERA_CONTROL};
+use crate::rawcommand::RawCommand;
+use crate::recording::{PacketWriter, RecordedPacket};
+use crate::transport::Direction;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use uuid::Uuid;
+
+/// Link type written for every interface, DLT_USER0 so Wireshark can be told how to
+/// dissect the payload
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_DESCRIPTION: u16 = 3;
+const OPT_EPB_FLAGS: u16 = 2;
+
+const FLAG_INBOUND: u32 = 0b01;
+const FLAG_OUTBOUND: u32 = 0b10;
+
+/// Writes recorded packets as a pcapng capture that opens in Wireshark
+///
+/// Every characteristic gets its own interface, named after it, and the direction is
+/// stored in the packet flags. Camera control payloads are split with RawCommand::split
+/// so every message is a packet record of its own. Timestamps use the default
+/// microsecond resolution.
+#[derive(Debug)]
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+    interfaces: HashMap<Uuid, u32>,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and returns a new PcapngWriter
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - anything implementing Write, like a File
+    pub fn new(mut writer: W) -> io::Result<PcapngWriter<W>> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // Section length is unknown up front
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &body)?;
+
+        Ok(PcapngWriter {
+            writer,
+            interfaces: HashMap::new(),
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn interface(&mut self, characteristic: Uuid) -> io::Result<u32> {
+        if let Some(id) = self.interfaces.get(&characteristic) {
+            return Ok(*id);
+        }
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // Snap length, 0 means no limit
+        body.extend_from_slice(&0u32.to_le_bytes());
+
+        let uuid = characteristic.to_string();
+        let name = characteristics::name(characteristic).unwrap_or(&uuid);
+        push_option(&mut body, OPT_IF_NAME, name.as_bytes());
+        push_option(&mut body, OPT_IF_DESCRIPTION, uuid.as_bytes());
+        push_option(&mut body, OPT_END, &[]);
+
+        write_block(&mut self.writer, INTERFACE_DESCRIPTION_BLOCK, &body)?;
+
+        let id = self.interfaces.len() as u32;
+        self.interfaces.insert(characteristic, id);
+        Ok(id)
+    }
+
+    fn write_record(
+        &mut self,
+        interface: u32,
+        timestamp_us: u64,
+        direction: Direction,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&interface.to_le_bytes());
+        body.extend_from_slice(&((timestamp_us >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp_us as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        pad(&mut body);
+
+        let flags = match direction {
+            Direction::Incoming => FLAG_INBOUND,
+            Direction::Outgoing => FLAG_OUTBOUND,
+        };
+        push_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut body, OPT_END, &[]);
+
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, &body)
+    }
+}
+
+impl<W: Write> PacketWriter for PcapngWriter<W> {
+    fn write_packet(&mut self, packet: &RecordedPacket) -> io::Result<()> {
+        let interface = self.interface(packet.characteristic)?;
+
+        match packet.characteristic {
+            OUTGOING_CAMERA_CONTROL | INCOMING_CAMERA_CONTROL => {
+                for message in RawCommand::split(&packet.data) {
+                    self.write_record(interface, packet.timestamp_us, packet.direction, message)?;
+                }
+            }
+            _ => self.write_record(
+                interface,
+                packet.timestamp_us,
+                packet.direction,
+                &packet.data,
+            )?,
+        }
+
+        self.writer.flush()
+    }
+}
+
+// Pads to the next 32-bit boundary, as every pcapng field must be
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    // Type and both length fields
+    let len = (body.len() + 12) as u32;
+
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&len.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::characteristics::TIMECODE;
+    use crate::command::{Command, Lens, Video};
+    use crate::rawcommand::Operation;
+    use std::convert::TryInto;
+
+    fn u32_at(buf: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+    }
+
+    // Splits a capture into (type, body), checking both length fields of every block
+    fn blocks(capture: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut blocks = Vec::new();
+        let mut rest = capture;
+
+        while !rest.is_empty() {
+            let len = u32_at(rest, 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(rest, len - 4) as usize, len);
+
+            blocks.push((u32_at(rest, 0), rest[8..len - 4].to_vec()));
+            rest = &rest[len..];
+        }
+        blocks
+    }
+
+    fn capture(packets: &[RecordedPacket]) -> Vec<u8> {
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        for packet in packets {
+            writer.write_packet(packet).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn writes_blocks_wireshark_reads() {
+        let blocks = blocks(&capture(&[RecordedPacket {
+            direction: Direction::Incoming,
+            timestamp_us: 0x1_0000_0002,
+            characteristic: TIMECODE,
+            data: vec![1, 2, 3, 4, 5],
+        }]));
+        assert_eq!(blocks.len(), 3);
+
+        let (block_type, shb) = &blocks[0];
+        assert_eq!(*block_type, SECTION_HEADER_BLOCK);
+        assert_eq!(shb[0..4], [0x4d, 0x3c, 0x2b, 0x1a]);
+
+        let (block_type, idb) = &blocks[1];
+        assert_eq!(*block_type, INTERFACE_DESCRIPTION_BLOCK);
+        assert_eq!(idb[0..2], LINKTYPE_USER0.to_le_bytes());
+        assert_eq!(u32_at(idb, 4), 0);
+
+        let (block_type, epb) = &blocks[2];
+        assert_eq!(*block_type, ENHANCED_PACKET_BLOCK);
+        assert_eq!(u32_at(epb, 0), 0);
+        assert_eq!(u32_at(epb, 4), 1);
+        assert_eq!(u32_at(epb, 8), 2);
+        assert_eq!(u32_at(epb, 12), 5);
+        assert_eq!(u32_at(epb, 16), 5);
+        assert_eq!(epb[20..25], [1, 2, 3, 4, 5]);
+        // Padded up to the flags option
+        assert_eq!(epb[25..28], [0, 0, 0]);
+        assert_eq!(epb[28..30], OPT_EPB_FLAGS.to_le_bytes());
+        assert_eq!(u32_at(epb, 32), FLAG_INBOUND);
+    }
+
+    #[test]
+    fn writes_a_record_per_camera_control_message() {
+        // Padded to 32 bits, the way they go over the air
+        let message = |command: &Command| {
+            let mut packet = RawCommand::to_raw(1, Operation::AssignValue, command);
+            pad(&mut packet);
+            packet
+        };
+        let focus = message(&Command::Lens(Lens::Focus(0.5)));
+        let iso = message(&Command::Video(Video::Iso(800)));
+        let packet = RecordedPacket {
+            direction: Direction::Outgoing,
+            timestamp_us: 0,
+            characteristic: OUTGOING_CAMERA_CONTROL,
+            data: [focus, iso.clone()].concat(),
+        };
+
+        let blocks = blocks(&capture(&[packet.clone(), packet]));
+        let types: Vec<u32> = blocks.iter().map(|(t, _)| *t).collect();
+        assert_eq!(
+            types,
+            [
+                SECTION_HEADER_BLOCK,
+                INTERFACE_DESCRIPTION_BLOCK,
+                ENHANCED_PACKET_BLOCK,
+                ENHANCED_PACKET_BLOCK,
+                ENHANCED_PACKET_BLOCK,
+                ENHANCED_PACKET_BLOCK,
+            ]
+        );
+
+        let (_, epb) = &blocks[3];
+        assert_eq!(u32_at(epb, 12) as usize, iso.len());
+        assert_eq!(epb[20..20 + iso.len()], iso[..]);
+        let flags = 20 + ((iso.len() + 3) & !3);
+        assert_eq!(u32_at(epb, flags + 4), FLAG_OUTBOUND);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::error::CameraControlError;
+use crate::transport::{CameraTransport, EventStream};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::task::JoinHandle;
+
+/// Wraps a transport so sending a packet only puts it in a queue
+///
+/// A task of its own writes the queue to the camera in order, so a slow link, like a
+/// Bluetooth write waiting for its response, never holds up whoever shares the `Camera`
+/// behind a lock. A write that fails is returned by the next call to send(), packets
+/// still queued when the link is disconnected are dropped.
+#[derive(Debug)]
+pub struct QueuedTransport<T: CameraTransport> {
+    inner: Arc<Mutex<T>>,
+    queue: Option<UnboundedSender<Vec<u8>>>,
+    writer: Option<JoinHandle<()>>,
+    error: Arc<std::sync::Mutex<Option<T::Error>>>,
+}
+
+impl<T: CameraTransport> QueuedTransport<T> {
+    /// Wraps a transport, nothing is queued until it is connected
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - the CameraTransport to write the queue to
+    pub fn new(inner: T) -> QueuedTransport<T> {
+        QueuedTransport {
+            inner: Arc::new(Mutex::new(inner)),
+            queue: None,
+            writer: None,
+            error: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    /// Gives you the wrapped transport, once the write in progress is done
+    pub async fn inner(&self) -> MutexGuard<'_, T> {
+        self.inner.lock().await
+    }
+
+    fn stop(&mut self) {
+        self.queue = None;
+        if let Some(writer) = self.writer.take() {
+            writer.abort();
+        }
+    }
+}
+
+impl<T: CameraTransport> Drop for QueuedTransport<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[async_trait]
+impl<T: CameraTransport> CameraTransport for QueuedTransport<T> {
+    type Error = T::Error;
+
+    async fn connect(&mut self, timeout: Duration) -> Result<(), T::Error> {
+        self.stop();
+        self.inner.lock().await.connect(timeout).await?;
+
+        let (queue, mut packets) = mpsc::unbounded_channel::<Vec<u8>>();
+        let inner = self.inner.clone();
+        let error = self.error.clone();
+        self.writer = Some(tokio::spawn(async move {
+            while let Some(packet) = packets.recv().await {
+                if let Err(e) = inner.lock().await.send(&packet).await {
+                    *error.lock().unwrap() = Some(e);
+                }
+            }
+        }));
+        self.queue = Some(queue);
+
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), T::Error> {
+        self.stop();
+        self.inner.lock().await.disconnect().await
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), T::Error> {
+        if let Some(e) = self.error.lock().unwrap().take() {
+            return Err(e);
+        }
+
+        self.queue
+            .as_ref()
+            .ok_or(CameraControlError::Disconnected)?
+            .send(packet.to_vec())
+            .map_err(|_| CameraControlError::Disconnected)?;
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, T::Error> {
+        self.inner.lock().await.events().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::command::{Command, Lens};
+    use crate::fault::{Direction, Fault, FaultyTransport};
+    use crate::rawcommand::Operation;
+    use crate::simulator::SimulatedCamera;
+    use tokio::time::{timeout, Instant};
+
+    #[tokio::test]
+    async fn writes_without_waiting_for_the_link() {
+        let slow = FaultyTransport::new(SimulatedCamera::new(), 1).schedule(
+            Direction::Outgoing,
+            0,
+            Fault::Delay(Duration::from_millis(300)),
+        );
+        let mut camera = Camera::with_transport(QueuedTransport::new(slow));
+        camera.connect(Duration::from_secs(1)).await.unwrap();
+        camera
+            .wait_synced(Duration::from_millis(50), Duration::from_secs(1))
+            .await
+            .unwrap();
+        let mut updates = camera.updates().await;
+
+        let focus = Command::Lens(Lens::Focus(0.25));
+        let start = Instant::now();
+        camera
+            .write(1, Operation::AssignValue, focus.clone())
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // The camera still gets it, once the link has caught up
+        let echoed = timeout(Duration::from_secs(2), async {
+            loop {
+                if updates.recv().await.unwrap() == focus {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(echoed.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn reports_failed_writes_on_the_next_send() {
+        let failing = FaultyTransport::new(SimulatedCamera::new(), 1).schedule(
+            Direction::Outgoing,
+            0,
+            Fault::Disconnect,
+        );
+        let mut transport = QueuedTransport::new(failing);
+        transport.connect(Duration::from_secs(1)).await.unwrap();
+
+        transport.send(&[0; 8]).await.unwrap();
+        while transport.error.lock().unwrap().is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(transport.send(&[0; 8]).await.is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::command::Command;
 use fixed::types::I5F11;
 use num_traits::cast::FromPrimitive;
+use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 use thiserror::Error;
 
@@ -18,11 +19,24 @@ pub enum CommandError {
     #[error("Not Enough Bytes")]
     NotEnoughBytes,
 
+    #[error("Value is too long: {0} bytes (max {1})")]
+    ValueTooLong(usize, usize),
+
+    #[error("Packet is too long: {0} bytes (max {1})")]
+    PacketTooLong(usize, usize),
+
+    #[error("Wrong number of values: {0} (expected {1})")]
+    WrongValueCount(usize, usize),
+
+    #[error("Parameter does not take numbers")]
+    NotNumeric,
+
     #[error(transparent)]
     UTF8Error(#[from] std::string::FromUtf8Error),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Operation {
     AssignValue,
     OffsetValue,
@@ -59,9 +73,14 @@ pub trait Parameter {
     fn to_bytes(&self) -> Vec<u8>;
 
     fn normalized_name(&self) -> String;
+
+    fn max_length(&self) -> Option<usize>;
 }
 
-#[derive(Debug)]
+/// Largest camera control packet the camera accepts, header included
+pub const MAX_PACKET_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct RawCommand {
     pub destination_device: u8,
     pub command_id: u8,
@@ -73,12 +92,17 @@ pub struct RawCommand {
 }
 
 impl RawCommand {
-    /// Takes the BLE name of the camera and returns a new BluetoothCamera instance
+    /// Parses a camera control packet into its fields
     pub fn from_raw(data: &[u8]) -> Result<Self, CommandError> {
-        if data.len() < 8 {
+        if data.len() < 8 || data[1] < 4 {
             return Err(CommandError::MessageShort);
         }
 
+        let end = 8 + (data[1] - 4) as usize;
+        if data.len() < end {
+            return Err(CommandError::NotEnoughBytes);
+        }
+
         Ok(RawCommand {
             destination_device: data[0],
             command_id: data[2],
@@ -87,10 +111,51 @@ impl RawCommand {
             data_type: data[6],
             operation: data[7],
 
-            data: data[8..8 + (data[1] - 4) as usize].to_vec(),
+            data: data[8..end].to_vec(),
         })
     }
 
+    /// Splits a buffer holding one or more camera control messages into the messages
+    ///
+    /// Each message is the 4 byte header, the length in its second byte and padding up
+    /// to the next 32-bit boundary. Bytes that do not make up a whole message are returned
+    /// as the last slice so nothing is lost.
+    pub fn split(data: &[u8]) -> Vec<&[u8]> {
+        let mut messages = Vec::new();
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                messages.push(rest);
+                break;
+            }
+
+            let len = (4 + rest[1] as usize + 3) & !3;
+            let (message, tail) = rest.split_at(len.min(rest.len()));
+            messages.push(message);
+            rest = tail;
+        }
+
+        messages
+    }
+
+    /// Serializes the fields back into a packet
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut v = vec![
+            self.destination_device,
+            self.data.len() as u8 + 4,
+            self.command_id,
+            0,
+            self.category,
+            self.parameter,
+            self.data_type,
+            self.operation,
+        ];
+        v.extend_from_slice(&self.data);
+
+        v
+    }
+
     pub fn to_raw(destination: u8, operation: Operation, cmd: &Command) -> Vec<u8> {
         let mut v = Vec::new();
 
@@ -119,6 +184,28 @@ impl RawCommand {
 
         v
     }
+
+    /// Same as to_raw but refuses to build packets the camera would not accept,
+    /// such as metadata strings longer than the spec allows
+    pub fn to_raw_checked(
+        destination: u8,
+        operation: Operation,
+        cmd: &Command,
+    ) -> Result<Vec<u8>, CommandError> {
+        if let Some(max) = cmd.max_length() {
+            let len = cmd.to_bytes().len();
+            if len > max {
+                return Err(CommandError::ValueTooLong(len, max));
+            }
+        }
+
+        let v = RawCommand::to_raw(destination, operation, cmd);
+        if v.len() > MAX_PACKET_LENGTH {
+            return Err(CommandError::PacketTooLong(v.len(), MAX_PACKET_LENGTH));
+        }
+
+        Ok(v)
+    }
 }
 
 pub trait ParamType {
@@ -129,6 +216,14 @@ pub trait ParamType {
     fn to_bytes(&self) -> Vec<u8>;
 
     fn data_as_string(&self) -> String;
+
+    /// Number of bytes a single value takes on the wire
+    fn byte_size() -> usize
+    where
+        Self: Sized,
+    {
+        std::mem::size_of::<Self>()
+    }
 }
 
 impl ParamType for String {
@@ -227,7 +322,7 @@ impl ParamType for i64 {
 
 impl ParamType for f32 {
     fn from_bytes(data: &[u8]) -> Result<Self, CommandError> {
-        data.chunks_exact(8)
+        data.chunks_exact(2)
             .next()
             .ok_or(CommandError::NotEnoughBytes)
             .map(|x| f32::from(I5F11::from_le_bytes(x.try_into().unwrap())))
@@ -240,11 +335,16 @@ impl ParamType for f32 {
     fn data_as_string(&self) -> String {
         self.to_string()
     }
+
+    // Sent as a fixed16, not as an IEEE float
+    fn byte_size() -> usize {
+        2
+    }
 }
 
 impl<T: ParamType> ParamType for Vec<T> {
     fn from_bytes(data: &[u8]) -> Result<Vec<T>, CommandError> {
-        data.chunks_exact(std::mem::size_of::<T>())
+        data.chunks_exact(T::byte_size())
             .map(<T as ParamType>::from_bytes)
             .collect()
     }
@@ -266,3 +366,39 @@ impl<T: ParamType> ParamType for Vec<T> {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{ColorCorrection, Lens};
+
+    #[test]
+    fn from_raw_rejects_length_past_end() {
+        // Claims 8 bytes of data but carries 2
+        let packet = [255, 12, 0, 0, 0, 0, 128, 0, 0x00, 0x04];
+        assert!(matches!(
+            RawCommand::from_raw(&packet),
+            Err(CommandError::NotEnoughBytes)
+        ));
+
+        // Length smaller than the header it counts
+        let packet = [255, 2, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(
+            RawCommand::from_raw(&packet),
+            Err(CommandError::MessageShort)
+        ));
+    }
+
+    #[test]
+    fn fixed16_decodes_from_two_bytes() {
+        let packet = [255, 6, 0, 0, 0, 0, 128, 0, 0x00, 0x04];
+        assert_eq!(
+            Command::from_raw(&packet).unwrap(),
+            Command::Lens(Lens::Focus(0.5))
+        );
+
+        let gain = Command::ColorCorrection(ColorCorrection::GainAdjust(vec![1.0, 1.5, 2.0, 0.25]));
+        let packet = RawCommand::to_raw(255, Operation::AssignValue, &gain);
+        assert_eq!(Command::from_raw(&packet).unwrap(), gain);
+    }
+}
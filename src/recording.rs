@@ -0,0 +1,445 @@
+use crate::characteristics::{
+    CAMERA_STATUS, INCOMING_CAMERA_CONTROL, OUTGOING_CAMERA_CONTROL, TIMECODE,
+};
+use crate::error::{CameraControlError, TransportError};
+use crate::transport::{CameraTransport, Direction, EventStream, TransportEvent};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// A single packet seen on the link to the camera
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedPacket {
+    pub direction: Direction,
+
+    /// Microseconds since the unix epoch
+    pub timestamp_us: u64,
+
+    /// The characteristic the packet was written to or notified on
+    pub characteristic: Uuid,
+
+    #[serde(with = "hex")]
+    pub data: Vec<u8>,
+}
+
+impl RecordedPacket {
+    /// Builds the packet for a transport event, None for events that carry no data
+    pub fn from_event(event: &TransportEvent, timestamp_us: u64) -> Option<RecordedPacket> {
+        let (characteristic, data) = match event {
+            TransportEvent::Packet(p) => (INCOMING_CAMERA_CONTROL, p.clone()),
+            TransportEvent::Timecode(t) => (TIMECODE, t.to_le_bytes().to_vec()),
+            TransportEvent::Status(s) => (CAMERA_STATUS, vec![*s]),
+            _ => return None,
+        };
+
+        Some(RecordedPacket {
+            direction: Direction::Incoming,
+            timestamp_us,
+            characteristic,
+            data,
+        })
+    }
+
+    /// Turns an incoming packet back into the event it was recorded from
+    pub fn to_event(&self) -> Option<TransportEvent> {
+        if self.direction != Direction::Incoming {
+            return None;
+        }
+
+        match self.characteristic {
+            INCOMING_CAMERA_CONTROL => Some(TransportEvent::Packet(self.data.clone())),
+            TIMECODE if self.data.len() == 4 => {
+                Some(TransportEvent::Timecode(u32::from_le_bytes([
+                    self.data[0],
+                    self.data[1],
+                    self.data[2],
+                    self.data[3],
+                ])))
+            }
+            CAMERA_STATUS if !self.data.is_empty() => Some(TransportEvent::Status(self.data[0])),
+            _ => None,
+        }
+    }
+}
+
+/// Somewhere recorded packets can be written to
+pub trait PacketWriter {
+    fn write_packet(&mut self, packet: &RecordedPacket) -> io::Result<()>;
+}
+
+/// Writes one JSON object per line, read it back with read_recording()
+#[derive(Debug)]
+pub struct JsonLinesWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(writer: W) -> JsonLinesWriter<W> {
+        JsonLinesWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> PacketWriter for JsonLinesWriter<W> {
+    fn write_packet(&mut self, packet: &RecordedPacket) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, packet)?;
+        self.writer.write_all(b"\n")?;
+        // Flush every packet so a crash still leaves a usable recording
+        self.writer.flush()
+    }
+}
+
+/// Reads a recording written by JsonLinesWriter
+///
+/// # Arguments
+///
+/// * `reader` - anything implementing Read, like a File
+pub fn read_recording<R: Read>(reader: R) -> io::Result<Vec<RecordedPacket>> {
+    let mut packets = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        packets.push(serde_json::from_str(&line)?);
+    }
+    Ok(packets)
+}
+
+// What the writer thread is asked to do next
+enum Entry {
+    Packet(RecordedPacket),
+    Sync(oneshot::Sender<()>),
+}
+
+// Hands packets to a thread that writes them, so the link never waits on the disk
+#[derive(Debug, Clone)]
+struct Recorder {
+    entries: UnboundedSender<Entry>,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl Recorder {
+    fn spawn(mut writer: Box<dyn PacketWriter + Send>) -> Recorder {
+        let (entries, mut rx) = mpsc::unbounded_channel();
+        let error = Arc::new(Mutex::new(None));
+
+        let errors = error.clone();
+        thread::spawn(move || {
+            while let Some(entry) = rx.blocking_recv() {
+                match entry {
+                    Entry::Packet(packet) => {
+                        if let Err(e) = writer.write_packet(&packet) {
+                            *errors.lock().unwrap() = Some(e);
+                        }
+                    }
+                    Entry::Sync(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+
+        Recorder { entries, error }
+    }
+
+    fn record(&self, packet: RecordedPacket) {
+        let _ = self.entries.send(Entry::Packet(packet));
+    }
+}
+
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Wraps a transport and records every packet going through it
+///
+/// Packets are written in the background, call sync() to wait for them to reach the
+/// writer. Failing to write the recording never interrupts the link to the camera, check
+/// take_error() to find out whether the recording is complete.
+#[derive(Debug)]
+pub struct RecordingTransport<T: CameraTransport> {
+    inner: T,
+    recorder: Recorder,
+}
+
+impl<T: CameraTransport> RecordingTransport<T> {
+    /// Wraps a transport, recording to the supplied writer
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - the CameraTransport to record traffic for
+    /// * `writer` - PacketWriter like this: JsonLinesWriter::new(file)
+    pub fn new<W: PacketWriter + Send + 'static>(inner: T, writer: W) -> RecordingTransport<T> {
+        RecordingTransport {
+            inner,
+            recorder: Recorder::spawn(Box::new(writer)),
+        }
+    }
+
+    /// Wraps a transport, recording as JSON lines to a newly created file
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - the CameraTransport to record traffic for
+    /// * `path` - where to write the recording, an existing file is overwritten
+    pub fn create<P: AsRef<Path>>(inner: T, path: P) -> io::Result<RecordingTransport<T>> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(RecordingTransport::new(inner, JsonLinesWriter::new(file)))
+    }
+
+    /// Waits until every packet recorded so far has been written
+    pub async fn sync(&self) {
+        let (done, written) = oneshot::channel();
+        if self.recorder.entries.send(Entry::Sync(done)).is_ok() {
+            let _ = written.await;
+        }
+    }
+
+    /// Returns the last error writing the recording, if any, and clears it
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.recorder.error.lock().unwrap().take()
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+#[async_trait]
+impl<T: CameraTransport> CameraTransport for RecordingTransport<T> {
+    type Error = T::Error;
+
+    async fn connect(&mut self, timeout: Duration) -> Result<(), T::Error> {
+        self.inner.connect(timeout).await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), T::Error> {
+        self.inner.disconnect().await
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), T::Error> {
+        let timestamp_us = now_us();
+        self.inner.send(packet).await?;
+
+        self.recorder.record(RecordedPacket {
+            direction: Direction::Outgoing,
+            timestamp_us,
+            characteristic: OUTGOING_CAMERA_CONTROL,
+            data: packet.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, T::Error> {
+        let recorder = self.recorder.clone();
+        let events = self.inner.events().await?.map(move |event| {
+            if let Some(packet) = RecordedPacket::from_event(&event, now_us()) {
+                recorder.record(packet);
+            }
+            event
+        });
+
+        Ok(Box::pin(events))
+    }
+}
+
+/// Plays a recording back as if it was a camera
+///
+/// Incoming packets are handed to `Camera` in the order and, by default, at the pace
+/// they were recorded. Whatever is sent to the replayed camera is kept so it can be
+/// compared with the outgoing packets of the recording.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    packets: Vec<RecordedPacket>,
+    speed: f64,
+
+    connected: bool,
+    sent: Vec<Vec<u8>>,
+}
+
+impl ReplayTransport {
+    /// Takes the packets of a recording and returns a new ReplayTransport
+    ///
+    /// # Arguments
+    ///
+    /// * `packets` - Vec<RecordedPacket> like the one read_recording() returns
+    pub fn new(packets: Vec<RecordedPacket>) -> ReplayTransport {
+        ReplayTransport {
+            packets,
+            speed: 1.0,
+
+            connected: false,
+            sent: Vec::new(),
+        }
+    }
+
+    /// Reads a JSON lines recording from disk and returns a new ReplayTransport
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<ReplayTransport> {
+        Ok(ReplayTransport::new(read_recording(File::open(path)?)?))
+    }
+
+    /// Sets how fast the recording plays back
+    ///
+    /// # Arguments
+    ///
+    /// * `speed` - f64 multiple of the recorded pace, 0.0 plays everything back at once
+    pub fn with_speed(mut self, speed: f64) -> ReplayTransport {
+        self.speed = speed.max(0.0);
+        self
+    }
+
+    /// Returns the recording being played back
+    pub fn packets(&self) -> &[RecordedPacket] {
+        &self.packets
+    }
+
+    /// Returns every packet sent to the replayed camera so far
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+}
+
+#[async_trait]
+impl CameraTransport for ReplayTransport {
+    type Error = TransportError;
+
+    async fn connect(&mut self, _timeout: Duration) -> Result<(), TransportError> {
+        self.connected = true;
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        self.connected = false;
+        Ok(())
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), TransportError> {
+        if !self.connected {
+            return Err(CameraControlError::Disconnected.into());
+        }
+        self.sent.push(packet.to_vec());
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, TransportError> {
+        if !self.connected {
+            return Err(TransportError::NotConnected);
+        }
+
+        let speed = self.speed;
+        let start = self.packets.first().map(|p| p.timestamp_us).unwrap_or(0);
+        let timed: Vec<(Duration, TransportEvent)> = self
+            .packets
+            .iter()
+            .filter_map(|p| {
+                let offset = p.timestamp_us.saturating_sub(start) as f64;
+                let at = if speed > 0.0 {
+                    Duration::from_micros((offset / speed) as u64)
+                } else {
+                    Duration::from_secs(0)
+                };
+                p.to_event().map(|e| (at, e))
+            })
+            .collect();
+
+        let begin = tokio::time::Instant::now();
+        let replay = stream::iter(timed).then(move |(at, event)| async move {
+            tokio::time::sleep_until(begin + at).await;
+            event
+        });
+
+        Ok(Box::pin(
+            stream::once(async { TransportEvent::Connected }).chain(replay),
+        ))
+    }
+}
+
+// Packet bytes as a hex string, so recordings stay readable and diffable
+pub(crate) mod hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let s: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.len() % 2 != 0 || !s.is_ascii() {
+            return Err(D::Error::custom("expected an even number of hex digits"));
+        }
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Command, Lens};
+    use crate::rawcommand::{Operation, RawCommand};
+    use crate::simulator::SimulatedCamera;
+
+    #[tokio::test]
+    async fn replays_what_was_recorded() {
+        let path = std::env::temp_dir().join(format!("recording-{}.jsonl", std::process::id()));
+        let mut transport = RecordingTransport::create(SimulatedCamera::new(), &path).unwrap();
+        transport.connect(Duration::from_secs(1)).await.unwrap();
+
+        // Only events that are polled get recorded
+        let mut events = transport.events().await.unwrap();
+        let mut live = Vec::new();
+        while live.len() < 10 {
+            let event = events.next().await.unwrap();
+            if RecordedPacket::from_event(&event, 0).is_some() {
+                live.push(event);
+            }
+        }
+
+        let focus =
+            RawCommand::to_raw(1, Operation::AssignValue, &Command::Lens(Lens::Focus(0.25)));
+        transport.send(&focus).await.unwrap();
+        transport.sync().await;
+        assert!(transport.take_error().is_none());
+
+        let recording = read_recording(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let sent: Vec<Vec<u8>> = recording
+            .iter()
+            .filter(|p| p.direction == Direction::Outgoing)
+            .map(|p| p.data.clone())
+            .collect();
+        assert_eq!(sent, vec![focus]);
+
+        let mut replay = ReplayTransport::new(recording).with_speed(0.0);
+        replay.connect(Duration::from_secs(1)).await.unwrap();
+        let replayed: Vec<TransportEvent> = replay.events().await.unwrap().collect().await;
+
+        assert_eq!(replayed[0], TransportEvent::Connected);
+        assert_eq!(replayed[1..], live[..]);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::bridge::{encode_packet, read_packet};
+use crate::camera::Camera;
+use crate::error::{CameraControlError, TransportError};
+use crate::transport::{CameraTransport, EventStream, TransportEvent};
+use async_trait::async_trait;
+use futures::stream;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time;
+
+/// A camera shared by a BridgeServer somewhere on the network
+pub type RemoteCamera = Camera<RemoteTransport>;
+
+impl Camera<RemoteTransport> {
+    /// Takes the address of the bridge and returns a new RemoteCamera instance
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - &str address of the bridge's raw TCP listener such as "10.0.0.2:9001"
+    pub fn new(addr: &str) -> RemoteCamera {
+        Camera::with_transport(RemoteTransport::new(addr))
+    }
+}
+
+/// Talks to a BridgeServer over its raw TCP protocol
+///
+/// The bridge sends everything the camera has reported as soon as we connect, so
+/// wait_synced and the cache behave as they do with the camera itself. Timecode and
+/// status are not forwarded by the bridge.
+#[derive(Debug)]
+pub struct RemoteTransport {
+    addr: String,
+
+    reader: Option<OwnedReadHalf>,
+    writer: Option<OwnedWriteHalf>,
+}
+
+impl RemoteTransport {
+    /// Takes the address of the bridge and returns a new RemoteTransport
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - &str address of the bridge's raw TCP listener such as "10.0.0.2:9001"
+    pub fn new(addr: &str) -> RemoteTransport {
+        RemoteTransport {
+            addr: addr.to_string(),
+
+            reader: None,
+            writer: None,
+        }
+    }
+}
+
+#[async_trait]
+impl CameraTransport for RemoteTransport {
+    type Error = TransportError;
+
+    /// Connects to the bridge, waiting as long as supplied timeout specifies
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - std::Duration of how long to wait before giving up
+    async fn connect(&mut self, timeout: Duration) -> Result<(), TransportError> {
+        self.disconnect().await?;
+
+        let stream = time::timeout(timeout, TcpStream::connect(&self.addr))
+            .await
+            .map_err(|_| CameraControlError::ConnectionTimeout)??;
+        stream.set_nodelay(true)?;
+
+        let (reader, writer) = stream.into_split();
+        self.reader = Some(reader);
+        self.writer = Some(writer);
+
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        if let Some(mut writer) = self.writer.take() {
+            let _ = writer.shutdown().await;
+        }
+        self.reader = None;
+        Ok(())
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), TransportError> {
+        let writer = self.writer.as_mut().ok_or(TransportError::NotConnected)?;
+
+        if writer.write_all(&encode_packet(packet)).await.is_err() {
+            self.writer = None;
+            return Err(CameraControlError::Disconnected.into());
+        }
+
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, TransportError> {
+        let mut reader = self.reader.take().ok_or(TransportError::NotConnected)?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let _ = tx.send(TransportEvent::Connected);
+        tokio::spawn(async move {
+            while let Ok(Some(packet)) = read_packet(&mut reader).await {
+                if tx.send(TransportEvent::Packet(packet)).is_err() {
+                    return;
+                }
+            }
+
+            let _ = tx.send(TransportEvent::Disconnected);
+        });
+
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::BridgeServer;
+    use crate::command::{Command, Lens, Video};
+    use crate::keys;
+    use crate::rawcommand::Operation;
+    use crate::simulator::SimulatedCamera;
+
+    // A bridge in front of a simulated camera with a few values set, and a RemoteCamera
+    // synced to it
+    async fn bridged() -> (BridgeServer<SimulatedCamera>, RemoteCamera) {
+        let simulator = SimulatedCamera::new();
+        simulator.set(Command::Video(Video::Iso(3200)));
+        simulator.set(Command::Lens(Lens::Focus(0.25)));
+
+        let mut camera = Camera::with_transport(simulator);
+        camera.connect(Duration::from_secs(1)).await.unwrap();
+        camera
+            .wait_synced(Duration::from_millis(50), Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        let mut server = BridgeServer::new(camera);
+        let addr = server.listen_tcp("127.0.0.1:0").await.unwrap();
+
+        let mut remote = RemoteCamera::new(&addr.to_string());
+        remote.connect(Duration::from_secs(1)).await.unwrap();
+        remote
+            .wait_synced(Duration::from_millis(50), Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        (server, remote)
+    }
+
+    #[tokio::test]
+    async fn syncs_the_bridge_snapshot() {
+        let (_server, remote) = bridged().await;
+
+        assert!(remote.is_connected());
+        assert_eq!(remote.get(keys::video::ISO).await, Some(3200));
+        assert_eq!(remote.get(keys::lens::FOCUS).await, Some(0.25));
+    }
+
+    #[tokio::test]
+    async fn reads_what_the_bridged_camera_has() {
+        let (server, remote) = bridged().await;
+        let camera = server.camera();
+        let camera = camera.lock().await;
+
+        assert_eq!(
+            remote.get(keys::video::ISO).await,
+            camera.get(keys::video::ISO).await
+        );
+        for name in ["video_iso", "lens_focus", "video_manual_white_balance"] {
+            assert!(remote.get_normalized(name).await.is_some());
+            assert_eq!(
+                remote.get_normalized(name).await,
+                camera.get_normalized(name).await
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_echo_back() {
+        let (_server, mut remote) = bridged().await;
+        let mut updates = remote.updates().await;
+
+        let iso = Command::Video(Video::Iso(640));
+        remote
+            .write(255, Operation::AssignValue, iso.clone())
+            .await
+            .unwrap();
+
+        let echoed = time::timeout(Duration::from_secs(2), async {
+            while updates.recv().await.unwrap() != iso {}
+        })
+        .await;
+        assert!(echoed.is_ok());
+        assert_eq!(remote.get(keys::video::ISO).await, Some(640));
+    }
+
+    #[tokio::test]
+    async fn reports_the_bridge_going_away() {
+        let (server, remote) = bridged().await;
+        drop(server);
+
+        let disconnected = time::timeout(Duration::from_secs(2), async {
+            while remote.is_connected() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(disconnected.is_ok());
+    }
+}
@@ -0,0 +1,607 @@
+use crate::command::{ColorCorrection, Command, Lens, Media, Video};
+use crate::error::TransportError;
+use crate::rawcommand::{Operation, RawCommand};
+use crate::transport::{CameraTransport, EventStream, TransportEvent};
+use async_trait::async_trait;
+use futures::stream::{self, SplitSink};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Path of the camera control API on the camera
+pub const API_PATH: &str = "/control/api/v1";
+
+/// Every property the transport maps onto commands, subscribed to over the WebSocket
+pub const PROPERTIES: &[&str] = &[
+    "/lens/focus",
+    "/lens/iris",
+    "/lens/zoom",
+    "/video/iso",
+    "/video/gain",
+    "/video/whiteBalance",
+    "/video/whiteBalanceTint",
+    "/video/shutter",
+    "/video/ndFilter",
+    "/colorCorrection/lift",
+    "/colorCorrection/gamma",
+    "/colorCorrection/gain",
+    "/colorCorrection/offset",
+    "/colorCorrection/contrast",
+    "/colorCorrection/color",
+    "/colorCorrection/lumaContribution",
+    "/transports/0/record",
+];
+
+/// Maps a command onto the REST requests that carry it, as (path, JSON body) pairs
+///
+/// Returns None for commands the REST API has no endpoint for.
+///
+/// # Arguments
+///
+/// * `command` - Command like this: Command::Video(Video::Iso(640))
+pub fn to_rest(command: &Command) -> Option<Vec<(&'static str, Value)>> {
+    let one = |path, body| Some(vec![(path, body)]);
+
+    match command {
+        Command::Lens(l) => match l {
+            Lens::Focus(v) => one("/lens/focus", json!({ "normalised": v })),
+            Lens::InstantaneousAutofocus => one("/lens/focus/doAutoFocus", json!({})),
+            Lens::ApertureFStop(v) => one("/lens/iris", json!({ "apertureStop": v })),
+            Lens::ApertureNormalised(v) => one("/lens/iris", json!({ "normalised": v })),
+            Lens::ApertureOrdinal(v) => one("/lens/iris", json!({ "apertureNumber": v })),
+            Lens::SetAbsoluteZoomMm(v) => one("/lens/zoom", json!({ "focalLength": v })),
+            Lens::SetAbsoluteZoomNormalised(v) => one("/lens/zoom", json!({ "normalised": v })),
+            _ => None,
+        },
+        Command::Video(v) => match v {
+            Video::Iso(v) => one("/video/iso", json!({ "iso": v })),
+            Video::Gain(v) => one("/video/gain", json!({ "gain": v })),
+            Video::ManualWhiteBalance(v) if v.len() == 2 => Some(vec![
+                ("/video/whiteBalance", json!({ "whiteBalance": v[0] })),
+                (
+                    "/video/whiteBalanceTint",
+                    json!({ "whiteBalanceTint": v[1] }),
+                ),
+            ]),
+            Video::SetAutoWb => one("/video/whiteBalance/doAuto", json!({})),
+            Video::ShutterAngle(v) => one("/video/shutter", json!({ "shutterAngle": v })),
+            Video::ShutterSpeed(v) => one("/video/shutter", json!({ "shutterSpeed": v })),
+            Video::NdFilter(v) if !v.is_empty() => one("/video/ndFilter", json!({ "stop": v[0] })),
+            _ => None,
+        },
+        Command::ColorCorrection(c) => match c {
+            ColorCorrection::LiftAdjust(v) => one("/colorCorrection/lift", rgbl(v)?),
+            ColorCorrection::GammaAdjust(v) => one("/colorCorrection/gamma", rgbl(v)?),
+            ColorCorrection::GainAdjust(v) => one("/colorCorrection/gain", rgbl(v)?),
+            ColorCorrection::OffsetAdjust(v) => one("/colorCorrection/offset", rgbl(v)?),
+            ColorCorrection::ContrastAdjust(v) if v.len() == 2 => one(
+                "/colorCorrection/contrast",
+                json!({ "pivot": v[0], "adjust": v[1] }),
+            ),
+            ColorCorrection::ColorAdjust(v) if v.len() == 2 => one(
+                "/colorCorrection/color",
+                json!({ "hue": v[0], "saturation": v[1] }),
+            ),
+            ColorCorrection::LumaMix(v) => one(
+                "/colorCorrection/lumaContribution",
+                json!({ "lumaContribution": v }),
+            ),
+            _ => None,
+        },
+        // Only recording maps onto the REST API, playback has its own endpoints
+        Command::Media(Media::TransportMode(v)) => match v.first() {
+            Some(0) => one("/transports/0/record", json!({ "recording": false })),
+            Some(2) => one("/transports/0/record", json!({ "recording": true })),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn rgbl(v: &[f32]) -> Option<Value> {
+    match v {
+        [red, green, blue, luma] => {
+            Some(json!({ "red": red, "green": green, "blue": blue, "luma": luma }))
+        }
+        _ => None,
+    }
+}
+
+/// Turns REST property values back into commands
+///
+/// White balance and tint are separate properties but a single command, so the mapper
+/// remembers the last of each to fill in the other and holds the command back until it
+/// has seen both.
+#[derive(Debug, Default)]
+pub struct RestMapper {
+    white_balance: Option<i16>,
+    tint: Option<i16>,
+}
+
+impl RestMapper {
+    pub fn new() -> RestMapper {
+        RestMapper::default()
+    }
+
+    /// Maps the value of a property, as sent in a WebSocket notification
+    ///
+    /// # Arguments
+    ///
+    /// * `property` - &str path like this: "/video/iso"
+    /// * `value` - JSON value of the property like this: {"iso": 400}
+    pub fn from_rest(&mut self, property: &str, value: &Value) -> Vec<Command> {
+        let f = |k: &str| value.get(k).and_then(|v| v.as_f64());
+        let i = |k: &str| value.get(k).and_then(|v| v.as_i64());
+        let mut commands = Vec::new();
+
+        match property {
+            "/lens/focus" => {
+                if let Some(v) = f("normalised") {
+                    commands.push(Command::Lens(Lens::Focus(v as f32)));
+                }
+            }
+            "/lens/iris" => {
+                if let Some(v) = f("apertureStop") {
+                    commands.push(Command::Lens(Lens::ApertureFStop(v as f32)));
+                }
+                if let Some(v) = f("normalised") {
+                    commands.push(Command::Lens(Lens::ApertureNormalised(v as f32)));
+                }
+                if let Some(v) = i("apertureNumber") {
+                    commands.push(Command::Lens(Lens::ApertureOrdinal(v as i16)));
+                }
+            }
+            "/lens/zoom" => {
+                if let Some(v) = i("focalLength") {
+                    commands.push(Command::Lens(Lens::SetAbsoluteZoomMm(v as i16)));
+                }
+                if let Some(v) = f("normalised") {
+                    commands.push(Command::Lens(Lens::SetAbsoluteZoomNormalised(v as f32)));
+                }
+            }
+            "/video/iso" => {
+                if let Some(v) = i("iso") {
+                    commands.push(Command::Video(Video::Iso(v as i32)));
+                }
+            }
+            "/video/gain" => {
+                if let Some(v) = i("gain") {
+                    commands.push(Command::Video(Video::Gain(v as i8)));
+                }
+            }
+            "/video/whiteBalance" | "/video/whiteBalanceTint" => {
+                if let Some(v) = i("whiteBalance") {
+                    self.white_balance = Some(v as i16);
+                }
+                if let Some(v) = i("whiteBalanceTint") {
+                    self.tint = Some(v as i16);
+                }
+                if let (Some(white_balance), Some(tint)) = (self.white_balance, self.tint) {
+                    commands.push(Command::Video(Video::ManualWhiteBalance(vec![
+                        white_balance,
+                        tint,
+                    ])));
+                }
+            }
+            "/video/shutter" => {
+                if let Some(v) = i("shutterAngle") {
+                    commands.push(Command::Video(Video::ShutterAngle(v as i32)));
+                }
+                if let Some(v) = i("shutterSpeed") {
+                    commands.push(Command::Video(Video::ShutterSpeed(v as i32)));
+                }
+            }
+            "/video/ndFilter" => {
+                if let Some(v) = f("stop") {
+                    commands.push(Command::Video(Video::NdFilter(vec![v as f32, 0.0])));
+                }
+            }
+            "/colorCorrection/lift"
+            | "/colorCorrection/gamma"
+            | "/colorCorrection/gain"
+            | "/colorCorrection/offset" => {
+                let v: Option<Vec<f32>> = ["red", "green", "blue", "luma"]
+                    .iter()
+                    .map(|k| f(k).map(|v| v as f32))
+                    .collect();
+                if let Some(v) = v {
+                    commands.push(Command::ColorCorrection(match property {
+                        "/colorCorrection/lift" => ColorCorrection::LiftAdjust(v),
+                        "/colorCorrection/gamma" => ColorCorrection::GammaAdjust(v),
+                        "/colorCorrection/gain" => ColorCorrection::GainAdjust(v),
+                        _ => ColorCorrection::OffsetAdjust(v),
+                    }));
+                }
+            }
+            "/colorCorrection/contrast" => {
+                if let (Some(pivot), Some(adjust)) = (f("pivot"), f("adjust")) {
+                    commands.push(Command::ColorCorrection(ColorCorrection::ContrastAdjust(
+                        vec![pivot as f32, adjust as f32],
+                    )));
+                }
+            }
+            "/colorCorrection/color" => {
+                if let (Some(hue), Some(saturation)) = (f("hue"), f("saturation")) {
+                    commands.push(Command::ColorCorrection(ColorCorrection::ColorAdjust(
+                        vec![hue as f32, saturation as f32],
+                    )));
+                }
+            }
+            "/colorCorrection/lumaContribution" => {
+                if let Some(v) = f("lumaContribution") {
+                    commands.push(Command::ColorCorrection(ColorCorrection::LumaMix(v as f32)));
+                }
+            }
+            "/transports/0/record" => {
+                if let Some(v) = value.get("recording").and_then(|v| v.as_bool()) {
+                    let mode = if v { 2 } else { 0 };
+                    commands.push(Command::Media(Media::TransportMode(vec![mode, 0, 0, 0, 0])));
+                }
+            }
+            _ => {}
+        }
+
+        commands
+    }
+}
+
+type WebSocketSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+/// Controls a networked camera through its REST API
+///
+/// Commands are sent as PUT requests and the camera's WebSocket notifications come back
+/// as incoming commands, so `Camera` works the same as over Bluetooth. Only commands
+/// with a REST endpoint can be written and only as absolute values.
+#[derive(Debug)]
+pub struct RestTransport {
+    host: String,
+    client: reqwest::Client,
+
+    sink: Option<WebSocketSink>,
+    incoming: Option<mpsc::UnboundedReceiver<TransportEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl RestTransport {
+    /// Takes the address of the camera and returns a new RestTransport
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - &str host name or address of the camera, with the port if it is not 80
+    pub fn new(host: &str) -> RestTransport {
+        RestTransport {
+            host: host.to_string(),
+            client: reqwest::Client::new(),
+
+            sink: None,
+            incoming: None,
+            task: None,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}{}", self.host, API_PATH, path)
+    }
+
+    async fn receive(
+        mut stream: stream::SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+        tx: mpsc::UnboundedSender<TransportEvent>,
+    ) {
+        let mut mapper = RestMapper::new();
+
+        while let Some(Ok(message)) = stream.next().await {
+            let text = match message {
+                Message::Text(t) => t,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            let message: Value = match serde_json::from_str(&text) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let data = &message["data"];
+
+            // Subscribing answers with the current values, later changes come as events
+            let mut values = Vec::new();
+            match data["action"].as_str() {
+                Some("propertyValueChanged") => {
+                    if let Some(p) = data["property"].as_str() {
+                        values.push((p.to_string(), data["value"].clone()));
+                    }
+                }
+                Some("subscribe") => {
+                    if let Some(v) = data["values"].as_object() {
+                        values.extend(v.iter().map(|(p, v)| (p.clone(), v.clone())));
+                    }
+                }
+                _ => {}
+            }
+
+            for (property, value) in values {
+                for command in mapper.from_rest(&property, &value) {
+                    let packet = RawCommand::to_raw(255, Operation::AssignValue, &command);
+                    if tx.send(TransportEvent::Packet(packet)).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let _ = tx.send(TransportEvent::Disconnected);
+    }
+}
+
+#[async_trait]
+impl CameraTransport for RestTransport {
+    type Error = TransportError;
+
+    /// Opens the notification WebSocket, waiting as long as supplied timeout specifies
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - std::Duration of how long to wait before giving up
+    async fn connect(&mut self, timeout: Duration) -> Result<(), TransportError> {
+        self.disconnect().await?;
+
+        let url = format!("ws://{}{}/event/websocket", self.host, API_PATH);
+        let (socket, _) = time::timeout(timeout, tokio_tungstenite::connect_async(url))
+            .await
+            .map_err(|_| crate::error::CameraControlError::ConnectionTimeout)??;
+
+        let (mut sink, stream) = socket.split();
+        let subscribe = json!({
+            "type": "request",
+            "data": { "action": "subscribe", "properties": PROPERTIES },
+        });
+        sink.send(Message::Text(subscribe.to_string())).await?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let _ = tx.send(TransportEvent::Connected);
+
+        self.task = Some(tokio::spawn(RestTransport::receive(stream, tx)));
+        self.sink = Some(sink);
+        self.incoming = Some(rx);
+
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if let Some(mut sink) = self.sink.take() {
+            let _ = sink.close().await;
+        }
+        self.incoming = None;
+        Ok(())
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), TransportError> {
+        if self.sink.is_none() {
+            return Err(TransportError::NotConnected);
+        }
+
+        let raw = RawCommand::from_raw(packet)?;
+        if raw.operation != Operation::AssignValue.id() {
+            return Err(TransportError::Unsupported(
+                "offsetting values over REST".to_string(),
+            ));
+        }
+
+        let command = Command::from_raw(packet)?;
+        let requests = to_rest(&command)
+            .ok_or_else(|| TransportError::Unsupported(command.normalized_name().1))?;
+
+        for (path, body) in requests {
+            let response = self.client.put(self.url(path)).json(&body).send().await?;
+            if !response.status().is_success() {
+                return Err(TransportError::HttpStatus(response.status().as_u16()));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, TransportError> {
+        let mut rx = self.incoming.take().ok_or(TransportError::NotConnected)?;
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    type Requests = mpsc::UnboundedReceiver<(String, Value)>;
+
+    // Stands in for the camera, passing on every request it gets and sending whatever
+    // notifications it is handed
+    async fn camera() -> (String, Requests, mpsc::UnboundedSender<Value>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let (requests, rx) = mpsc::unbounded_channel();
+        let (notify, notifications) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut notifications = Some(notifications);
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let head = loop {
+                    let mut buf = [0u8; 2048];
+                    let n = stream.peek(&mut buf).await.unwrap();
+                    let head = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                    if head.contains("\r\n\r\n") {
+                        break head;
+                    }
+                };
+
+                if head.contains("upgrade: websocket") {
+                    let socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    tokio::spawn(websocket(
+                        socket,
+                        requests.clone(),
+                        notifications.take().unwrap(),
+                    ));
+                } else {
+                    tokio::spawn(http(stream, requests.clone()));
+                }
+            }
+        });
+
+        (host, rx, notify)
+    }
+
+    async fn websocket(
+        mut socket: WebSocketStream<TcpStream>,
+        requests: mpsc::UnboundedSender<(String, Value)>,
+        mut notifications: mpsc::UnboundedReceiver<Value>,
+    ) {
+        if let Some(Ok(Message::Text(t))) = socket.next().await {
+            let _ = requests.send(("WS".to_string(), serde_json::from_str(&t).unwrap()));
+        }
+        while let Some(v) = notifications.recv().await {
+            socket.send(Message::Text(v.to_string())).await.unwrap();
+        }
+    }
+
+    async fn http(mut stream: TcpStream, requests: mpsc::UnboundedSender<(String, Value)>) {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 2048];
+
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                return;
+            }
+            data.extend_from_slice(&buf[..n]);
+
+            let end = match data.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(i) => i + 4,
+                None => continue,
+            };
+            let head = String::from_utf8_lossy(&data[..end]).to_string();
+            let length = head
+                .lines()
+                .find_map(|l| {
+                    let l = l.to_lowercase();
+                    l.strip_prefix("content-length:")
+                        .map(|v| v.trim().parse::<usize>().unwrap())
+                })
+                .unwrap_or(0);
+            if data.len() < end + length {
+                continue;
+            }
+
+            let request: Vec<&str> = head.split(' ').take(2).collect();
+            let body = serde_json::from_slice(&data[end..end + length]).unwrap();
+            let _ = requests.send((request.join(" "), body));
+
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            return;
+        }
+    }
+
+    async fn next_command(events: &mut EventStream) -> Command {
+        match time::timeout(Duration::from_secs(5), events.next()).await {
+            Ok(Some(TransportEvent::Packet(p))) => Command::from_raw(&p).unwrap(),
+            other => panic!("expected a packet, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn holds_white_balance_until_tint_is_known() {
+        let mut mapper = RestMapper::new();
+
+        assert!(mapper
+            .from_rest("/video/whiteBalance", &json!({ "whiteBalance": 5600 }))
+            .is_empty());
+        assert_eq!(
+            mapper.from_rest(
+                "/video/whiteBalanceTint",
+                &json!({ "whiteBalanceTint": 10 })
+            ),
+            vec![Command::Video(Video::ManualWhiteBalance(vec![5600, 10]))]
+        );
+        assert_eq!(
+            mapper.from_rest("/video/whiteBalance", &json!({ "whiteBalance": 3200 })),
+            vec![Command::Video(Video::ManualWhiteBalance(vec![3200, 10]))]
+        );
+    }
+
+    #[tokio::test]
+    async fn talks_to_a_camera() {
+        let (host, mut requests, notify) = camera().await;
+        let mut transport = RestTransport::new(&host);
+        transport.connect(Duration::from_secs(5)).await.unwrap();
+        let mut events = transport.events().await.unwrap();
+        assert_eq!(events.next().await, Some(TransportEvent::Connected));
+
+        let (kind, subscribe) = requests.recv().await.unwrap();
+        assert_eq!(kind, "WS");
+        assert_eq!(subscribe["data"]["action"], "subscribe");
+        assert_eq!(subscribe["data"]["properties"], json!(PROPERTIES));
+
+        // Current values come back first, white balance waits for its tint
+        notify
+            .send(json!({
+                "type": "response",
+                "data": {
+                    "action": "subscribe",
+                    "values": {
+                        "/video/iso": { "iso": 800 },
+                        "/video/whiteBalance": { "whiteBalance": 5600 },
+                    },
+                },
+            }))
+            .unwrap();
+        notify
+            .send(json!({
+                "type": "event",
+                "data": {
+                    "action": "propertyValueChanged",
+                    "property": "/video/whiteBalanceTint",
+                    "value": { "whiteBalanceTint": -4 },
+                },
+            }))
+            .unwrap();
+
+        assert_eq!(
+            next_command(&mut events).await,
+            Command::Video(Video::Iso(800))
+        );
+        assert_eq!(
+            next_command(&mut events).await,
+            Command::Video(Video::ManualWhiteBalance(vec![5600, -4]))
+        );
+
+        let iso = RawCommand::to_raw(
+            255,
+            Operation::AssignValue,
+            &Command::Video(Video::Iso(640)),
+        );
+        transport.send(&iso).await.unwrap();
+        assert_eq!(
+            requests.recv().await.unwrap(),
+            (format!("PUT {}/video/iso", API_PATH), json!({ "iso": 640 }))
+        );
+
+        let offset =
+            RawCommand::to_raw(255, Operation::OffsetValue, &Command::Video(Video::Iso(1)));
+        assert!(matches!(
+            transport.send(&offset).await,
+            Err(TransportError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,456 @@
+use crate::command::{Command, Tally};
+use crate::error::AncError;
+use crate::rawcommand::{Operation, RawCommand};
+
+/// Data ID shared by the Blackmagic camera control and tally packets
+pub const BLACKMAGIC_DID: u8 = 0x51;
+/// Secondary data ID of camera control packets
+pub const CAMERA_CONTROL_SDID: u8 = 0x53;
+/// Secondary data ID of tally packets
+pub const TALLY_SDID: u8 = 0x52;
+
+/// Most user data words a single ancillary packet can carry
+pub const MAX_USER_DATA: usize = 255;
+
+/// The ancillary data flag every component ANC packet starts with
+pub const DATA_FLAG: [u16; 3] = [0x000, 0x3FF, 0x3FF];
+
+/// A SMPTE 291M ancillary data packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AncPacket {
+    pub did: u8,
+    pub sdid: u8,
+    pub data: Vec<u8>,
+}
+
+impl AncPacket {
+    /// Takes the ids and user data and returns a new AncPacket
+    ///
+    /// # Arguments
+    ///
+    /// * `did` - u8 data id
+    /// * `sdid` - u8 secondary data id
+    /// * `data` - Vec<u8> user data, at most 255 bytes
+    pub fn new(did: u8, sdid: u8, data: Vec<u8>) -> Result<AncPacket, AncError> {
+        if data.len() > MAX_USER_DATA {
+            return Err(AncError::TooLong(data.len()));
+        }
+        Ok(AncPacket { did, sdid, data })
+    }
+
+    /// Packs camera control messages into as few ancillary packets as they fit in
+    ///
+    /// Every message is padded to a 32-bit boundary as the SDI camera control protocol
+    /// requires and messages are never split across packets.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - camera control packets, as built by RawCommand::to_raw
+    pub fn camera_control<M: AsRef<[u8]>>(messages: &[M]) -> Result<Vec<AncPacket>, AncError> {
+        let mut packets = Vec::new();
+        let mut data: Vec<u8> = Vec::new();
+
+        for m in messages {
+            let mut message = m.as_ref().to_vec();
+            message.resize((message.len() + 3) & !3, 0);
+
+            if message.len() > MAX_USER_DATA {
+                return Err(AncError::TooLong(message.len()));
+            }
+
+            if data.len() + message.len() > MAX_USER_DATA {
+                packets.push(AncPacket::new(
+                    BLACKMAGIC_DID,
+                    CAMERA_CONTROL_SDID,
+                    std::mem::take(&mut data),
+                )?);
+            }
+            data.extend_from_slice(&message);
+        }
+
+        if !data.is_empty() {
+            packets.push(AncPacket::new(BLACKMAGIC_DID, CAMERA_CONTROL_SDID, data)?);
+        }
+
+        Ok(packets)
+    }
+
+    /// Packs commands into camera control ancillary packets
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - u8 camera id, 255 to broadcast to all cameras
+    /// * `operation` - Operation to perform with the values
+    /// * `commands` - &[Command] like this: &[Command::Video(Video::Iso(640))]
+    pub fn from_commands(
+        destination: u8,
+        operation: Operation,
+        commands: &[Command],
+    ) -> Result<Vec<AncPacket>, AncError> {
+        let messages = commands
+            .iter()
+            .map(|c| RawCommand::to_raw_checked(destination, operation, c))
+            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+
+        AncPacket::camera_control(&messages)
+    }
+
+    /// Returns the camera control messages carried by a camera control packet
+    pub fn messages(&self) -> Result<Vec<&[u8]>, AncError> {
+        if self.did != BLACKMAGIC_DID || self.sdid != CAMERA_CONTROL_SDID {
+            return Err(AncError::WrongPacket(self.did, self.sdid));
+        }
+        Ok(RawCommand::split(&self.data))
+    }
+
+    /// Decodes the commands carried by a camera control packet
+    pub fn commands(&self) -> Result<Vec<Command>, AncError> {
+        self.messages()?
+            .into_iter()
+            .map(|m| Ok(Command::from_raw(m)?))
+            .collect()
+    }
+
+    /// Encodes the packet as 10-bit words, data flag and checksum included
+    pub fn to_words(&self) -> Vec<u16> {
+        let mut words = DATA_FLAG.to_vec();
+
+        words.push(with_parity(self.did));
+        words.push(with_parity(self.sdid));
+        words.push(with_parity(self.data.len() as u8));
+        words.extend(self.data.iter().map(|b| with_parity(*b)));
+
+        words.push(checksum(&words[3..]));
+
+        words
+    }
+
+    /// Decodes a packet from 10-bit words, checking parity and the checksum
+    ///
+    /// # Arguments
+    ///
+    /// * `words` - &[u16] starting with the data flag, anything after the checksum is ignored
+    pub fn from_words(words: &[u16]) -> Result<AncPacket, AncError> {
+        if words.len() < 3 || words[0..3] != DATA_FLAG {
+            return Err(AncError::MissingDataFlag);
+        }
+        if words.len() < 7 {
+            return Err(AncError::Truncated);
+        }
+
+        let count = (words[5] & 0xFF) as usize;
+        let end = 6 + count;
+        if words.len() < end + 1 {
+            return Err(AncError::Truncated);
+        }
+
+        let mut bytes = Vec::with_capacity(count + 3);
+        for (i, w) in words[3..end].iter().enumerate() {
+            if with_parity(*w as u8) != *w {
+                return Err(AncError::Parity(i + 3));
+            }
+            bytes.push(*w as u8);
+        }
+
+        let expected = checksum(&words[3..end]);
+        if words[end] != expected {
+            return Err(AncError::Checksum(expected, words[end]));
+        }
+
+        Ok(AncPacket {
+            did: bytes[0],
+            sdid: bytes[1],
+            data: bytes[3..].to_vec(),
+        })
+    }
+}
+
+/// Tally lights of a single camera
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CameraTally {
+    pub program: bool,
+    pub preview: bool,
+}
+
+/// Program and preview tally for every camera on an SDI feed
+///
+/// Each camera takes four bits of the tally packet, bit 0 for program and bit 1 for
+/// preview, starting with camera 1 in the low bits of the first byte. Cameras past the
+/// last one with a light on are off whether they were set or not, so they make no
+/// difference to equality.
+#[derive(Debug, Clone, Default)]
+pub struct TallyState {
+    cameras: Vec<CameraTally>,
+}
+
+impl TallyState {
+    pub fn new() -> TallyState {
+        TallyState::default()
+    }
+
+    /// Sets the tally of a camera
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - u8 camera id, counting from 1
+    /// * `tally` - CameraTally with the lights to turn on
+    pub fn set(&mut self, camera: u8, tally: CameraTally) {
+        if camera == 0 {
+            return;
+        }
+
+        let i = camera as usize - 1;
+        if self.cameras.len() <= i {
+            self.cameras.resize(i + 1, CameraTally::default());
+        }
+        self.cameras[i] = tally;
+    }
+
+    /// Returns the tally of a camera, all off for cameras that were never set
+    pub fn get(&self, camera: u8) -> CameraTally {
+        match camera {
+            0 => CameraTally::default(),
+            c => self
+                .cameras
+                .get(c as usize - 1)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the camera ids and tally of every camera up to the highest id set
+    pub fn cameras(&self) -> impl Iterator<Item = (u8, CameraTally)> + '_ {
+        self.cameras
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (i as u8 + 1, *t))
+    }
+
+    // Cameras up to the last one with a light on
+    fn lit(&self) -> &[CameraTally] {
+        let used = self
+            .cameras
+            .iter()
+            .rposition(|t| t.program || t.preview)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        &self.cameras[..used]
+    }
+
+    /// Packs the tally into the user data of a tally packet
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let lit = self.lit();
+
+        let mut bytes = vec![0u8; lit.len().div_ceil(2)];
+        for (i, t) in lit.iter().enumerate() {
+            let nibble = (t.program as u8) | ((t.preview as u8) << 1);
+            bytes[i / 2] |= nibble << ((i % 2) * 4);
+        }
+        bytes
+    }
+
+    /// Unpacks the user data of a tally packet
+    pub fn from_bytes(data: &[u8]) -> TallyState {
+        let mut state = TallyState::new();
+        for (i, b) in data.iter().enumerate() {
+            for half in 0..2 {
+                let nibble = b >> (half * 4);
+                let camera = i * 2 + half + 1;
+                if camera < 255 {
+                    state.set(
+                        camera as u8,
+                        CameraTally {
+                            program: nibble & 0x1 != 0,
+                            preview: nibble & 0x2 != 0,
+                        },
+                    );
+                }
+            }
+        }
+        state
+    }
+
+    /// Builds the tally ancillary packet
+    pub fn to_anc(&self) -> Result<AncPacket, AncError> {
+        AncPacket::new(BLACKMAGIC_DID, TALLY_SDID, self.to_bytes())
+    }
+
+    /// Reads a tally ancillary packet
+    pub fn from_anc(packet: &AncPacket) -> Result<TallyState, AncError> {
+        if packet.did != BLACKMAGIC_DID || packet.sdid != TALLY_SDID {
+            return Err(AncError::WrongPacket(packet.did, packet.sdid));
+        }
+        Ok(TallyState::from_bytes(&packet.data))
+    }
+
+    /// Turns the tally into front tally brightness commands, one per camera
+    ///
+    /// The camera only has a single tally light, so program wins over preview and
+    /// cameras with neither are turned off.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - f32 brightness from 0.0 to 1.0 for cameras on program
+    /// * `preview` - f32 brightness from 0.0 to 1.0 for cameras on preview
+    pub fn to_commands(&self, program: f32, preview: f32) -> Vec<(u8, Command)> {
+        self.cameras()
+            .map(|(camera, t)| {
+                let brightness = match (t.program, t.preview) {
+                    (true, _) => program,
+                    (false, true) => preview,
+                    _ => 0.0,
+                };
+                (
+                    camera,
+                    Command::Tally(Tally::FrontTallyBrightness(brightness)),
+                )
+            })
+            .collect()
+    }
+}
+
+impl PartialEq for TallyState {
+    fn eq(&self, other: &TallyState) -> bool {
+        self.lit() == other.lit()
+    }
+}
+
+impl Eq for TallyState {}
+
+// Bit 8 is the even parity of bits 0-7 and bit 9 its inverse
+fn with_parity(b: u8) -> u16 {
+    let parity = (b.count_ones() & 1) as u16;
+    (b as u16) | (parity << 8) | ((parity ^ 1) << 9)
+}
+
+// Nine bit sum of DID through the last user data word, bit 9 the inverse of bit 8
+fn checksum(words: &[u16]) -> u16 {
+    let sum = words.iter().fold(0u16, |s, w| (s + (w & 0x1FF)) & 0x1FF);
+    sum | ((!sum & 0x100) << 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Lens, Video};
+
+    #[test]
+    fn parity_bits() {
+        for b in 0..=255u8 {
+            let word = with_parity(b);
+            let b8 = (word >> 8) & 1;
+            assert_eq!(word & 0xFF, b as u16);
+            assert_eq!(b8, (b.count_ones() & 1) as u16);
+            assert_eq!(word >> 9, b8 ^ 1);
+        }
+    }
+
+    #[test]
+    fn encodes_a_known_packet() {
+        let packet =
+            AncPacket::new(BLACKMAGIC_DID, CAMERA_CONTROL_SDID, vec![0xFF, 4, 0, 0]).unwrap();
+
+        assert_eq!(
+            packet.to_words(),
+            [0x000, 0x3FF, 0x3FF, 0x151, 0x253, 0x104, 0x2FF, 0x104, 0x200, 0x200, 0x2AB]
+        );
+    }
+
+    #[test]
+    fn rejects_damaged_packets() {
+        let words = AncPacket::new(BLACKMAGIC_DID, CAMERA_CONTROL_SDID, vec![0xFF, 4, 0, 0])
+            .unwrap()
+            .to_words();
+
+        let mut parity = words.clone();
+        parity[7] ^= 0x100;
+        assert!(matches!(
+            AncPacket::from_words(&parity),
+            Err(AncError::Parity(7))
+        ));
+
+        let mut checksum = words.clone();
+        checksum[10] = 0x2AC;
+        assert!(matches!(
+            AncPacket::from_words(&checksum),
+            Err(AncError::Checksum(0x2AB, 0x2AC))
+        ));
+
+        assert!(matches!(
+            AncPacket::from_words(&words[..10]),
+            Err(AncError::Truncated)
+        ));
+        assert!(matches!(
+            AncPacket::from_words(&words[1..]),
+            Err(AncError::MissingDataFlag)
+        ));
+    }
+
+    #[test]
+    fn words_round_trip() {
+        let commands = [
+            Command::Lens(Lens::Focus(0.5)),
+            Command::Video(Video::Iso(800)),
+        ];
+        let packets = AncPacket::from_commands(1, Operation::AssignValue, &commands).unwrap();
+        assert_eq!(packets.len(), 1);
+
+        // Words after the checksum belong to whatever follows
+        let mut words = packets[0].to_words();
+        words.extend_from_slice(&[0x040, 0x200]);
+
+        let decoded = AncPacket::from_words(&words).unwrap();
+        assert_eq!(decoded, packets[0]);
+        assert_eq!(decoded.commands().unwrap(), commands);
+    }
+
+    #[test]
+    fn tally_round_trips() {
+        let mut state = TallyState::new();
+        state.set(
+            1,
+            CameraTally {
+                program: true,
+                preview: false,
+            },
+        );
+        assert_eq!(state.to_bytes(), [0x01]);
+        assert_eq!(TallyState::from_bytes(&state.to_bytes()), state);
+
+        state.set(
+            4,
+            CameraTally {
+                program: true,
+                preview: true,
+            },
+        );
+        state.set(
+            5,
+            CameraTally {
+                program: false,
+                preview: true,
+            },
+        );
+        assert_eq!(state.to_bytes(), [0x01, 0x30, 0x02]);
+        assert_eq!(TallyState::from_bytes(&state.to_bytes()), state);
+
+        let packet = state.to_anc().unwrap();
+        assert_eq!((packet.did, packet.sdid), (BLACKMAGIC_DID, TALLY_SDID));
+        let decoded = TallyState::from_anc(&AncPacket::from_words(&packet.to_words()).unwrap());
+        assert_eq!(decoded.unwrap(), state);
+    }
+
+    #[test]
+    fn cameras_left_off_do_not_count() {
+        let mut state = TallyState::new();
+        state.set(3, CameraTally::default());
+        assert_eq!(state, TallyState::new());
+        assert!(state.to_bytes().is_empty());
+
+        let wrong = AncPacket::new(BLACKMAGIC_DID, CAMERA_CONTROL_SDID, vec![]).unwrap();
+        assert!(matches!(
+            TallyState::from_anc(&wrong),
+            Err(AncError::WrongPacket(BLACKMAGIC_DID, CAMERA_CONTROL_SDID))
+        ));
+    }
+}
@@ -0,0 +1,364 @@
+use crate::error::{CameraControlError, TransportError};
+use crate::rawcommand::{CommandError, MAX_PACKET_LENGTH};
+use crate::transport::{CameraTransport, EventStream, TransportEvent};
+use async_trait::async_trait;
+use futures::stream;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::mpsc;
+
+/// First byte of every frame
+pub const SYNC: u8 = 0xA5;
+
+/// Largest packet a frame can carry, longer lengths are treated as line noise
+pub const MAX_FRAME_PAYLOAD: usize = MAX_PACKET_LENGTH;
+
+/// Wraps a camera control packet in a frame
+///
+/// A frame is the sync byte, the payload length, the payload and a checksum that makes
+/// the length, payload and checksum bytes sum to zero.
+///
+/// # Arguments
+///
+/// * `packet` - &[u8] of at most MAX_FRAME_PAYLOAD bytes, as built by RawCommand::to_raw
+pub fn encode_frame(packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(packet.len() + 3);
+    frame.push(SYNC);
+    frame.push(packet.len() as u8);
+    frame.extend_from_slice(packet);
+    frame.push(checksum(&frame[1..]));
+    frame
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |s, b| s.wrapping_add(*b))
+        .wrapping_neg()
+}
+
+/// Pulls frames out of a byte stream
+///
+/// Bytes that do not make up a valid frame are skipped until the next sync byte, so
+/// line noise or a reset microcontroller only costs the frames it hits.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    discarded: u64,
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::default()
+    }
+
+    /// Adds bytes read from the stream
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns the next complete frame's payload, None until more bytes are needed
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        loop {
+            match self.buffer.iter().position(|b| *b == SYNC) {
+                Some(i) => self.discard(i),
+                None => {
+                    let len = self.buffer.len();
+                    self.discard(len);
+                    return None;
+                }
+            }
+
+            if self.buffer.len() < 2 {
+                return None;
+            }
+
+            let length = self.buffer[1] as usize;
+            if length > MAX_FRAME_PAYLOAD {
+                self.discard(1);
+                continue;
+            }
+            if self.buffer.len() < length + 3 {
+                return None;
+            }
+
+            let frame = &self.buffer[1..length + 3];
+            if checksum(frame) == 0 {
+                let payload = self.buffer[2..length + 2].to_vec();
+                self.buffer.drain(..length + 3);
+                return Some(payload);
+            }
+
+            // Not a frame after all, look for the next sync byte
+            self.discard(1);
+        }
+    }
+
+    /// Returns how many bytes have been skipped while resynchronising
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    fn discard(&mut self, n: usize) {
+        self.buffer.drain(..n);
+        self.discarded += n as u64;
+    }
+}
+
+/// Carries camera control packets over a serial line or any other byte stream
+///
+/// Meant for rigs where a microcontroller, such as one with the 3G-SDI shield, injects
+/// the packets into SDI. Open the port with a serial crate like tokio-serial and hand the
+/// stream over, everything else works like any other CameraTransport.
+#[derive(Debug)]
+pub struct SerialTransport<S> {
+    stream: Option<S>,
+    reader: Option<ReadHalf<S>>,
+    writer: Option<WriteHalf<S>>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static> SerialTransport<S> {
+    /// Takes an open byte stream and returns a new SerialTransport
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - anything implementing AsyncRead and AsyncWrite
+    pub fn new(stream: S) -> SerialTransport<S> {
+        SerialTransport {
+            stream: Some(stream),
+            reader: None,
+            writer: None,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static> CameraTransport
+    for SerialTransport<S>
+{
+    type Error = TransportError;
+
+    /// Splits the stream, once the stream is closed it can not be connected again
+    async fn connect(&mut self, _timeout: Duration) -> Result<(), TransportError> {
+        let stream = self.stream.take().ok_or(TransportError::Closed)?;
+        let (reader, writer) = tokio::io::split(stream);
+        self.reader = Some(reader);
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.shutdown().await?;
+        }
+        self.reader = None;
+        Ok(())
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), TransportError> {
+        let writer = self.writer.as_mut().ok_or(TransportError::NotConnected)?;
+
+        if packet.len() > MAX_FRAME_PAYLOAD {
+            return Err(CommandError::PacketTooLong(packet.len(), MAX_FRAME_PAYLOAD).into());
+        }
+
+        let result = async {
+            writer.write_all(&encode_frame(packet)).await?;
+            writer.flush().await
+        }
+        .await;
+
+        if result.is_err() {
+            self.writer = None;
+            return Err(CameraControlError::Disconnected.into());
+        }
+
+        Ok(())
+    }
+
+    async fn events(&mut self) -> Result<EventStream, TransportError> {
+        let mut reader = self.reader.take().ok_or(TransportError::NotConnected)?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let _ = tx.send(TransportEvent::Connected);
+        tokio::spawn(async move {
+            let mut decoder = FrameDecoder::new();
+            let mut buf = [0u8; 256];
+
+            loop {
+                match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        decoder.push(&buf[..n]);
+                        while let Some(packet) = decoder.next_frame() {
+                            if tx.send(TransportEvent::Packet(packet)).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let _ = tx.send(TransportEvent::Disconnected);
+        });
+
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_carry_length_and_checksum() {
+        let frame = encode_frame(&[1, 2, 3]);
+
+        assert_eq!(frame[..5], [SYNC, 3, 1, 2, 3]);
+        assert_eq!(frame.len(), 6);
+        assert_eq!(frame[1..].iter().fold(0u8, |s, b| s.wrapping_add(*b)), 0);
+    }
+
+    #[test]
+    fn decodes_frames_split_over_reads() {
+        let frame = encode_frame(&[1, 2, 3]);
+        let mut decoder = FrameDecoder::new();
+
+        decoder.push(&frame[..2]);
+        assert_eq!(decoder.next_frame(), None);
+        decoder.push(&frame[2..]);
+        assert_eq!(decoder.next_frame(), Some(vec![1, 2, 3]));
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decoder.discarded(), 0);
+    }
+
+    #[test]
+    fn skips_bad_checksums_and_lengths() {
+        let mut bad = encode_frame(&[1, 2, 3]);
+        *bad.last_mut().unwrap() ^= 0xff;
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bad);
+        decoder.push(&[SYNC, MAX_FRAME_PAYLOAD as u8 + 1]);
+        decoder.push(&encode_frame(&[4]));
+
+        assert_eq!(decoder.next_frame(), Some(vec![4]));
+        assert_eq!(decoder.discarded(), bad.len() as u64 + 2);
+    }
+
+    #[test]
+    fn resyncs_after_garbage() {
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&[0x00, 0x42, SYNC, SYNC, 0xff]);
+        decoder.push(&encode_frame(&[5, 6]));
+        decoder.push(&[0x99]);
+        decoder.push(&encode_frame(&[7]));
+
+        assert_eq!(decoder.next_frame(), Some(vec![5, 6]));
+        assert_eq!(decoder.next_frame(), Some(vec![7]));
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decoder.discarded(), 6);
+    }
+
+    #[cfg(unix)]
+    mod pty {
+        use super::*;
+        use futures::StreamExt;
+        use std::io;
+        use std::os::unix::io::FromRawFd;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+        use tokio::io::ReadBuf;
+        use tokio::time::timeout;
+
+        // A tokio File does one thing at a time, so reading and writing get a file each
+        struct Pty {
+            reader: tokio::fs::File,
+            writer: tokio::fs::File,
+        }
+
+        impl AsyncRead for Pty {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut ReadBuf<'_>,
+            ) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.reader).poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncWrite for Pty {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.writer).poll_write(cx, buf)
+            }
+
+            fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.writer).poll_flush(cx)
+            }
+
+            fn poll_shutdown(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.writer).poll_shutdown(cx)
+            }
+        }
+
+        #[tokio::test]
+        async fn round_trips_over_a_pty() {
+            let (master, slave) = unsafe {
+                let (mut master, mut slave) = (0, 0);
+                let opened = libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                );
+                assert_eq!(opened, 0);
+
+                // Pass bytes through untouched, like a serial port would
+                let mut termios = std::mem::zeroed();
+                libc::tcgetattr(slave, &mut termios);
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(slave, libc::TCSANOW, &termios);
+
+                (
+                    std::fs::File::from_raw_fd(master),
+                    std::fs::File::from_raw_fd(slave),
+                )
+            };
+            let mut master = tokio::fs::File::from_std(master);
+            let slave = Pty {
+                reader: tokio::fs::File::from_std(slave.try_clone().unwrap()),
+                writer: tokio::fs::File::from_std(slave),
+            };
+
+            let mut transport = SerialTransport::new(slave);
+            transport.connect(Duration::from_secs(1)).await.unwrap();
+            let mut events = transport.events().await.unwrap();
+            assert_eq!(events.next().await, Some(TransportEvent::Connected));
+
+            let mut incoming = vec![0x00, SYNC];
+            incoming.extend(encode_frame(&[1, 2, 3]));
+            master.write_all(&incoming).await.unwrap();
+            master.flush().await.unwrap();
+
+            let event = timeout(Duration::from_secs(5), events.next())
+                .await
+                .unwrap();
+            assert_eq!(event, Some(TransportEvent::Packet(vec![1, 2, 3])));
+
+            transport.send(&[4, 5]).await.unwrap();
+            let mut frame = vec![0u8; 5];
+            timeout(Duration::from_secs(5), master.read_exact(&mut frame))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(frame, encode_frame(&[4, 5]));
+        }
+    }
+}
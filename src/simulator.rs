@@ -0,0 +1,647 @@
+use crate::command::{Command, Lens, Media, PtzControl, Video};
+use crate::error::TransportError;
+use crate::key::CommandKey;
+use crate::keys;
+use crate::rawcommand::{Operation, RawCommand};
+use crate::transport::{status, CameraTransport, EventStream, TransportEvent};
+use async_trait::async_trait;
+use futures::stream;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Destination id that addresses every camera
+pub const BROADCAST: u8 = 255;
+
+/// How far the zoom travels per second at full continuous zoom speed, normalised
+pub const ZOOM_RATE: f32 = 0.5;
+
+/// How far the head turns per second at full pan or tilt velocity, in degrees
+pub const PAN_TILT_RATE: f32 = 60.0;
+
+/// How far the head turns either way from where it started, in degrees (pan, tilt)
+pub const PAN_TILT_LIMITS: (f32, f32) = (170.0, 90.0);
+
+/// How long an instantaneous autofocus takes to settle
+pub const AUTOFOCUS_SETTLE: Duration = Duration::from_millis(500);
+
+/// How long an instantaneous auto aperture takes to settle
+pub const AUTO_APERTURE_SETTLE: Duration = Duration::from_millis(300);
+
+/// Transport modes carried in the first element of Media::TransportMode
+pub const TRANSPORT_PREVIEW: i8 = 0;
+pub const TRANSPORT_PLAY: i8 = 1;
+pub const TRANSPORT_RECORD: i8 = 2;
+
+/// A virtual camera that lives in-process
+///
+/// Holds a value for every parameter in the protocol, echoes accepted writes back the way
+/// a real camera does and ticks timecode, so code written against `Camera` can run
+/// without any hardware. Hand it to `Camera::with_transport` in place of a real link.
+///
+/// Time dependent behaviour is modelled once per frame: continuous zoom and pan/tilt
+/// velocity move the lens and head, autofocus and auto aperture settle after a delay and
+/// the transport only moves between preview, play and record the way a camera allows.
+/// Time comes from tokio, so tests can pause and advance it.
+#[derive(Debug)]
+pub struct SimulatedCamera {
+    inner: Arc<Mutex<Simulation>>,
+    tick_task: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Simulation {
+    id: u8,
+    connected: bool,
+    values: HashMap<CommandKey, Command>,
+    events: Option<UnboundedSender<TransportEvent>>,
+
+    frame_rate: u32,
+    frames: f64,
+    last_tick: Option<Instant>,
+
+    zoom: f32,
+    zoom_speed: f32,
+    zoom_range: (i16, i16),
+
+    pan_tilt: (f32, f32),
+    pan_tilt_velocity: (f32, f32),
+
+    autofocus_target: f32,
+    auto_aperture_target: f32,
+    pending: Vec<(Instant, Command)>,
+}
+
+impl Simulation {
+    fn notify(&self, event: TransportEvent) {
+        if let Some(tx) = &self.events {
+            let _ = tx.send(event);
+        }
+    }
+
+    // Stores a value and reports it to the client
+    fn update(&mut self, cmd: Command) {
+        self.notify(TransportEvent::Packet(RawCommand::to_raw(
+            self.id,
+            Operation::AssignValue,
+            &cmd,
+        )));
+        self.values.insert(cmd.key(), cmd);
+    }
+
+    fn update_zoom(&mut self) {
+        let (wide, tele) = self.zoom_range;
+        let mm = wide as f32 + self.zoom * (tele - wide) as f32;
+
+        self.update(Command::Lens(Lens::SetAbsoluteZoomNormalised(self.zoom)));
+        self.update(Command::Lens(Lens::SetAbsoluteZoomMm(mm.round() as i16)));
+    }
+
+    // The protocol has no pan/tilt position, so a head that runs into a limit reports
+    // it the way a real one does, by its velocity dropping to zero
+    fn move_head(&mut self, dt: f32) {
+        let (pan, tilt) = self.pan_tilt_velocity;
+        let (pan_limit, tilt_limit) = PAN_TILT_LIMITS;
+
+        self.pan_tilt.0 = (self.pan_tilt.0 + pan * PAN_TILT_RATE * dt).clamp(-pan_limit, pan_limit);
+        self.pan_tilt.1 =
+            (self.pan_tilt.1 + tilt * PAN_TILT_RATE * dt).clamp(-tilt_limit, tilt_limit);
+
+        let stopped = |position: f32, velocity: f32, limit: f32| {
+            if position.abs() >= limit && position.signum() == velocity.signum() {
+                0.0
+            } else {
+                velocity
+            }
+        };
+        let velocity = (
+            stopped(self.pan_tilt.0, pan, pan_limit),
+            stopped(self.pan_tilt.1, tilt, tilt_limit),
+        );
+
+        if velocity != self.pan_tilt_velocity {
+            self.pan_tilt_velocity = velocity;
+            self.update(Command::PtzControl(PtzControl::PanTiltVelocity(vec![
+                velocity.0, velocity.1,
+            ])));
+        }
+    }
+
+    fn transport_mode(&self) -> i8 {
+        match self.values.get(&keys::media::TRANSPORT_MODE.key()) {
+            Some(Command::Media(Media::TransportMode(v))) => {
+                v.first().copied().unwrap_or(TRANSPORT_PREVIEW)
+            }
+            _ => TRANSPORT_PREVIEW,
+        }
+    }
+
+    // Applies a value the client asked for, with whatever the camera would do about it
+    fn receive(&mut self, cmd: Command) {
+        match cmd {
+            Command::Lens(Lens::InstantaneousAutofocus) => {
+                let target = Command::Lens(Lens::Focus(self.autofocus_target));
+                self.pending
+                    .push((Instant::now() + AUTOFOCUS_SETTLE, target));
+            }
+            Command::Lens(Lens::InstantaneousAutoAperture) => {
+                let target = Command::Lens(Lens::ApertureNormalised(self.auto_aperture_target));
+                self.pending
+                    .push((Instant::now() + AUTO_APERTURE_SETTLE, target));
+            }
+            Command::Lens(Lens::SetContinuousZoomSpeed(v)) => {
+                self.zoom_speed = v.clamp(-1.0, 1.0);
+                self.update(Command::Lens(Lens::SetContinuousZoomSpeed(self.zoom_speed)));
+            }
+            Command::Lens(Lens::SetAbsoluteZoomNormalised(v)) => {
+                self.zoom = v.clamp(0.0, 1.0);
+                self.update_zoom();
+            }
+            Command::Lens(Lens::SetAbsoluteZoomMm(v)) => {
+                let (wide, tele) = self.zoom_range;
+                let v = v.clamp(wide, tele);
+                self.zoom = if tele > wide {
+                    (v - wide) as f32 / (tele - wide) as f32
+                } else {
+                    0.0
+                };
+                self.update_zoom();
+            }
+            Command::PtzControl(PtzControl::PanTiltVelocity(v)) => {
+                let pan = v.first().copied().unwrap_or(0.0).clamp(-1.0, 1.0);
+                let tilt = v.get(1).copied().unwrap_or(0.0).clamp(-1.0, 1.0);
+                self.pan_tilt_velocity = (pan, tilt);
+                self.update(Command::PtzControl(PtzControl::PanTiltVelocity(vec![
+                    pan, tilt,
+                ])));
+            }
+            Command::Media(Media::TransportMode(v)) => {
+                let requested = match v.first() {
+                    Some(m) => *m,
+                    None => return,
+                };
+
+                let current = self.transport_mode();
+                let allowed = matches!(
+                    (current, requested),
+                    (_, TRANSPORT_PREVIEW) | (TRANSPORT_PREVIEW, _)
+                ) || current == requested;
+
+                if allowed && (TRANSPORT_PREVIEW..=TRANSPORT_RECORD).contains(&requested) {
+                    self.update(Command::Media(Media::TransportMode(v)));
+                } else if let Some(current) =
+                    self.values.get(&keys::media::TRANSPORT_MODE.key()).cloned()
+                {
+                    // The camera reports the mode it stayed in
+                    self.update(current);
+                }
+            }
+            Command::Video(Video::VideoMode(v)) => {
+                if let Some(rate) = v.first() {
+                    if *rate > 0 {
+                        self.frame_rate = *rate as u32;
+                    }
+                }
+                self.update(Command::Video(Video::VideoMode(v)));
+            }
+            cmd => {
+                // Other triggers have nothing to report
+                if self.values.contains_key(&cmd.key()) {
+                    self.update(cmd);
+                }
+            }
+        }
+    }
+
+    // Advances everything that moves on its own
+    fn tick(&mut self, now: Instant) {
+        let dt = match self.last_tick {
+            Some(last) => now.duration_since(last).as_secs_f32(),
+            None => 0.0,
+        };
+        self.last_tick = Some(now);
+
+        let before = self.frames as u64;
+        self.frames += dt as f64 * self.frame_rate as f64;
+        if self.frames as u64 != before {
+            self.notify(TransportEvent::Timecode(timecode_bcd(
+                self.frames as u64,
+                self.frame_rate,
+            )));
+        }
+
+        if self.zoom_speed != 0.0 {
+            let zoom = (self.zoom + self.zoom_speed * ZOOM_RATE * dt).clamp(0.0, 1.0);
+            if zoom != self.zoom {
+                self.zoom = zoom;
+                self.update_zoom();
+            }
+        }
+
+        if self.pan_tilt_velocity != (0.0, 0.0) {
+            self.move_head(dt);
+        }
+
+        let (due, pending): (Vec<_>, Vec<_>) =
+            self.pending.drain(..).partition(|(at, _)| *at <= now);
+        self.pending = pending;
+        for (_, cmd) in due {
+            self.update(cmd);
+        }
+    }
+}
+
+impl SimulatedCamera {
+    /// Returns a simulated camera with id 1, running at 24 frames per second
+    pub fn new() -> SimulatedCamera {
+        let mut values: HashMap<CommandKey, Command> = Command::defaults()
+            .into_iter()
+            .map(|c| (c.key(), c))
+            .collect();
+
+        let video_mode = Command::Video(Video::VideoMode(vec![24, 0, 0, 0, 0]));
+        values.insert(video_mode.key(), video_mode);
+
+        SimulatedCamera {
+            inner: Arc::new(Mutex::new(Simulation {
+                id: 1,
+                connected: false,
+                values,
+                events: None,
+
+                frame_rate: 24,
+                frames: 0.0,
+                last_tick: None,
+
+                zoom: 0.0,
+                zoom_speed: 0.0,
+                zoom_range: (12, 120),
+
+                pan_tilt: (0.0, 0.0),
+                pan_tilt_velocity: (0.0, 0.0),
+
+                autofocus_target: 0.5,
+                auto_aperture_target: 0.5,
+                pending: Vec::new(),
+            })),
+            tick_task: None,
+        }
+    }
+
+    /// Sets the camera id the simulator answers to, besides broadcast
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - u8 camera id
+    pub fn with_id(self, id: u8) -> SimulatedCamera {
+        self.inner.lock().unwrap().id = id;
+        self
+    }
+
+    /// Sets how fast timecode advances, clients can change it through Video::VideoMode
+    ///
+    /// # Arguments
+    ///
+    /// * `frame_rate` - u32 frames per second
+    pub fn with_frame_rate(self, frame_rate: u32) -> SimulatedCamera {
+        let frame_rate = frame_rate.clamp(1, i8::MAX as u32);
+        {
+            let mut sim = self.inner.lock().unwrap();
+            sim.frame_rate = frame_rate;
+            let video_mode = Command::Video(Video::VideoMode(vec![frame_rate as i8, 0, 0, 0, 0]));
+            sim.values.insert(video_mode.key(), video_mode);
+        }
+        self
+    }
+
+    /// Sets the focal length range of the simulated zoom lens
+    ///
+    /// # Arguments
+    ///
+    /// * `wide` - i16 focal length in mm at the wide end
+    /// * `tele` - i16 focal length in mm at the tele end
+    pub fn with_zoom_range(self, wide: i16, tele: i16) -> SimulatedCamera {
+        self.inner.lock().unwrap().zoom_range = (wide, tele.max(wide));
+        self
+    }
+
+    /// Sets where instantaneous autofocus ends up, normalised like Lens::Focus
+    pub fn with_autofocus_target(self, focus: f32) -> SimulatedCamera {
+        self.inner.lock().unwrap().autofocus_target = focus;
+        self
+    }
+
+    /// Sets where instantaneous auto aperture ends up, normalised like Lens::ApertureNormalised
+    pub fn with_auto_aperture_target(self, aperture: f32) -> SimulatedCamera {
+        self.inner.lock().unwrap().auto_aperture_target = aperture;
+        self
+    }
+
+    pub fn id(&self) -> u8 {
+        self.inner.lock().unwrap().id
+    }
+
+    /// Gives you the value the simulated camera currently holds for the supplied key
+    pub fn get(&self, key: impl Into<CommandKey>) -> Option<Command> {
+        self.inner.lock().unwrap().values.get(&key.into()).cloned()
+    }
+
+    /// Changes a value on the camera side, as if someone turned a dial on the body
+    ///
+    /// The change is reported to the connected client like any other update.
+    pub fn set(&self, cmd: Command) {
+        self.inner.lock().unwrap().update(cmd);
+    }
+
+    /// Returns the current timecode as a 32-bit BCD number
+    pub fn timecode(&self) -> u32 {
+        let sim = self.inner.lock().unwrap();
+        timecode_bcd(sim.frames as u64, sim.frame_rate)
+    }
+
+    /// Returns the zoom position, normalised from wide (0.0) to tele (1.0)
+    pub fn zoom(&self) -> f32 {
+        self.inner.lock().unwrap().zoom
+    }
+
+    /// Returns how far the head has turned from where it started, in degrees (pan, tilt)
+    ///
+    /// Clients only learn about it when the head stops at one of PAN_TILT_LIMITS, as the
+    /// protocol has no parameter for the position.
+    pub fn pan_tilt(&self) -> (f32, f32) {
+        self.inner.lock().unwrap().pan_tilt
+    }
+
+    fn start_ticking(&mut self) {
+        let inner = self.inner.clone();
+
+        self.tick_task = Some(tokio::spawn(async move {
+            loop {
+                let frame_rate = {
+                    let mut sim = inner.lock().unwrap();
+                    sim.tick(Instant::now());
+                    sim.frame_rate
+                };
+
+                tokio::time::sleep(Duration::from_secs_f64(1.0 / frame_rate as f64)).await;
+            }
+        }));
+    }
+
+    fn stop_ticking(&mut self) {
+        if let Some(task) = self.tick_task.take() {
+            task.abort();
+        }
+        self.inner.lock().unwrap().last_tick = None;
+    }
+}
+
+impl Default for SimulatedCamera {
+    fn default() -> Self {
+        SimulatedCamera::new()
+    }
+}
+
+impl Drop for SimulatedCamera {
+    fn drop(&mut self) {
+        if let Some(task) = self.tick_task.take() {
+            task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl CameraTransport for SimulatedCamera {
+    type Error = TransportError;
+
+    async fn connect(&mut self, _timeout: Duration) -> Result<(), TransportError> {
+        self.inner.lock().unwrap().connected = true;
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), TransportError> {
+        self.stop_ticking();
+
+        let mut sim = self.inner.lock().unwrap();
+        sim.connected = false;
+        sim.notify(TransportEvent::Disconnected);
+        sim.events = None;
+
+        Ok(())
+    }
+
+    /// Applies a packet the way a camera would
+    ///
+    /// Packets for other cameras and anything the camera would not understand are
+    /// silently dropped, accepted values are echoed back.
+    async fn send(&mut self, packet: &[u8]) -> Result<(), TransportError> {
+        let mut sim = self.inner.lock().unwrap();
+        if !sim.connected {
+            return Err(TransportError::NotConnected);
+        }
+
+        let raw = match RawCommand::from_raw(packet) {
+            Ok(v) => v,
+            Err(_) => return Ok(()),
+        };
+
+        if raw.destination_device != sim.id && raw.destination_device != BROADCAST {
+            return Ok(());
+        }
+
+        let cmd = match Command::from_raw(packet) {
+            Ok(v) => v,
+            Err(_) => return Ok(()),
+        };
+
+        let cmd = match Operation::from_u8(raw.operation) {
+            Operation::AssignValue => Some(cmd),
+            Operation::OffsetValue => sim
+                .values
+                .get(&cmd.key())
+                .and_then(|current| offset(current, &raw)),
+            Operation::Unknown => None,
+        };
+
+        if let Some(cmd) = cmd {
+            sim.receive(cmd);
+        }
+
+        Ok(())
+    }
+
+    /// Starts reporting, beginning with the camera's full state like a real camera does
+    async fn events(&mut self) -> Result<EventStream, TransportError> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        {
+            let mut sim = self.inner.lock().unwrap();
+            if !sim.connected {
+                return Err(TransportError::NotConnected);
+            }
+
+            sim.events = Some(tx);
+            sim.notify(TransportEvent::Connected);
+            sim.notify(TransportEvent::Status(
+                status::CAMERA_POWER_ON
+                    | status::CONNECTED
+                    | status::PAIRED
+                    | status::VERSIONS_VERIFIED
+                    | status::INITIAL_PAYLOAD_RECEIVED
+                    | status::CAMERA_READY,
+            ));
+
+            for cmd in sim.values.values() {
+                sim.notify(TransportEvent::Packet(RawCommand::to_raw(
+                    sim.id,
+                    Operation::AssignValue,
+                    cmd,
+                )));
+            }
+        }
+
+        self.stop_ticking();
+        self.start_ticking();
+
+        Ok(Box::pin(stream::poll_fn(move |cx| rx.poll_recv(cx))))
+    }
+}
+
+// Adds the packet's values to the current ones, element by element
+fn offset(current: &Command, raw: &RawCommand) -> Option<Command> {
+    let width = match raw.data_type {
+        1 => 1,
+        2 | 128 => 2,
+        3 => 4,
+        4 => 8,
+        _ => return None,
+    };
+
+    let mut data = current.to_bytes();
+    for (v, d) in data
+        .chunks_exact_mut(width)
+        .zip(raw.data.chunks_exact(width))
+    {
+        match width {
+            1 => v[0] = (v[0] as i8).saturating_add(d[0] as i8) as u8,
+            2 => {
+                let sum = i16::from_le_bytes(v.try_into().ok()?)
+                    .saturating_add(i16::from_le_bytes(d.try_into().ok()?));
+                v.copy_from_slice(&sum.to_le_bytes());
+            }
+            4 => {
+                let sum = i32::from_le_bytes(v.try_into().ok()?)
+                    .saturating_add(i32::from_le_bytes(d.try_into().ok()?));
+                v.copy_from_slice(&sum.to_le_bytes());
+            }
+            _ => {
+                let sum = i64::from_le_bytes(v.try_into().ok()?)
+                    .saturating_add(i64::from_le_bytes(d.try_into().ok()?));
+                v.copy_from_slice(&sum.to_le_bytes());
+            }
+        }
+    }
+
+    let mut packet = RawCommand::to_raw(raw.destination_device, Operation::AssignValue, current);
+    packet.truncate(8);
+    packet.extend(data);
+
+    Command::from_raw(&packet).ok()
+}
+
+/// Encodes a frame count as timecode (HH:MM:SS:FF) in a 32-bit BCD number
+pub fn timecode_bcd(frames: u64, frame_rate: u32) -> u32 {
+    let fps = frame_rate.max(1) as u64;
+    let seconds = frames / fps;
+
+    (bcd(seconds / 3600 % 24) << 24)
+        | (bcd(seconds / 60 % 60) << 16)
+        | (bcd(seconds % 60) << 8)
+        | bcd(frames % fps)
+}
+
+fn bcd(v: u64) -> u32 {
+    (((v / 10) << 4) | (v % 10)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    fn simulation() -> (SimulatedCamera, UnboundedReceiver<TransportEvent>) {
+        let camera = SimulatedCamera::new();
+        let (tx, rx) = mpsc::unbounded_channel();
+        camera.inner.lock().unwrap().events = Some(tx);
+        (camera, rx)
+    }
+
+    fn commands(rx: &mut UnboundedReceiver<TransportEvent>) -> Vec<Command> {
+        let mut commands = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            if let TransportEvent::Packet(p) = event {
+                commands.push(Command::from_raw(&p).unwrap());
+            }
+        }
+        commands
+    }
+
+    #[test]
+    fn rejects_unknown_transport_modes() {
+        let (camera, _rx) = simulation();
+        let mut sim = camera.inner.lock().unwrap();
+
+        for mode in [-1, TRANSPORT_RECORD + 1] {
+            sim.receive(Command::Media(Media::TransportMode(vec![mode, 0, 0, 0, 0])));
+            assert_eq!(sim.transport_mode(), TRANSPORT_PREVIEW);
+        }
+
+        sim.receive(Command::Media(Media::TransportMode(vec![
+            TRANSPORT_RECORD,
+            0,
+            0,
+            0,
+            0,
+        ])));
+        assert_eq!(sim.transport_mode(), TRANSPORT_RECORD);
+    }
+
+    #[test]
+    fn head_reports_stopping_at_its_limit() {
+        let (camera, mut rx) = simulation();
+        let mut sim = camera.inner.lock().unwrap();
+        let start = Instant::now();
+
+        sim.receive(Command::PtzControl(PtzControl::PanTiltVelocity(vec![
+            1.0, 0.5,
+        ])));
+        sim.tick(start);
+        commands(&mut rx);
+
+        // Pan hits 170 degrees and tilt 90 before 4 seconds, so the head reports stopping
+        sim.tick(start + Duration::from_secs(4));
+        assert_eq!(sim.pan_tilt, (170.0, 90.0));
+
+        let reported: Vec<Command> = commands(&mut rx)
+            .into_iter()
+            .filter(|c| c.key() == keys::ptz_control::PAN_TILT_VELOCITY.key())
+            .collect();
+        assert_eq!(
+            reported,
+            vec![Command::PtzControl(PtzControl::PanTiltVelocity(vec![
+                0.0, 0.0
+            ]))]
+        );
+
+        // Turning back is allowed
+        sim.receive(Command::PtzControl(PtzControl::PanTiltVelocity(vec![
+            -1.0, 0.0,
+        ])));
+        sim.tick(start + Duration::from_secs(5));
+        assert_eq!(sim.pan_tilt.0, 110.0);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::command::Command;
+use crate::key::{CommandKey, Key};
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+pub type CommandStream = Pin<Box<dyn Stream<Item = Command> + Send>>;
+pub type ValueStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+
+/// Per-parameter watch channels
+///
+/// Every subscribed key gets its own watch channel holding only the latest value, so a
+/// slow consumer skips intermediate values instead of falling behind or lagging out.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    channels: Mutex<HashMap<CommandKey, watch::Sender<Option<Command>>>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions::default()
+    }
+
+    /// Hands the command to whoever watches its key
+    pub fn publish(&self, cmd: &Command) {
+        let channels = self.channels.lock().unwrap();
+        if let Some(tx) = channels.get(&cmd.key()) {
+            tx.send_replace(Some(cmd.clone()));
+        }
+    }
+
+    /// Returns a receiver for the key, seeded with `current` if the channel is new
+    ///
+    /// Callers should hold the cache lock while calling this so no update can slip in
+    /// between reading `current` and the channel being registered.
+    pub fn watch(
+        &self,
+        key: CommandKey,
+        current: Option<Command>,
+    ) -> watch::Receiver<Option<Command>> {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| watch::channel(current).0)
+            .subscribe()
+    }
+
+    /// Stream of every value of a single parameter, typed
+    pub fn stream<T: Send + 'static>(
+        &self,
+        key: Key<T>,
+        current: Option<Command>,
+    ) -> ValueStream<T> {
+        let rx = self.watch(key.key(), current);
+        Box::pin(watch_stream(rx).filter_map(move |c| futures::future::ready(key.extract(&c))))
+    }
+
+    /// Stream of every value of a set of parameters, such as a whole category
+    pub fn stream_keys<F>(&self, keys: &[CommandKey], mut current: F) -> CommandStream
+    where
+        F: FnMut(CommandKey) -> Option<Command>,
+    {
+        let streams: Vec<_> = keys
+            .iter()
+            .map(|k| watch_stream(self.watch(*k, current(*k))))
+            .collect();
+
+        Box::pin(stream::select_all(streams))
+    }
+}
+
+// Yields the current value (if any) and then every change until the sender goes away
+fn watch_stream(rx: watch::Receiver<Option<Command>>) -> CommandStream {
+    Box::pin(stream::unfold(
+        (rx, false),
+        |(mut rx, mut seen)| async move {
+            loop {
+                if seen {
+                    rx.changed().await.ok()?;
+                }
+                seen = true;
+
+                let current = rx.borrow_and_update().clone();
+                if let Some(cmd) = current {
+                    return Some((cmd, (rx, true)));
+                }
+            }
+        },
+    ))
+}
@@ -0,0 +1,70 @@
+use crate::error::CameraControlError;
+use crate::rawcommand::CommandError;
+use async_trait::async_trait;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Something that happened on the link to the camera
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportEvent {
+    /// A camera control packet sent by the camera
+    Packet(Vec<u8>),
+
+    /// Timecode as a 32-bit BCD number (09:12:53:10 = 0x09125310)
+    Timecode(u32),
+
+    /// Camera status flags
+    Status(u8),
+
+    Connected,
+    Disconnected,
+}
+
+/// Which way a packet travels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// From us to the camera
+    Outgoing,
+    /// From the camera to us
+    Incoming,
+}
+
+/// Flags carried by TransportEvent::Status
+pub mod status {
+    pub const CAMERA_POWER_ON: u8 = 0x01;
+    pub const CONNECTED: u8 = 0x02;
+    pub const PAIRED: u8 = 0x04;
+    pub const VERSIONS_VERIFIED: u8 = 0x08;
+    pub const INITIAL_PAYLOAD_RECEIVED: u8 = 0x10;
+    pub const CAMERA_READY: u8 = 0x20;
+}
+
+pub type EventStream = Pin<Box<dyn Stream<Item = TransportEvent> + Send>>;
+
+/// A link that can carry camera control packets to and from a camera
+///
+/// Implement this to drive a camera over something other than Bluetooth, the command
+/// encoding, cache and update handling is shared through `Camera`.
+#[async_trait]
+pub trait CameraTransport: Send + Sync + 'static {
+    type Error: std::error::Error
+        + From<CommandError>
+        + From<CameraControlError>
+        + Send
+        + Sync
+        + 'static;
+
+    /// Establishes the link, waiting as long as supplied timeout specifies
+    async fn connect(&mut self, timeout: Duration) -> Result<(), Self::Error>;
+
+    async fn disconnect(&mut self) -> Result<(), Self::Error>;
+
+    /// Sends a single camera control packet, as built by RawCommand::to_raw
+    async fn send(&mut self, packet: &[u8]) -> Result<(), Self::Error>;
+
+    /// Returns the stream of everything the camera sends from now on
+    async fn events(&mut self) -> Result<EventStream, Self::Error>;
+}
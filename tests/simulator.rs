@@ -0,0 +1,163 @@
+use blackmagic_camera_control::command::{Command, Lens, Video};
+use blackmagic_camera_control::{keys, Camera, Operation, SimulatedCamera};
+use futures::stream::StreamExt;
+use std::time::Duration;
+use tokio::time::timeout;
+
+async fn connected(simulator: SimulatedCamera) -> Camera<SimulatedCamera> {
+    let mut camera = Camera::with_transport(simulator);
+    camera.connect(Duration::from_secs(1)).await.unwrap();
+    camera
+        .wait_synced(Duration::from_millis(50), Duration::from_secs(1))
+        .await
+        .unwrap();
+    camera
+}
+
+// Changes the ISO on the camera and waits for the cache to have it
+async fn change_iso(camera: &Camera<SimulatedCamera>, iso: i32) {
+    camera.transport().set(Command::Video(Video::Iso(iso)));
+    timeout(Duration::from_secs(1), async {
+        while camera.get(keys::video::ISO).await != Some(iso) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn syncs_full_state_on_connect() {
+    let camera = connected(SimulatedCamera::new()).await;
+
+    assert!(camera.is_connected());
+    assert!(camera.get(keys::video::ISO).await.is_some());
+    assert!(camera.get(keys::lens::FOCUS).await.is_some());
+}
+
+#[tokio::test]
+async fn echoes_accepted_writes() {
+    let mut camera = connected(SimulatedCamera::new()).await;
+    let mut updates = camera.updates().await;
+
+    camera
+        .write(255, Operation::AssignValue, Command::Video(Video::Iso(640)))
+        .await
+        .unwrap();
+
+    let update = timeout(Duration::from_secs(1), updates.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(update, Command::Video(Video::Iso(640)));
+    assert_eq!(camera.get(keys::video::ISO).await, Some(640));
+}
+
+#[tokio::test]
+async fn applies_offsets_to_the_current_value() {
+    let mut camera = connected(SimulatedCamera::new()).await;
+    let mut updates = camera.updates().await;
+
+    camera
+        .write(
+            255,
+            Operation::AssignValue,
+            Command::Lens(Lens::Focus(0.25)),
+        )
+        .await
+        .unwrap();
+    camera
+        .write(255, Operation::OffsetValue, Command::Lens(Lens::Focus(0.5)))
+        .await
+        .unwrap();
+
+    let mut last = None;
+    while let Ok(Ok(update)) = timeout(Duration::from_millis(200), updates.recv()).await {
+        last = Some(update);
+    }
+    assert_eq!(last, Some(Command::Lens(Lens::Focus(0.75))));
+}
+
+#[tokio::test]
+async fn ignores_writes_for_other_cameras() {
+    let mut camera = connected(SimulatedCamera::new().with_id(3)).await;
+    let before = camera.get(keys::video::ISO).await;
+
+    camera
+        .write(4, Operation::AssignValue, Command::Video(Video::Iso(12800)))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(camera.get(keys::video::ISO).await, before);
+}
+
+#[tokio::test]
+async fn reports_changes_made_on_the_camera() {
+    let camera = connected(SimulatedCamera::new()).await;
+
+    camera.transport().set(Command::Video(Video::Iso(3200)));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(camera.get(keys::video::ISO).await, Some(3200));
+}
+
+#[tokio::test]
+async fn subscriptions_start_with_the_cached_value() {
+    let camera = connected(SimulatedCamera::new()).await;
+    change_iso(&camera, 3200).await;
+
+    let mut iso = camera.subscribe(keys::video::ISO).await;
+    assert_eq!(iso.next().await, Some(3200));
+}
+
+#[tokio::test]
+async fn slow_subscribers_get_the_latest_value() {
+    let camera = connected(SimulatedCamera::new()).await;
+    change_iso(&camera, 200).await;
+    let mut iso = camera.subscribe(keys::video::ISO).await;
+    assert_eq!(iso.next().await, Some(200));
+
+    change_iso(&camera, 400).await;
+    change_iso(&camera, 800).await;
+    change_iso(&camera, 1600).await;
+
+    assert_eq!(iso.next().await, Some(1600));
+    assert!(timeout(Duration::from_millis(100), iso.next())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn group_subscriptions_only_carry_their_category() {
+    let camera = connected(SimulatedCamera::new()).await;
+    let mut lens = camera.subscribe_group(keys::lens::CATEGORY).await;
+
+    camera.transport().set(Command::Video(Video::Iso(6400)));
+    camera.transport().set(Command::Lens(Lens::Focus(0.125)));
+
+    let focus = timeout(Duration::from_secs(1), async {
+        loop {
+            let command = lens.next().await.unwrap();
+            assert_eq!(command.key().category, keys::lens::CATEGORY);
+            if command == Command::Lens(Lens::Focus(0.125)) {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(focus.is_ok());
+}
+
+#[tokio::test]
+async fn later_subscribers_get_the_current_value() {
+    let camera = connected(SimulatedCamera::new()).await;
+    let mut first = camera.subscribe(keys::video::ISO).await;
+    first.next().await.unwrap();
+
+    change_iso(&camera, 640).await;
+
+    let mut second = camera.subscribe(keys::video::ISO).await;
+    assert_eq!(second.next().await, Some(640));
+    assert_eq!(first.next().await, Some(640));
+}